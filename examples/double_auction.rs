@@ -0,0 +1,38 @@
+extern crate flow_rs;
+use flow_rs::simulation::adaptive_behavior::{GdAgent, ZipAgent};
+use flow_rs::simulation::efficiency::{EfficiencyReport, Valuation};
+use flow_rs::simulation::harness::Harness;
+use flow_rs::simulation::seed::ExperimentSeed;
+use flow_rs::simulation::strategy::{Strategy, StrategyRunner};
+use flow_rs::simulation::zic_behavior::SupplyDemand;
+
+pub fn main() {
+    // Gode and Sunder's baseline: 50 buyers and 50 sellers with values and costs drawn
+    // from 50 to 150, shouting within 1 to 200 in a continuous double auction. The
+    // agents are zic (default), zip or gd.
+    let mut args = std::env::args().skip(1);
+    let kind = args.next().unwrap_or_else(|| String::from("zic"));
+    let seed = ExperimentSeed::new(args.next().map_or(0, |a| a.parse().expect("seed must be a u64")));
+    let market = SupplyDemand::uniform(50, (50.0, 150.0), (50.0, 150.0), &mut seed.stream("schedules"));
+    let eq = market.equilibrium().expect("the schedules don't cross");
+    let valuations = Valuation::from_schedules(&market);
+    let mut agents: Vec<Box<dyn Strategy>> = match kind.as_str() {
+        "zic" => market.zic_agents((1.0, 200.0), seed).into_iter().map(|a| Box::new(a) as Box<dyn Strategy>).collect(),
+        "zip" => market.agents(|id, side, limit| {
+            let rng = seed.stream(&id);
+            Box::new(ZipAgent::new(id, side, limit, rng)) as Box<dyn Strategy>
+        }),
+        "gd" => market.agents(|id, side, limit| Box::new(GdAgent::new(id, side, limit, (1.0, 200.0), 50)) as Box<dyn Strategy>),
+        _ => panic!("agents must be zic, zip or gd"),
+    };
+
+    let mut harness = Harness::new();
+    harness.set_logging(false);
+    let submitted = StrategyRunner::in_process(&mut harness, &mut agents, 1, 60_000, seed);
+
+    let trades = harness.trade_log.since(0);
+    let report = EfficiencyReport::compute(&valuations, &trades).expect("the schedules don't cross");
+    println!("equilibrium: {} units at {:.2} to {:.2}, surplus {:.2}", eq.quantity, eq.price_low, eq.price_high, eq.surplus);
+    println!("{}: {} orders, {} trades, surplus {:.2} ({:.1}% efficient)", kind, submitted, report.trades, report.surplus, 100.0 * report.efficiency);
+    println!("{}", report.to_json());
+}
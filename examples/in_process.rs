@@ -0,0 +1,26 @@
+extern crate flow_rs;
+use flow_rs::simulation::trader::Traders;
+use flow_rs::simulation::harness::Harness;
+use flow_rs::simulation::random_behavior::{RandBehavior, RandomFlow};
+use flow_rs::simulation::seed::ExperimentSeed;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+pub fn main() {
+    // Initialize the new Trader struct and an in-process exchange, no servers need to be started
+    let traders = Arc::new(Traders::new());
+    let mut harness = Harness::new();
+    harness.set_logging(false);
+
+    // Arrivals every 1ms, updates every 2ms and cancels every 4ms of virtual time. The
+    // default of 285 virtual seconds sends roughly 10 million orders. The same seed
+    // reproduces the same run.
+    let mut args = std::env::args().skip(1);
+    let until = args.next().map_or(285_000, |a| a.parse().expect("until must be in milliseconds"));
+    let seed = ExperimentSeed::new(args.next().map_or(0, |a| a.parse().expect("seed must be a u64")));
+    let start = Instant::now();
+    let flow = RandomFlow { intervals: (1, 2, 4), ..RandomFlow::default() };
+    let submitted = RandBehavior::in_process(&mut harness, traders, &flow, until, seed);
+    println!("{} orders and {} trades in {:?}", submitted, harness.trade_log.len(), start.elapsed());
+}
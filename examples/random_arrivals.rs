@@ -1,23 +1,39 @@
 extern crate flow_rs;
 use flow_rs::simulation::trader::Traders;
 use flow_rs::controller::Controller;
+use flow_rs::simulation::experiment::{AgentConfig, ExperimentConfig};
 use flow_rs::simulation::random_behavior::RandBehavior;
+use flow_rs::simulation::seed::ExperimentSeed;
 
+use std::fs;
 use std::sync::Arc;
 
 pub fn main() {
+    // The random flow and seed come from an experiment config, by default
+    // examples/configs/random_arrivals.json. Its grid is for sweeps and is ignored here.
+    let path = std::env::args().nth(1).unwrap_or_else(|| String::from("examples/configs/random_arrivals.json"));
+    let config = ExperimentConfig::from_json(&fs::read_to_string(&path).expect("couldn't read the config"))
+        .expect("invalid experiment config");
+    let flow = match config.base.agents {
+        AgentConfig::Random { flow } => flow,
+        _ => panic!("the config's agents must be random"),
+    };
+
     // Initialize the new Trader struct
     let traders = Arc::new(Traders::new());
 
+    // Every random order is drawn from the config's first seed
+    let seed = ExperimentSeed::new(*config.seeds.first().expect("the config has no seeds"));
+
     // Initialize the dispatcher controller
     let mut controller = Controller::new();
 
     // Establish the async tasks to repeatedly send orders over tcp
-    let tcp_address = format!("127.0.0.1:5000");
+    let tcp_address = String::from("127.0.0.1:5000");
 
-    let tcp_arrivals = RandBehavior::tcp_arrival_interval(Arc::clone(&traders), 500, tcp_address.clone()); 
-    let tcp_updates = RandBehavior::tcp_update_interval(Arc::clone(&traders), 1000, tcp_address.clone());
-    let tcp_cancels = RandBehavior::tcp_cancel_interval(Arc::clone(&traders), 2000, tcp_address.clone());
+    let tcp_arrivals = RandBehavior::tcp_arrival_interval(Arc::clone(&traders), &flow, tcp_address.clone(), seed); 
+    let tcp_updates = RandBehavior::tcp_update_interval(Arc::clone(&traders), &flow, tcp_address.clone(), seed);
+    let tcp_cancels = RandBehavior::tcp_cancel_interval(Arc::clone(&traders), &flow, tcp_address.clone(), seed);
 
     controller.push(tcp_arrivals);
     controller.push(tcp_updates);
@@ -28,9 +44,9 @@ pub fn main() {
     // env_logger::init();
     // let ws_address: &'static str = "ws://127.0.0.1:3015";
 
-    // let ws_arrivals = RandBehavior::ws_arrival_interval(Arc::clone(&traders), 500, &ws_address); 
-    // let ws_updates = RandBehavior::ws_update_interval(Arc::clone(&traders), 1000, &ws_address);
-    // let ws_cancels = RandBehavior::ws_cancel_interval(Arc::clone(&traders), 2000, &ws_address);
+    // let ws_arrivals = RandBehavior::ws_arrival_interval(Arc::clone(&traders), &flow, &ws_address, seed); 
+    // let ws_updates = RandBehavior::ws_update_interval(Arc::clone(&traders), &flow, &ws_address, seed);
+    // let ws_cancels = RandBehavior::ws_cancel_interval(Arc::clone(&traders), &flow, &ws_address, seed);
 
     // controller.push(ws_arrivals);
     // controller.push(ws_updates);
@@ -39,4 +55,3 @@ pub fn main() {
     // Start the controller which will asynchronously dispatch the git push
     controller.run();
 }
-
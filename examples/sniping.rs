@@ -0,0 +1,19 @@
+extern crate flow_rs;
+use flow_rs::simulation::latency_arbitrage::{SnipingConfig, SnipingExperiment};
+use flow_rs::simulation::seed::ExperimentSeed;
+
+use std::time::Duration;
+
+pub fn main() {
+    // Market makers requoting in 5ms against snipers arriving in 1ms, under the continuous
+    // market and batch auctions of increasing length
+    let seed = ExperimentSeed::new(std::env::args().nth(1).map_or(0, |a| a.parse().expect("seed must be a u64")));
+    let cda = SnipingConfig::default();
+    println!("design      sniper profit  maker pnl  sniped volume  mean spread");
+    for batch in [None, Some(10), Some(100), Some(1000)] {
+        let config = SnipingConfig { batch_interval: batch.map(Duration::from_millis), ..cda.clone() };
+        let report = SnipingExperiment::run(&config, seed);
+        let design = batch.map_or(String::from("cda"), |b| format!("fba {}ms", b));
+        println!("{:<11} {:>13.2} {:>10.2} {:>14.1} {:>12.3}", design, report.sniper_profit, report.maker_pnl, report.sniped_volume, report.mean_spread);
+    }
+}
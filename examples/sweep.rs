@@ -0,0 +1,18 @@
+extern crate flow_rs;
+use flow_rs::simulation::experiment::{ExperimentConfig, ExperimentRunner};
+
+use std::fs;
+use std::time::Instant;
+
+pub fn main() {
+    // Runs every point of an experiment config's grid with each of its seeds in parallel
+    // and prints one CSV row per run, e.g.
+    // cargo run --release --example sweep examples/configs/batch_intervals.json > results.csv
+    let path = std::env::args().nth(1).unwrap_or_else(|| String::from("examples/configs/batch_intervals.json"));
+    let config = ExperimentConfig::from_json(&fs::read_to_string(&path).expect("couldn't read the config"))
+        .expect("invalid experiment config");
+    let start = Instant::now();
+    let table = ExperimentRunner::run(&config).expect("couldn't expand the grid");
+    eprintln!("{}: {} runs in {:?}", config.name, table.rows.len(), start.elapsed());
+    print!("{}", table.to_csv());
+}
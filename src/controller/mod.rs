@@ -6,11 +6,15 @@ use futures::{Future};
 
 use futures::future;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum State {
 	Process,
 	PreAuction,
 	Auction,
+	Halted,
+	Closed,
+	Commit,
+	Reveal,
 }
 
 // A wrapper around tokio to dispatch tasks asynchronously
@@ -18,6 +22,12 @@ pub struct Controller {
 	tasks: Vec<AsyncTask>,
 }
 
+impl Default for Controller {
+	fn default() -> Self {
+		Controller::new()
+	}
+}
+
 impl Controller {
 	pub fn new() -> Controller {
 		Controller{
@@ -36,7 +46,7 @@ impl Controller {
 	}
 }
 
-pub type AsyncTask = Box<Future<Item = (), Error = ()> + Send>;
+pub type AsyncTask = Box<dyn Future<Item = (), Error = ()> + Send>;
 
 // A wrapper to easily create dispatch closure's asynchronously as tasks in tokio
 pub struct Task {
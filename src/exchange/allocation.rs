@@ -0,0 +1,195 @@
+use crate::order::Order;
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// Decides how the volume executing at the marginal price level of a batch auction
+/// is rationed among the orders resting at that level. Orders at better prices are
+/// always filled in full before the policy is consulted.
+/// Implement this trait to experiment with new allocation rules and select it for a
+/// market with Book::set_allocation on both Books.
+pub trait AllocationPolicy: Send + Sync {
+	/// Returns the fill for each order in level, which is sorted by time priority
+	/// (earliest arrival first). The fills must sum to volume, which never exceeds the
+	/// level's total quantity, and no fill may exceed its order's quantity.
+	fn allocate(&self, level: &[Order], volume: f64) -> Vec<f64>;
+}
+
+/// Fills the level in order of arrival, the earliest order first
+pub struct StrictTime {}
+
+impl AllocationPolicy for StrictTime {
+	fn allocate(&self, level: &[Order], volume: f64) -> Vec<f64> {
+		let mut remaining = volume;
+		level.iter().map(|o| {
+			let fill = o.quantity.min(remaining);
+			remaining -= fill;
+			fill
+		}).collect()
+	}
+}
+
+/// Fills every order at the level in proportion to its size
+pub struct ProRata {}
+
+impl AllocationPolicy for ProRata {
+	fn allocate(&self, level: &[Order], volume: f64) -> Vec<f64> {
+		let quantities: Vec<f64> = level.iter().map(|o| o.quantity).collect();
+		weighted_allocation(&quantities, &quantities, volume)
+	}
+}
+
+/// Pro rata with a minimum allocation. Each order in time priority is first topped up
+/// to min_qty (or its size if smaller) while volume lasts, then the rest is pro rata.
+/// min_qty: f64 -> quantity every order is guaranteed before the pro rata split
+pub struct ProRataMin {
+	pub min_qty: f64,
+}
+
+impl ProRataMin {
+	pub fn new(min_qty: f64) -> ProRataMin {
+		ProRataMin {
+			min_qty,
+		}
+	}
+}
+
+impl AllocationPolicy for ProRataMin {
+	fn allocate(&self, level: &[Order], volume: f64) -> Vec<f64> {
+		let mut remaining = volume;
+		let mut fills: Vec<f64> = level.iter().map(|o| {
+			let fill = o.quantity.min(self.min_qty).min(remaining);
+			remaining -= fill;
+			fill
+		}).collect();
+
+		let leftover: Vec<f64> = level.iter().zip(fills.iter()).map(|(o, f)| o.quantity - f).collect();
+		for (fill, extra) in fills.iter_mut().zip(weighted_allocation(&leftover, &leftover, remaining)) {
+			*fill += extra;
+		}
+		fills
+	}
+}
+
+/// Size-weighted time priority. Each order's pro rata weight is its size discounted
+/// by how many orders arrived before it at the level, so both large and early orders
+/// are favoured. A decay of 1 is pure pro rata and a decay near 0 approaches strict time.
+/// Volume the weights can't place, e.g. once a tiny decay underflows to zero weight, is
+/// filled in time priority so none of it is lost.
+/// decay: f64 -> factor in (0, 1] applied to the weight once per earlier order
+pub struct SizeTime {
+	decay: f64,
+}
+
+impl SizeTime {
+	/// Fails unless the decay is within (0, 1]
+	pub fn new(decay: f64) -> Result<SizeTime, &'static str> {
+		if decay.is_nan() || decay <= 0.0 || decay > 1.0 {
+			return Err("SizeTime decay must be in (0, 1]");
+		}
+		Ok(SizeTime {
+			decay,
+		})
+	}
+
+	pub fn decay(&self) -> f64 {
+		self.decay
+	}
+}
+
+impl AllocationPolicy for SizeTime {
+	fn allocate(&self, level: &[Order], volume: f64) -> Vec<f64> {
+		let quantities: Vec<f64> = level.iter().map(|o| o.quantity).collect();
+		let weights: Vec<f64> = quantities.iter().enumerate()
+										  .map(|(i, q)| q * self.decay.powi(i as i32))
+										  .collect();
+		let mut fills = weighted_allocation(&quantities, &weights, volume);
+		let mut remaining = volume - fills.iter().sum::<f64>();
+		for (fill, quantity) in fills.iter_mut().zip(quantities.iter()) {
+			let extra = (quantity - *fill).min(remaining).max(0.0);
+			*fill += extra;
+			remaining -= extra;
+		}
+		fills
+	}
+}
+
+/// Splits volume in proportion to the weights without giving any order more than its
+/// quantity. Volume left over by capped orders is split again among the uncapped ones.
+pub fn weighted_allocation(quantities: &[f64], weights: &[f64], volume: f64) -> Vec<f64> {
+	let mut fills = vec![0.0; quantities.len()];
+	let mut remaining = volume;
+	while remaining > EPSILON {
+		let open: Vec<usize> = (0..quantities.len())
+							   .filter(|&i| quantities[i] - fills[i] > EPSILON && weights[i] > 0.0)
+							   .collect();
+		let total_weight: f64 = open.iter().map(|&i| weights[i]).sum();
+		if open.is_empty() || total_weight <= 0.0 {
+			break;
+		}
+
+		let mut allocated = 0.0;
+		for &i in open.iter() {
+			let fill = (remaining * weights[i] / total_weight).min(quantities[i] - fills[i]);
+			fills[i] += fill;
+			allocated += fill;
+		}
+		remaining -= allocated;
+	}
+	fills
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{OrderType, TradeType};
+
+	fn level(quantities: &[f64]) -> Vec<Order> {
+		quantities.iter().enumerate()
+				  .map(|(i, q)| Order::new(format!("b{}", i), OrderType::Enter, TradeType::Bid, 100.0, *q))
+				  .collect()
+	}
+
+	fn assert_fills(fills: Vec<f64>, expected: &[f64]) {
+		assert_eq!(fills.len(), expected.len());
+		for (fill, e) in fills.iter().zip(expected) {
+			assert!((fill - e).abs() < 1e-6, "{:?} != {:?}", fills, expected);
+		}
+	}
+
+	#[test]
+	fn test_policies() {
+		let orders = level(&[2.0, 10.0, 8.0]);
+		assert_fills(StrictTime{}.allocate(&orders, 10.0), &[2.0, 8.0, 0.0]);
+		assert_fills(ProRata{}.allocate(&orders, 10.0), &[1.0, 5.0, 4.0]);
+		// Everyone gets 2 first, the remaining 4 is split over the leftover 0, 8 and 6
+		assert_fills(ProRataMin::new(2.0).allocate(&orders, 10.0), &[2.0, 2.0 + 4.0 * 8.0 / 14.0, 2.0 + 4.0 * 6.0 / 14.0]);
+		assert_fills(SizeTime::new(1.0).unwrap().allocate(&orders, 10.0), &[1.0, 5.0, 4.0]);
+	}
+
+	#[test]
+	fn test_weighted_allocation_caps() {
+		// The first order's weight entitles it to 8 but it only has 2, the rest is redistributed
+		assert_fills(weighted_allocation(&[2.0, 10.0], &[4.0, 1.0], 10.0), &[2.0, 8.0]);
+		let fills = SizeTime::new(0.5).unwrap().allocate(&level(&[10.0, 10.0]), 6.0);
+		assert_fills(fills, &[4.0, 2.0]);
+	}
+
+	#[test]
+	fn test_size_time_fills_volume() {
+		// The third order's weight underflows to zero, what the first two can't take goes to it
+		let orders = level(&[2.0, 10.0, 8.0]);
+		let fills = SizeTime::new(1e-200).unwrap().allocate(&orders, 15.0);
+		assert!((fills.iter().sum::<f64>() - 15.0).abs() < 1e-6);
+		assert_fills(fills, &[2.0, 10.0, 3.0]);
+	}
+
+	#[test]
+	fn test_size_time_rejects_invalid_decay() {
+		assert!(SizeTime::new(0.0).is_err());
+		assert!(SizeTime::new(-0.5).is_err());
+		assert!(SizeTime::new(1.5).is_err());
+		assert!(SizeTime::new(f64::NAN).is_err());
+		assert_eq!(SizeTime::new(1.0).unwrap().decay(), 1.0);
+	}
+}
@@ -0,0 +1,270 @@
+use crate::controller::{Task, State};
+use crate::exchange::queue::Queue;
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::order::{Order, OrderType, TradeType};
+
+use std::sync::{Mutex, Arc};
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// An automated market maker venue trading the base asset against the quote asset
+/// along a constant product curve x * y = k. Fees are charged on the input amount and
+/// stay in the pool. A concentrated liquidity pool only trades within a price range,
+/// modelled as a constant product pool over virtual reserves.
+/// pool_id: String -> trader_id the pool appears under in trades
+/// reserves: Mutex<(f64, f64)> -> virtual (base, quote) reserves the curve is applied to
+/// offsets: (f64, f64) -> part of the virtual reserves that isn't real, (0, 0) for full range
+/// fee: f64 -> fraction of every swap's input kept by the pool
+pub struct Pool {
+	pub pool_id: String,
+	pub reserves: Mutex<(f64, f64)>,
+	pub offsets: (f64, f64),
+	pub fee: f64,
+}
+
+impl Pool {
+	/// A full range constant product pool holding base and quote. Fails unless the fee
+	/// is within [0, 1) and both reserves are positive.
+	pub fn new(pool_id: String, base: f64, quote: f64, fee: f64) -> Result<Pool, &'static str> {
+		Pool::validate_fee(fee)?;
+		if !(base > 0.0 && quote > 0.0) {
+			return Err("Pool reserves must be positive");
+		}
+		Ok(Pool {
+			pool_id,
+			reserves: Mutex::new((base, quote)),
+			offsets: (0.0, 0.0),
+			fee,
+		})
+	}
+
+	/// A pool with the liquidity concentrated between p_low and p_high, currently at price.
+	/// Fails unless the fee is within [0, 1), the liquidity is positive and 0 < p_low < p_high.
+	pub fn concentrated(pool_id: String, liquidity: f64, price: f64, p_low: f64, p_high: f64, fee: f64) -> Result<Pool, &'static str> {
+		Pool::validate_fee(fee)?;
+		if liquidity.is_nan() || liquidity <= 0.0 {
+			return Err("Pool liquidity must be positive");
+		}
+		if !(p_low > 0.0 && p_low < p_high) {
+			return Err("Pool price range must satisfy 0 < p_low < p_high");
+		}
+		let price = price.max(p_low).min(p_high);
+		Ok(Pool {
+			pool_id,
+			reserves: Mutex::new((liquidity / price.sqrt(), liquidity * price.sqrt())),
+			offsets: (liquidity / p_high.sqrt(), liquidity * p_low.sqrt()),
+			fee,
+		})
+	}
+
+	// A fee of 1 or more would leave nothing of the input to swap
+	fn validate_fee(fee: f64) -> Result<(), &'static str> {
+		if !(0.0..1.0).contains(&fee) {
+			return Err("Pool fee must be within [0, 1)");
+		}
+		Ok(())
+	}
+
+	/// The marginal price of the base asset before fees
+	pub fn price(&self) -> f64 {
+		let (x, y) = *self.reserves.lock().unwrap();
+		y / x
+	}
+
+	/// The real (base, quote) reserves held by the pool
+	pub fn real_reserves(&self) -> (f64, f64) {
+		let (x, y) = *self.reserves.lock().unwrap();
+		(x - self.offsets.0, y - self.offsets.1)
+	}
+
+	/// The marginal price to buy the base asset from the pool, including the fee
+	pub fn buy_price(&self) -> f64 {
+		self.price() / (1.0 - self.fee)
+	}
+
+	/// The marginal price received selling the base asset to the pool, after the fee
+	pub fn sell_price(&self) -> f64 {
+		self.price() * (1.0 - self.fee)
+	}
+
+	/// Swaps the order against the curve as far as its quantity and limit price allow,
+	/// the limit acting as the slippage bound on the marginal price including fees.
+	/// Returns the trade at the swap's average price, or None if nothing could be swapped.
+	/// Any unfilled remainder is dropped since the pool never rests orders.
+	pub fn swap(&self, order: &Order) -> Option<Trade> {
+		self.swap_within(&order.trader_id, &order.trade_type, order.price, order.quantity)
+	}
+
+	/// Swaps up to quantity for the trader until the marginal price including fees reaches limit
+	pub fn swap_within(&self, trader_id: &str, trade_type: &TradeType, limit: f64, quantity: f64) -> Option<Trade> {
+		let mut reserves = self.reserves.lock().unwrap();
+		let (x, y) = *reserves;
+		let k = x * y;
+		let net = 1.0 - self.fee;
+
+		let (base, quote) = match trade_type {
+			TradeType::Bid => {
+				// Buying base until the marginal price y / x / (1 - fee) after the swap reaches
+				// the limit. With the fee left in the pool the base reserve u where that happens
+				// solves limit * net^2 * u^2 + y * fee * u - k = 0. A bid at or below zero has
+				// no root and never swaps.
+				if limit <= 0.0 {
+					return None;
+				}
+				let a = limit * net * net;
+				let u = (-y * self.fee + (y * y * self.fee * self.fee + 4.0 * a * k).sqrt()) / (2.0 * a);
+				if !u.is_finite() {
+					return None;
+				}
+				let dx = quantity.min(x - u.max(self.offsets.0));
+				if dx <= EPSILON {
+					return None;
+				}
+				let dy = (k / (x - dx) - y) / net;
+				*reserves = (x - dx, y + dy);
+				(dx, dy)
+			},
+			TradeType::Ask => {
+				// Selling base until the marginal price y / x * (1 - fee) after the swap falls to
+				// the limit, where the base reserve v solves limit * net * v^2 + limit * x * fee * v
+				// - k * net = 0. The quote reserve can't fall below its offset.
+				let (a, b) = (limit * net, limit * x * self.fee);
+				// A market sell at MIN_PRICE has no slippage bound
+				let v = if a > 0.0 { (-b + (b * b + 4.0 * a * k * net).sqrt()) / (2.0 * a) } else { f64::INFINITY };
+				if v.is_nan() || (a > 0.0 && !v.is_finite()) {
+					return None;
+				}
+				let range_limit = (k / self.offsets.1 - x) / net;
+				let dx = quantity.min(v - x).min(range_limit);
+				if dx <= EPSILON {
+					return None;
+				}
+				let dy = y - k / (x + dx * net);
+				*reserves = (x + dx, y - dy);
+				(dx, dy)
+			},
+		};
+
+		let (bid_id, ask_id) = match trade_type {
+			TradeType::Bid => (String::from(trader_id), self.pool_id.clone()),
+			TradeType::Ask => (self.pool_id.clone(), String::from(trader_id)),
+		};
+		Some(Trade::new(bid_id, ask_id, Some(trade_type.clone()), quote / base, base))
+	}
+}
+
+pub struct AmmProcessor {}
+
+impl AmmProcessor {
+	// Processes the orders in the queue against the pool in order of arrival. Enter and
+	// Update orders are swaps, cancels have nothing resting to cancel.
+	pub fn process_order_queue(queue: Arc<Queue>, pool: Arc<Pool>) -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		for order in queue.pop_all() {
+			if order.order_type == OrderType::Cancel {
+				continue;
+			}
+			match pool.swap(&order) {
+				Some(trade) => {
+					log_event!("{} swapped {} shares with {} @{}", order.trader_id, trade.quantity, pool.pool_id, trade.price);
+					trades.push(trade);
+				},
+				None => log_event!("{} couldn't swap within its limit", order.trader_id),
+			}
+		}
+		trades
+	}
+
+	/// Processes the queue against the pool on an interval determined by the duration parameter
+	/// in milliseconds while the market is processing, appending every trade to the trade log.
+	/// This task replaces QueueProcessor::async_queue_task when the venue is an AMM.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_amm_task(queue: Arc<Queue>, pool: Arc<Pool>, state: Arc<Mutex<State>>, trade_log: Arc<TradeLog>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			if *state.lock().unwrap() != State::Process {
+				return;
+			}
+			trade_log.add(AmmProcessor::process_order_queue(Arc::clone(&queue), Arc::clone(&pool)));
+		}, duration)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::order;
+
+	#[test]
+	fn test_constant_product_swap() {
+		let pool = Pool::new(String::from("amm"), 100.0, 10_000.0, 0.0).unwrap();
+		let trade = pool.swap(&order("t", TradeType::Bid, 1_000.0, 10.0)).unwrap();
+		assert_eq!((trade.bid_id.as_str(), trade.ask_id.as_str()), ("t", "amm"));
+		assert!((trade.price - 10_000.0 / 90.0).abs() < 1e-6);
+		let (x, y) = pool.real_reserves();
+		assert!((x * y - 1_000_000.0).abs() < 1e-3);
+
+		// Selling the 10 back returns the pool to where it started
+		let trade = pool.swap(&order("t", TradeType::Ask, 1.0, 10.0)).unwrap();
+		assert!((trade.price - 10_000.0 / 90.0).abs() < 1e-6);
+		assert!((pool.price() - 100.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_slippage_limit_and_fee() {
+		let pool = Pool::new(String::from("amm"), 100.0, 10_000.0, 0.003).unwrap();
+		// The buy stops once the marginal price with fees reaches 110
+		let trade = pool.swap(&order("t", TradeType::Bid, 110.0, 50.0)).unwrap();
+		assert!(trade.quantity < 50.0);
+		assert!((pool.price() / 0.997 - 110.0).abs() < 1e-6);
+		assert!(pool.swap(&order("t", TradeType::Bid, 110.0, 50.0)).is_none());
+		assert!(pool.swap(&order("t", TradeType::Ask, 200.0, 5.0)).is_none());
+
+		// And the sell once the marginal price after fees falls to 105
+		pool.swap(&order("t", TradeType::Ask, 105.0, 50.0)).unwrap();
+		assert!((pool.price() * 0.997 - 105.0).abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_concentrated_range() {
+		let pool = Pool::concentrated(String::from("clmm"), 1_000.0, 100.0, 90.0, 110.0, 0.0).unwrap();
+		// A market buy can't push the price beyond the top of the range
+		let trade = pool.swap(&order("t", TradeType::Bid, 999_999_999.0, 1_000.0)).unwrap();
+		assert!((pool.price() - 110.0).abs() < 1e-6);
+		assert!(pool.real_reserves().0.abs() < 1e-6);
+		assert!(trade.quantity < 10.0);
+
+		// And a market sell can't push it below the bottom
+		pool.swap(&order("t", TradeType::Ask, 0.0, 1_000.0)).unwrap();
+		assert!((pool.price() - 90.0).abs() < 1e-6);
+		assert!(pool.real_reserves().1.abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_non_positive_bid_limit() {
+		let pool = Pool::new(String::from("amm"), 100.0, 10_000.0, 0.003).unwrap();
+		// A bid at zero or below can't pay any price, so nothing swaps and the reserves stay put
+		assert!(pool.swap(&order("t", TradeType::Bid, 0.0, 10.0)).is_none());
+		assert!(pool.swap(&order("t", TradeType::Bid, -5.0, 10.0)).is_none());
+		assert_eq!(pool.real_reserves(), (100.0, 10_000.0));
+	}
+
+	#[test]
+	fn test_invalid_pools_rejected() {
+		let pool = |base, quote, fee| Pool::new(String::from("amm"), base, quote, fee).is_err();
+		assert!(pool(100.0, 10_000.0, -0.01));
+		assert!(pool(100.0, 10_000.0, 1.0));
+		assert!(pool(100.0, 10_000.0, f64::NAN));
+		assert!(pool(0.0, 10_000.0, 0.003));
+		assert!(pool(100.0, -1.0, 0.003));
+		assert!(!pool(100.0, 10_000.0, 0.0));
+
+		let clmm = |liquidity, p_low, p_high, fee| Pool::concentrated(String::from("clmm"), liquidity, 100.0, p_low, p_high, fee).is_err();
+		assert!(clmm(1_000.0, 90.0, 110.0, 1.5));
+		assert!(clmm(0.0, 90.0, 110.0, 0.003));
+		assert!(clmm(1_000.0, 0.0, 110.0, 0.003));
+		assert!(clmm(1_000.0, 110.0, 90.0, 0.003));
+		assert!(clmm(1_000.0, 100.0, 100.0, 0.003));
+		assert!(!clmm(1_000.0, 90.0, 110.0, 0.003));
+	}
+}
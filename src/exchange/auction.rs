@@ -1,27 +1,48 @@
 use crate::controller::{Task, State};
 use crate::exchange::order_book::Book;
-use crate::order::Order;
+use crate::exchange::trade::Trade;
+use crate::exchange::hybrid::HybridRouter;
+use crate::order::{Order, TradeType};
 
 use std::sync::{Mutex, Arc};
 use std::cmp::Ordering;
 
-use rayon::prelude::*;
 use crate::utility::get_time;
 
 
 const EPSILON: f64 =  0.000_000_001;
-const MAX_PRICE: f64 = 999_999_999.0;
-const MIN_PRICE: f64 = 0.0;
+pub const MAX_PRICE: f64 = 999_999_999.0;
+pub const MIN_PRICE: f64 = 0.0;
 
 pub struct Auction {}
 
+/// The result of searching the Books for a single clearing price.
+/// price: f64 -> the clearing price
+/// volume: f64 -> quantity that executes at price
+/// imbalance: f64 -> demand minus supply at price, the unmatched quantity
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equilibrium {
+	pub price: f64,
+	pub volume: f64,
+	pub imbalance: f64,
+}
+
 // TODO replace prints with way to log tx's
 
 impl Auction {
 	// Checks whether the new bid crosses the best ask. 
 	// A new bid will cross at best ask.price iff best ask.price ≤ new bid.price
 	// If the new order's quantity is not satisfied, the next best ask is checked.
-	pub fn calc_bid_crossing(bids: Arc<Book>, asks:Arc<Book>, mut new_bid: Order) {
+	// Returns the trades that occurred in the order they were filled.
+	pub fn calc_bid_crossing(bids: Arc<Book>, asks:Arc<Book>, mut new_bid: Order) -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		// In hybrid mode the AMM pool fills first while it is cheaper than the best ask
+		if let Some(trade) = HybridRouter::route(&asks, &mut new_bid) {
+			trades.push(trade);
+			if new_bid.quantity <= EPSILON {
+				return trades;
+			}
+		}
 		if new_bid.price >= asks.get_min_price() {
 			// buying for more than best ask is asking for -> tx @ ask price
 			// Get the best ask from book, if there is one, else nothing to cross so add bid to book
@@ -29,7 +50,7 @@ impl Auction {
 				Some(order) => order,
 				None => {
 					bids.add_order(new_bid).expect("Failed to add bid to book...");
-					return
+					return trades
 				}
 			};
 			// Modify quantities of best ask and new bid
@@ -37,16 +58,20 @@ impl Auction {
 				Ordering::Less => {
 					// This new bid will be satisfied and not be added to the book
 					best_ask.quantity -= new_bid.quantity;
-					println!("New bid:{} transacted {} shares with best ask:{} @{}", 
+					log_event!("New bid:{} transacted {} shares with best ask:{} @{}", 
 							new_bid.trader_id, new_bid.quantity, best_ask.trader_id, best_ask.price);
+					trades.push(Trade::new(new_bid.trader_id.clone(), best_ask.trader_id.clone(), 
+							Some(TradeType::Bid), best_ask.price, new_bid.quantity));
 					// Return the best ask to the book
 					asks.push_to_end(best_ask).expect("couldn't push");
 				},
 				Ordering::Greater => {
 					// This new bid potentially will cross with multiple asks
 					new_bid.quantity -= best_ask.quantity;
-					println!("New bid:{} transacted {} shares with best ask:{} @{}, clearing best ask from book", 
+					log_event!("New bid:{} transacted {} shares with best ask:{} @{}, clearing best ask from book", 
 							new_bid.trader_id, best_ask.quantity, best_ask.trader_id, best_ask.price);
+					trades.push(Trade::new(new_bid.trader_id.clone(), best_ask.trader_id.clone(), 
+							Some(TradeType::Bid), best_ask.price, best_ask.quantity));
 					
 					// Update the best ask price 
 					match asks.peek_best_price() {
@@ -58,18 +83,20 @@ impl Auction {
 							// No more asks in the book, need to add this bid to book, set default best ask price
 							asks.update_best_price(MAX_PRICE);
 							bids.add_order(new_bid).expect("Failed to add bid to book...");
-							return
+							return trades
 						}
 					}
 					// Don't return the bid to the book
 					
 					// Recursively check if new bid will fill more orders:
-					Auction::calc_bid_crossing(bids, asks, new_bid);
+					trades.extend(Auction::calc_bid_crossing(bids, asks, new_bid));
 				},
 				Ordering::Equal => {
 					// new bid clears the best ask removing it from book
-					println!("New bid:{} transacted {} shares with best ask:{} @{}, clearing best ask from book", 
+					log_event!("New bid:{} transacted {} shares with best ask:{} @{}, clearing best ask from book", 
 							new_bid.trader_id, new_bid.quantity, best_ask.trader_id, best_ask.price);
+					trades.push(Trade::new(new_bid.trader_id.clone(), best_ask.trader_id.clone(), 
+							Some(TradeType::Bid), best_ask.price, new_bid.quantity));
 
 					// Update the best ask price 
 					match asks.peek_best_price() {
@@ -89,12 +116,22 @@ impl Auction {
 			// New bid didn't cross, needs to be added to the book
 			bids.add_order(new_bid).expect("Failed to add bid to book...");
 		}
+		trades
 	}
 
 	// Checks whether the new ask crosses the best bid. 
 	// A new ask will cross at best bid.price iff best bid.price ≥ new ask.price
 	// If the new order's quantity is not satisfied, the next best bid is checked.
-	pub fn calc_ask_crossing(bids: Arc<Book>, asks:Arc<Book>, mut new_ask: Order) {
+	// Returns the trades that occurred in the order they were filled.
+	pub fn calc_ask_crossing(bids: Arc<Book>, asks:Arc<Book>, mut new_ask: Order) -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		// In hybrid mode the AMM pool fills first while it pays more than the best bid
+		if let Some(trade) = HybridRouter::route(&bids, &mut new_ask) {
+			trades.push(trade);
+			if new_ask.quantity <= EPSILON {
+				return trades;
+			}
+		}
 		if new_ask.price <= bids.get_max_price() {
 			// asking for less than best bid willing to pay -> tx @ bid price
 			// Modify quantities of best bid and this new ask
@@ -102,23 +139,27 @@ impl Auction {
 				Some(order) => order,
 				None => {
 					asks.add_order(new_ask).expect("Failed to add ask to book...");
-					return
+					return trades
 				}
 			};
 			match new_ask.quantity.partial_cmp(&best_bid.quantity).expect("bad cmp") {
 				Ordering::Less => {
 					// This new ask will be satisfied and not be added to the book
 					best_bid.quantity -= new_ask.quantity;
-					println!("New ask:{} transacted {} shares with best bid:{} @{}", 
+					log_event!("New ask:{} transacted {} shares with best bid:{} @{}", 
 							new_ask.trader_id, new_ask.quantity, best_bid.trader_id, best_bid.price);
+					trades.push(Trade::new(best_bid.trader_id.clone(), new_ask.trader_id.clone(), 
+							Some(TradeType::Ask), best_bid.price, new_ask.quantity));
 					// Return the best bid to the book
 					bids.push_to_end(best_bid).expect("bad push");
 				},
 				Ordering::Greater => {
 					// This new ask potentially will cross with multiple bids
 					new_ask.quantity -= best_bid.quantity;
-					println!("New ask:{} transacted {} shares with best bid:{} @{}, clearing best bid from book", 
+					log_event!("New ask:{} transacted {} shares with best bid:{} @{}, clearing best bid from book", 
 							new_ask.trader_id, best_bid.quantity, best_bid.trader_id, best_bid.price);
+					trades.push(Trade::new(best_bid.trader_id.clone(), new_ask.trader_id.clone(), 
+							Some(TradeType::Ask), best_bid.price, best_bid.quantity));
 					
 					// Update the best bid price 
 					match bids.peek_best_price() {
@@ -130,16 +171,18 @@ impl Auction {
 							// No more bids in the book, need to add this ask to book, set default best bid price
 							bids.update_best_price(MIN_PRICE);
 							asks.add_order(new_ask).expect("Failed to add ask to book...");
-							return
+							return trades
 						}
 					}
 					// Recursively check if new ask will fill more orders:
-					Auction::calc_ask_crossing(bids, asks, new_ask);
+					trades.extend(Auction::calc_ask_crossing(bids, asks, new_ask));
 				},
 				Ordering::Equal => {
 					// new ask clears the best bid removing it from book
-					println!("New ask:{} transacted {} shares with best bid:{} @{}, clearing best bid from book", 
+					log_event!("New ask:{} transacted {} shares with best bid:{} @{}, clearing best bid from book", 
 							new_ask.trader_id, new_ask.quantity, best_bid.trader_id, best_bid.price);
+					trades.push(Trade::new(best_bid.trader_id.clone(), new_ask.trader_id.clone(), 
+							Some(TradeType::Ask), best_bid.price, new_ask.quantity));
 					
 					// Update the best bid price 
 					match bids.peek_best_price() {
@@ -159,13 +202,126 @@ impl Auction {
 			// New ask didn't cross, needs to be added to the book
 			asks.add_order(new_ask).expect("Failed to add ask to book...");
 		}
+		trades
 	}
 
 	
 
 	// Calculates which orders in the order book will transact at auction time.
 	pub fn frequent_batch_auction(bids: Arc<Book>, asks: Arc<Book>) -> Option<f64> {
-		unimplemented!();
+		let (price, trades) = Auction::uncross(bids, asks)?;
+		for trade in trades {
+			log_event!("Auction bid:{} transacted {} shares with ask:{} @{}", 
+					trade.bid_id, trade.quantity, trade.ask_id, trade.price);
+		}
+		Some(price)
+	}
+
+	// Finds the single price that maximizes the volume executable between the Books.
+	// Ties are broken by the smallest demand/supply imbalance, then by the price closest
+	// to the middle of the remaining tied prices. Returns None if the Books don't cross.
+	pub fn calc_equilibrium(bids: &Book, asks: &Book) -> Option<Equilibrium> {
+		let bid_levels: Vec<(f64, f64)> = bids.orders.lock().unwrap().iter().map(|o| (o.price, o.quantity)).collect();
+		let ask_levels: Vec<(f64, f64)> = asks.orders.lock().unwrap().iter().map(|o| (o.price, o.quantity)).collect();
+
+		// The executable volume only changes at prices where orders rest
+		let mut prices: Vec<f64> = bid_levels.iter().chain(ask_levels.iter()).map(|(p, _)| *p).collect();
+		prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		prices.dedup();
+
+		let mut tied = Vec::<Equilibrium>::new();
+		for price in prices {
+			let demand: f64 = bid_levels.iter().filter(|(p, _)| *p >= price).map(|(_, q)| q).sum();
+			let supply: f64 = ask_levels.iter().filter(|(p, _)| *p <= price).map(|(_, q)| q).sum();
+			let eq = Equilibrium { price, volume: demand.min(supply), imbalance: demand - supply };
+			if eq.volume < EPSILON {
+				continue;
+			}
+			let best = tied.first().map(|b| (b.volume, b.imbalance.abs()));
+			match best {
+				Some((volume, _)) if Auction::less_than_e(&eq.volume, &volume) => continue,
+				Some((volume, _)) if Auction::greater_than_e(&eq.volume, &volume) => tied.clear(),
+				Some((_, imbalance)) if Auction::less_than_e(&eq.imbalance.abs(), &imbalance) => tied.clear(),
+				Some((_, imbalance)) if Auction::greater_than_e(&eq.imbalance.abs(), &imbalance) => continue,
+				_ => {},
+			}
+			tied.push(eq);
+		}
+
+		// Prices were visited in ascending order so the tied prices are sorted
+		let middle = (tied.first()?.price + tied.last()?.price) / 2.0;
+		tied.into_iter().min_by(|a, b| (a.price - middle).abs().partial_cmp(&(b.price - middle).abs()).unwrap())
+	}
+
+	// Uncrosses the Books at the equilibrium price. Orders on each side are filled in
+	// price priority until the equilibrium volume is reached, with the marginal level
+	// rationed by each Book's allocation policy, and any remainders stay in the Books. Returns the clearing price and the resulting trades.
+	pub fn uncross(bids: Arc<Book>, asks: Arc<Book>) -> Option<(f64, Vec<Trade>)> {
+		let eq = Auction::calc_equilibrium(&bids, &asks)?;
+		let bid_fills = Auction::take_fills(&bids, eq.volume);
+		let ask_fills = Auction::take_fills(&asks, eq.volume);
+		Some((eq.price, Auction::pair_fills(bid_fills, ask_fills, eq.price)))
+	}
+
+	// Fills the Book's price levels from the best price until volume is filled, returning
+	// each trader's fill. The level that can't be filled in full is rationed by the Book's
+	// allocation policy, and partially filled orders keep their priority.
+	fn take_fills(book: &Book, volume: f64) -> Vec<(String, f64)> {
+		let policy = book.get_allocation();
+		let mut orders = book.orders.lock().expect("ERROR: Couldn't lock book to fill orders");
+		let mut fills = Vec::<(String, f64)>::new();
+		let mut remaining = volume;
+		while remaining > EPSILON && !orders.is_empty() {
+			// The best level sits at the end of the Book with its earliest order first
+			let price = orders.last().unwrap().price;
+			let start = orders.iter().rposition(|o| o.price != price).map_or(0, |i| i + 1);
+			let level_qty: f64 = orders[start..].iter().map(|o| o.quantity).sum();
+
+			if level_qty <= remaining + EPSILON {
+				for order in orders.drain(start..) {
+					fills.push((order.trader_id.clone(), order.quantity));
+				}
+				remaining -= level_qty;
+				continue;
+			}
+
+			// The policy sees the level unboxed, its orders go back with their fills taken
+			let mut level: Vec<Order> = orders.drain(start..).map(|o| *o).collect();
+			let allocations = policy.allocate(&level, remaining);
+			for (order, fill) in level.iter_mut().zip(allocations) {
+				if fill > EPSILON {
+					order.quantity -= fill;
+					fills.push((order.trader_id.clone(), fill));
+				}
+			}
+			orders.extend(level.into_iter().filter(|o| o.quantity > EPSILON).map(Box::new));
+			break;
+		}
+		drop(orders);
+		book.refresh_touch();
+		fills
+	}
+
+	// Matches the bid fills against the ask fills in order to produce trades at price
+	pub(crate) fn pair_fills(bid_fills: Vec<(String, f64)>, ask_fills: Vec<(String, f64)>, price: f64) -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		let mut asks = ask_fills.into_iter().peekable();
+		for (bid_id, mut bid_qty) in bid_fills {
+			while bid_qty > EPSILON {
+				let (ask_id, ask_qty) = match asks.peek_mut() {
+					Some(ask) => ask,
+					None => break,
+				};
+				let qty = bid_qty.min(*ask_qty);
+				trades.push(Trade::new(bid_id.clone(), ask_id.clone(), None, price, qty));
+				bid_qty -= qty;
+				*ask_qty -= qty;
+				if *ask_qty <= EPSILON {
+					asks.next();
+				}
+			}
+		}
+		trades
 	}
 
 	/// Schedules an auction to run on an interval determined by the duration parameter in milliseconds.
@@ -178,11 +334,11 @@ impl Auction {
 	    		let mut state = state.lock().unwrap();
 	    		*state = State::Auction;
 	    	}
-	    	println!("Starting Auction @{:?}", get_time());
+	    	log_event!("Starting Auction @{:?}", get_time());
 	    	if let Some(cross_price) = Auction::frequent_batch_auction(Arc::clone(&bids), Arc::clone(&asks)) {
-	    		println!("Found Cross at @{:?} \nP = {}\n", get_time(), cross_price);
+	    		log_event!("Found Cross at @{:?} \nP = {}\n", get_time(), cross_price);
 	    	} else {
-	    		println!("Error, Cross not found\n");
+	    		log_event!("Error, Cross not found\n");
 	    	}
 	    	
 	    	{
@@ -222,37 +378,75 @@ impl Auction {
 	pub fn greater_than_e(a: &f64, b: &f64) -> bool {
 		let a = a.abs();
 		let b = b.abs();
-	    if (a - b).abs() > EPSILON && a - b > 0.0 {
-	    	return true;
-	    } else {
-	    	return false;
-	    }
+	    (a - b).abs() > EPSILON && a - b > 0.0
 	}
 
 	// true if a < b
 	pub fn less_than_e(a: &f64, b: &f64) -> bool {
 		let a = a.abs();
 		let b = b.abs();
-	    if (a - b).abs() > EPSILON && a - b < 0.0 {
-	    	return true;
-	    } else {
-	    	return false;
-	    }
+	    (a - b).abs() > EPSILON && a - b < 0.0
 	}
 
 	pub	fn equal_e(a: &f64, b: &f64) -> bool {
-	    if (a - b).abs() < EPSILON {
-	    	return true;
-	    } else {
-	    	return false;
-	    }
+	    (a - b).abs() < EPSILON
 	}
 }
 
 
 
+#[test]
+fn test_uncross() {
+	use crate::order::OrderType;
+	let bids = Arc::new(Book::new(TradeType::Bid));
+	let asks = Arc::new(Book::new(TradeType::Ask));
+	for (id, price, qty) in [("b1", 102.0, 5.0), ("b2", 101.0, 5.0), ("b3", 99.0, 5.0)] {
+		bids.add_order(Order::new(String::from(id), OrderType::Enter, TradeType::Bid, price, qty)).unwrap();
+	}
+	for (id, price, qty) in [("a1", 98.0, 4.0), ("a2", 100.0, 4.0), ("a3", 103.0, 5.0)] {
+		asks.add_order(Order::new(String::from(id), OrderType::Enter, TradeType::Ask, price, qty)).unwrap();
+	}
+
+	// 8 shares execute at 100 and 101 with the same imbalance, ties at equal
+	// distance from the middle settle on the lower price
+	let eq = Auction::calc_equilibrium(&bids, &asks).unwrap();
+	assert_eq!((eq.price, eq.volume, eq.imbalance), (100.0, 8.0, 2.0));
+
+	let (price, trades) = Auction::uncross(Arc::clone(&bids), Arc::clone(&asks)).unwrap();
+	assert_eq!(price, 100.0);
+	assert_eq!(trades.iter().map(|t| t.quantity).sum::<f64>(), 8.0);
+	assert!(trades.iter().all(|t| t.price == 100.0 && t.aggressor.is_none()));
+
+	// b2 keeps 2 shares, a3 is untouched and the Books no longer cross
+	assert_eq!(bids.get_max_price(), 101.0);
+	assert_eq!(asks.get_min_price(), 103.0);
+	assert!(Auction::calc_equilibrium(&bids, &asks).is_none());
+}
+
+#[test]
+fn test_uncross_pro_rata() {
+	use crate::order::OrderType;
+	use crate::exchange::allocation::ProRata;
+	let bids = Arc::new(Book::new(TradeType::Bid));
+	let asks = Arc::new(Book::new(TradeType::Ask));
+	bids.set_allocation(Arc::new(ProRata{}));
+	for (id, qty) in [("b1", 2.0), ("b2", 6.0)] {
+		bids.add_order(Order::new(String::from(id), OrderType::Enter, TradeType::Bid, 100.0, qty)).unwrap();
+	}
+	asks.add_order(Order::new(String::from("a1"), OrderType::Enter, TradeType::Ask, 100.0, 4.0)).unwrap();
+
+	// Both bids get half of their size instead of b1 filling first
+	let (_, trades) = Auction::uncross(Arc::clone(&bids), Arc::clone(&asks)).unwrap();
+	assert_eq!(trades.len(), 2);
+	assert_eq!((trades[0].bid_id.as_str(), trades[0].quantity), ("b1", 1.0));
+	assert_eq!((trades[1].bid_id.as_str(), trades[1].quantity), ("b2", 3.0));
+	assert_eq!(bids.len(), 2);
+	assert!(asks.is_empty());
+}
+
 #[test]
 fn test_par_iter() {
+	use rayon::prelude::*;
 	let big_sum: u32 = (0..10).collect::<Vec<u32>>()
 		.par_iter()
 	    .map(|x| x * x)
@@ -0,0 +1,216 @@
+use crate::controller::{Task, State};
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::queue_processing::QueueProcessor;
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::order::{Order, TradeType};
+use crate::utility::get_time;
+
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use std::sync::{Mutex, Arc};
+
+/// Decides the sequence the orders collected into a block are applied to the Books in.
+/// Implement this trait to model other sequencers.
+pub trait OrderingRule: Send + Sync {
+	/// Returns the block's orders in the order they will be processed. The block
+	/// arrives in order of arrival at the Queue.
+	fn order_block(&self, block: Vec<Order>) -> Vec<Order>;
+}
+
+/// Processes the block in order of arrival, like the continuous market
+pub struct Fifo {}
+
+impl OrderingRule for Fifo {
+	fn order_block(&self, block: Vec<Order>) -> Vec<Order> {
+		block
+	}
+}
+
+/// Processes the highest gas price first, ties in order of arrival
+pub struct GasPriority {}
+
+impl OrderingRule for GasPriority {
+	fn order_block(&self, mut block: Vec<Order>) -> Vec<Order> {
+		block.sort_by(|a, b| b.gas_price.partial_cmp(&a.gas_price).unwrap());
+		block
+	}
+}
+
+/// Processes the block in a uniformly random order, drawn from a seeded stream so runs
+/// can be reproduced
+/// rng: Mutex<StdRng> -> source of the shuffles, e.g. an ExperimentSeed's substream
+pub struct RandomShuffle {
+	rng: Mutex<StdRng>,
+}
+
+impl RandomShuffle {
+	pub fn new(rng: StdRng) -> RandomShuffle {
+		RandomShuffle {
+			rng: Mutex::new(rng),
+		}
+	}
+}
+
+impl OrderingRule for RandomShuffle {
+	fn order_block(&self, mut block: Vec<Order>) -> Vec<Order> {
+		block.shuffle(&mut *self.rng.lock().unwrap());
+		block
+	}
+}
+
+/// A block producer that reorders blocks in favour of its own orders. The side with
+/// the most quantity from other traders is the block's user flow: the producer's
+/// orders on that side are moved to the front of the block to trade ahead of it and
+/// its orders on the opposite side are moved to the back to trade against its impact.
+/// Other orders keep their order of arrival.
+/// producer_id: String -> trader_id the block producer submits its own orders under
+pub struct Adversarial {
+	pub producer_id: String,
+}
+
+impl Adversarial {
+	pub fn new(producer_id: String) -> Adversarial {
+		Adversarial {
+			producer_id,
+		}
+	}
+}
+
+impl OrderingRule for Adversarial {
+	fn order_block(&self, block: Vec<Order>) -> Vec<Order> {
+		let (own, others): (Vec<Order>, Vec<Order>) = block.into_iter().partition(|o| o.trader_id == self.producer_id);
+		let flow = |side: TradeType| -> f64 {
+			others.iter().filter(|o| o.trade_type == side).map(|o| o.quantity).sum()
+		};
+		let user_side = if flow(TradeType::Bid) >= flow(TradeType::Ask) { TradeType::Bid } else { TradeType::Ask };
+
+		let (front, back): (Vec<Order>, Vec<Order>) = own.into_iter().partition(|o| o.trade_type == user_side);
+		front.into_iter().chain(others).chain(back).collect()
+	}
+}
+
+/// The record of a block applied to the Books.
+/// height: u64 -> number of the block, starting at 0
+/// order_ids: Vec<String> -> trader_id of each order in the sequence it was processed
+/// trades: Vec<Trade> -> trades the block produced
+#[derive(Debug, Clone)]
+pub struct Block {
+	pub height: u64,
+	pub order_ids: Vec<String>,
+	pub trades: Vec<Trade>,
+}
+
+/// Collects the orders arriving at the Queue into blocks and applies each block to the
+/// Books in the sequence chosen by its OrderingRule, in place of continuous processing.
+/// rule: Box<dyn OrderingRule> -> how each block is sequenced
+/// height: Mutex<u64> -> height of the next block
+pub struct BlockProducer {
+	pub rule: Box<dyn OrderingRule>,
+	height: Mutex<u64>,
+}
+
+impl BlockProducer {
+	pub fn new(rule: Box<dyn OrderingRule>) -> BlockProducer {
+		BlockProducer {
+			rule,
+			height: Mutex::new(0),
+		}
+	}
+
+	/// Takes every order waiting in the Queue as the next block, sequences it and processes
+	/// it in that order. Stops released by the block's trades are returned to the Queue
+	/// for the next block.
+	pub fn produce_block(&self, queue: &Queue, bids: Arc<Book>, asks: Arc<Book>) -> Block {
+		let ordered = self.rule.order_block(queue.pop_all());
+		let order_ids = ordered.iter().map(|o| o.trader_id.clone()).collect();
+
+		let block = Arc::new(Queue::new());
+		for order in ordered {
+			block.add(order);
+		}
+		let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&block), bids, asks);
+		for order in block.pop_all() {
+			queue.add(order);
+		}
+
+		let mut height = self.height.lock().unwrap();
+		let produced = Block { height: *height, order_ids, trades };
+		*height += 1;
+		produced
+	}
+
+	/// Produces a block on an interval determined by the duration parameter in milliseconds
+	/// while the market is processing, appending every trade to the trade log. This task
+	/// replaces QueueProcessor::async_queue_task in block producer mode.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_block_task(producer: Arc<BlockProducer>,
+							queue: Arc<Queue>,
+							bids: Arc<Book>,
+							asks: Arc<Book>,
+							state: Arc<Mutex<State>>,
+							trade_log: Arc<TradeLog>,
+							duration: u64) -> Task
+	{
+		Task::rpt_task(move || {
+			if *state.lock().unwrap() != State::Process {
+				return;
+			}
+			let block = producer.produce_block(&queue, Arc::clone(&bids), Arc::clone(&asks));
+			if !block.order_ids.is_empty() {
+				log_event!("Block {} @{:?}: {} orders, {} trades", block.height, get_time(),
+						 block.order_ids.len(), block.trades.len());
+			}
+			trade_log.add(block.trades);
+		}, duration)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{order, OrderSetup};
+
+	fn ids(block: &[Order]) -> Vec<&str> {
+		block.iter().map(|o| o.trader_id.as_str()).collect()
+	}
+
+	#[test]
+	fn test_ordering_rules() {
+		let block = || vec![order("a", TradeType::Bid, 100.0, 5.0).with_gas_price(1.0),
+							order("p1", TradeType::Ask, 101.0, 2.0),
+							order("b", TradeType::Bid, 100.0, 1.0).with_gas_price(3.0),
+							order("p1", TradeType::Bid, 100.0, 2.0)];
+		assert_eq!(ids(&Fifo{}.order_block(block())), ["a", "p1", "b", "p1"]);
+		assert_eq!(ids(&GasPriority{}.order_block(block())), ["b", "a", "p1", "p1"]);
+		let shuffle = |seed: u64| -> Vec<String> {
+			let rule = RandomShuffle::new(crate::simulation::seed::ExperimentSeed::new(seed).stream("sequencer"));
+			rule.order_block(block()).into_iter().map(|o| o.trader_id).collect()
+		};
+		assert_eq!(shuffle(1).len(), 4);
+		assert_eq!(shuffle(1), shuffle(1));
+
+		// The users are buying so the producer's bid goes first and its ask last
+		let sequenced = Adversarial::new(String::from("p1")).order_block(block());
+		assert_eq!(ids(&sequenced), ["p1", "a", "b", "p1"]);
+		assert_eq!(sequenced[0].trade_type, TradeType::Bid);
+	}
+
+	#[test]
+	fn test_produce_block() {
+		let (queue, bids, asks, _) = crate::setup_exchange();
+		asks.add_order(order("s", TradeType::Ask, 100.0, 5.0)).unwrap();
+		queue.add(order("user", TradeType::Bid, 100.0, 5.0).with_gas_price(1.0));
+		queue.add(order("p1", TradeType::Bid, 100.0, 5.0));
+
+		// The producer's bid is moved ahead of the user's and takes the liquidity
+		let producer = BlockProducer::new(Box::new(Adversarial::new(String::from("p1"))));
+		let block = producer.produce_block(&queue, Arc::clone(&bids), Arc::clone(&asks));
+		assert_eq!(block.height, 0);
+		assert_eq!(block.order_ids, ["p1", "user"]);
+		assert_eq!(block.trades.len(), 1);
+		assert_eq!(block.trades[0].bid_id, "p1");
+		assert_eq!(producer.produce_block(&queue, bids, asks).height, 1);
+	}
+}
@@ -0,0 +1,261 @@
+use crate::controller::{Task, State};
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::queue_processing::QueueProcessor;
+use crate::exchange::auction::{Auction, MAX_PRICE, MIN_PRICE};
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::order::Order;
+use crate::utility::get_time;
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, Arc};
+use std::time::Duration;
+
+/// Enum for matching over what the market does when a circuit breaker trips
+/// Halt -> stop processing the queue until the halt ends
+/// ReopeningAuction -> accumulate orders without matching, then uncross the Books
+#[derive(Debug, PartialEq, Clone)]
+pub enum BreakerAction {
+	Halt,
+	ReopeningAuction,
+}
+
+/// A volatility circuit breaker that trips when the trade price moves more than
+/// max_move within the window.
+/// max_move: f64 -> fractional move between the window's low and high that trips the breaker
+/// window: Duration -> how far back trades are considered
+/// halt_length: Duration -> how long the market stays halted once tripped
+/// action: BreakerAction{Halt, ReopeningAuction} -> what the market does while tripped
+pub struct CircuitBreaker {
+	pub max_move: f64,
+	pub window: Duration,
+	pub halt_length: Duration,
+	pub action: BreakerAction,
+	prices: Mutex<VecDeque<(Duration, f64)>>,
+	halted_at: Mutex<Option<Duration>>,
+}
+
+impl CircuitBreaker {
+	pub fn new(max_move: f64, window: Duration, halt_length: Duration, action: BreakerAction) -> CircuitBreaker {
+		CircuitBreaker {
+			max_move,
+			window,
+			halt_length,
+			action,
+			prices: Mutex::new(VecDeque::new()),
+			halted_at: Mutex::new(None),
+		}
+	}
+
+	/// Records the trades' prices and returns true if the price range within the
+	/// window now exceeds max_move. Trades older than the window are forgotten and
+	/// off-book trades are ignored.
+	pub fn record(&self, trades: &[Trade]) -> bool {
+		let mut prices = self.prices.lock().unwrap();
+		let mut tripped = false;
+		for trade in trades.iter().filter(|t| !t.off_book) {
+			prices.push_back((trade.time, trade.price));
+			while let Some(&(time, _)) = prices.front() {
+				if trade.time > time + self.window {
+					prices.pop_front();
+				} else {
+					break;
+				}
+			}
+			let low = prices.iter().fold(f64::MAX, |low, (_, p)| low.min(*p));
+			let high = prices.iter().fold(0.0, |high: f64, (_, p)| high.max(*p));
+			if low > 0.0 && (high - low) / low > self.max_move {
+				tripped = true;
+			}
+		}
+		tripped
+	}
+
+	/// Returns true if the breaker has tripped and the market has not yet resumed
+	pub fn is_tripped(&self) -> bool {
+		self.halted_at.lock().unwrap().is_some()
+	}
+
+	/// Switches the market into the breaker's halted state. A closed market has nothing
+	/// to halt and stays closed.
+	pub fn trip(&self, state: &Mutex<State>, now: Duration) {
+		if *state.lock().unwrap() == State::Closed {
+			log_event!("Circuit breaker tripped @{:?} after the close, ignored", now);
+			return;
+		}
+		*self.halted_at.lock().unwrap() = Some(now);
+		*state.lock().unwrap() = match self.action {
+			BreakerAction::Halt => State::Halted,
+			BreakerAction::ReopeningAuction => State::PreAuction,
+		};
+		log_event!("Circuit breaker tripped @{:?}, market {:?}", now, *state.lock().unwrap());
+	}
+
+	/// Resumes continuous trading once the halt has lasted halt_length. A reopening
+	/// auction uncrosses the orders that accumulated during the halt first. Returns
+	/// the reopening trades if the market resumed, after releasing any stops they set off
+	/// onto the Queue. If the session closed during the halt the breaker resets and the
+	/// market stays closed.
+	pub fn try_resume(&self, state: &Mutex<State>, queue: &Queue, bids: Arc<Book>, asks: Arc<Book>, now: Duration) -> Option<Vec<Trade>> {
+		let halted_at = (*self.halted_at.lock().unwrap())?;
+		if *state.lock().unwrap() == State::Closed {
+			self.prices.lock().unwrap().clear();
+			*self.halted_at.lock().unwrap() = None;
+			log_event!("Circuit breaker reset @{:?}, market closed", now);
+			return None;
+		}
+		if now < halted_at + self.halt_length {
+			return None;
+		}
+
+		let mut trades = Vec::<Trade>::new();
+		if self.action == BreakerAction::ReopeningAuction {
+			*state.lock().unwrap() = State::Auction;
+			if let Some((price, reopening)) = Auction::uncross(Arc::clone(&bids), Arc::clone(&asks)) {
+				log_event!("Reopening auction cleared {} trades @{}", reopening.len(), price);
+				QueueProcessor::release_stops(&bids, &asks, queue, &reopening);
+				trades = reopening;
+			}
+		}
+
+		// The price window restarts so the move that tripped the breaker doesn't trip it again
+		self.prices.lock().unwrap().clear();
+		*self.halted_at.lock().unwrap() = None;
+		*state.lock().unwrap() = State::Process;
+		log_event!("Market resumed @{:?}", now);
+		Some(trades)
+	}
+
+	/// Watches the trade log on an interval determined by the duration parameter in milliseconds,
+	/// tripping the breaker on large moves and resuming the market once the halt is over.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_breaker_task(breaker: Arc<CircuitBreaker>,
+							  queue: Arc<Queue>,
+							  trade_log: Arc<TradeLog>,
+							  bids: Arc<Book>,
+							  asks: Arc<Book>,
+							  state: Arc<Mutex<State>>,
+							  duration: u64) -> Task
+	{
+		let seen = Mutex::new(0);
+		Task::rpt_task(move || {
+			let mut seen = seen.lock().unwrap();
+			let new_trades = trade_log.since(*seen);
+			*seen += new_trades.len();
+
+			if breaker.is_tripped() {
+				if let Some(trades) = breaker.try_resume(&state, &queue, Arc::clone(&bids), Arc::clone(&asks), get_time()) {
+					*seen += trades.len();
+					trade_log.add(trades);
+				}
+			} else if breaker.record(&new_trades) {
+				breaker.trip(&state, get_time());
+			}
+		}, duration)
+	}
+}
+
+/// A static price band around a reference price. Limit orders priced outside the
+/// band are rejected and market orders are capped at the band's edge.
+/// reference: f64 -> price the band is centered on, e.g. the previous close
+/// width: f64 -> fractional distance from the reference to either edge
+#[derive(Debug, PartialEq, Clone)]
+pub struct PriceBand {
+	pub reference: f64,
+	pub width: f64,
+}
+
+impl PriceBand {
+	pub fn new(reference: f64, width: f64) -> PriceBand {
+		PriceBand {
+			reference,
+			width,
+		}
+	}
+
+	pub fn lower(&self) -> f64 {
+		self.reference * (1.0 - self.width)
+	}
+
+	pub fn upper(&self) -> f64 {
+		self.reference * (1.0 + self.width)
+	}
+
+	/// Checks the order against the band, capping market orders at the band's edge
+	pub fn check(&self, order: &mut Order) -> Result<(), &'static str> {
+		if order.price >= MAX_PRICE {
+			order.price = self.upper();
+		} else if order.price <= MIN_PRICE {
+			order.price = self.lower();
+		}
+
+		if order.price < self.lower() || order.price > self.upper() {
+			return Err("ERROR: order price outside of price band");
+		}
+		Ok(())
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{OrderType, TradeType, ExecType};
+
+	fn trade(price: f64, millis: u64) -> Trade {
+		let mut trade = Trade::new(String::from("b"), String::from("a"), None, price, 1.0);
+		trade.time = Duration::from_millis(millis);
+		trade
+	}
+
+	#[test]
+	fn test_breaker_window() {
+		let breaker = CircuitBreaker::new(0.05, Duration::from_millis(1_000),
+										  Duration::from_millis(500), BreakerAction::Halt);
+		// A 10% move spread over longer than the window doesn't trip
+		assert!(!breaker.record(&[trade(100.0, 0), trade(104.0, 900), trade(110.0, 2_000)]));
+		// But a 6% drop within it does
+		assert!(breaker.record(&[trade(103.0, 2_500)]));
+	}
+
+	#[test]
+	fn test_breaker_halts_and_resumes() {
+		let state = Mutex::new(State::Process);
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		let breaker = CircuitBreaker::new(0.05, Duration::from_millis(1_000),
+										  Duration::from_millis(500), BreakerAction::ReopeningAuction);
+		breaker.trip(&state, Duration::from_millis(1_000));
+		assert_eq!(*state.lock().unwrap(), State::PreAuction);
+
+		// Orders accumulate while tripped
+		let queue = Queue::new();
+		let mut stop = Order::new(String::from("stop"), OrderType::Enter, TradeType::Ask, 90.0, 5.0);
+		stop.exec_type = ExecType::Stop(100.0);
+		asks.stops.add_order(stop);
+		bids.add_order(Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 101.0, 5.0)).unwrap();
+		asks.add_order(Order::new(String::from("a"), OrderType::Enter, TradeType::Ask, 99.0, 5.0)).unwrap();
+
+		assert!(breaker.try_resume(&state, &queue, Arc::clone(&bids), Arc::clone(&asks), Duration::from_millis(1_200)).is_none());
+		let trades = breaker.try_resume(&state, &queue, Arc::clone(&bids), Arc::clone(&asks), Duration::from_millis(1_500)).unwrap();
+		assert_eq!(trades.len(), 1);
+
+		// The reopening trade at 99 sets off the sell stop waiting at 100
+		assert_eq!(trades[0].price, 99.0);
+		let released = queue.pop().unwrap();
+		assert_eq!((released.trader_id.as_str(), released.price), ("stop", MIN_PRICE));
+		assert!(asks.stops.is_empty());
+		assert_eq!(*state.lock().unwrap(), State::Process);
+		assert!(!breaker.is_tripped());
+	}
+
+	#[test]
+	fn test_price_band() {
+		let band = PriceBand::new(100.0, 0.1);
+		let mut order = Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 111.0, 5.0);
+		assert!(band.check(&mut order).is_err());
+		order.price = MAX_PRICE;
+		assert!(band.check(&mut order).is_ok());
+		assert!((order.price - 110.0).abs() < 1e-9);
+	}
+}
@@ -0,0 +1,382 @@
+use crate::controller::{Task, State};
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::queue_processing::QueueProcessor;
+use crate::exchange::schedule::TradingSchedule;
+use crate::exchange::trade::TradeLog;
+use crate::order::{Order, TradeType, ExecType, PegType, TimeInForce};
+use crate::utility::get_time;
+
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Arc};
+use std::time::Duration;
+
+/// A sealed order a trader committed to without revealing it.
+/// trader_id: String -> trader that sent the commitment
+/// hash: String -> CommitStore::commitment of the order and the trader's secret nonce
+/// time: Duration -> time the commitment was received
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commitment {
+	pub trader_id: String,
+	pub hash: String,
+	pub time: Duration,
+}
+
+/// Reported for every commitment still unrevealed when the reveal window closes.
+/// commitment: Commitment -> the commitment that was never revealed
+/// penalty: f64 -> penalty charged to the trader, 0 if the store doesn't penalize
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnrevealedReport {
+	pub commitment: Commitment,
+	pub penalty: f64,
+}
+
+/// Stores the commitments of a sealed-bid round and verifies their reveals. Commitments
+/// are only accepted in the Commit phase and reveals only in the Reveal phase, and valid
+/// reveals wait here until the round's batch auction admits them to the Books.
+/// phase: Mutex<State> -> phase of the current round, State::Process outside of a round
+/// commitments: Mutex<HashMap<String, Commitment>> -> outstanding commitment per trader_id
+/// revealed: Mutex<Vec<Order>> -> verified orders in order of reveal
+/// penalty: Mutex<Option<f64>> -> optional penalty for every commitment left unrevealed
+/// penalties: Mutex<HashMap<String, f64>> -> total penalties charged per trader_id
+pub struct CommitStore {
+	pub phase: Mutex<State>,
+	commitments: Mutex<HashMap<String, Commitment>>,
+	revealed: Mutex<Vec<Order>>,
+	pub penalty: Mutex<Option<f64>>,
+	penalties: Mutex<HashMap<String, f64>>,
+}
+
+impl Default for CommitStore {
+	fn default() -> Self {
+		CommitStore::new(None)
+	}
+}
+
+impl CommitStore {
+	pub fn new(penalty: Option<f64>) -> CommitStore {
+		CommitStore {
+			phase: Mutex::new(State::Process),
+			commitments: Mutex::new(HashMap::new()),
+			revealed: Mutex::new(Vec::new()),
+			penalty: Mutex::new(penalty),
+			penalties: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// The commitment a trader must send for the order and a secret nonce: the SHA-256
+	/// digest of CommitStore::preimage as 64 lowercase hex characters.
+	pub fn commitment(order: &Order, nonce: u64) -> String {
+		Sha256::digest(CommitStore::preimage(order, nonce).as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+	}
+
+	/// The canonical text a commitment hashes, fixed so that changes to the order's JSON
+	/// never invalidate commitments. The fields are joined by '|' in this order:
+	///
+	/// v1|len:trader_id|trade_type|exec|time_in_force|price|quantity|gas_price|nonce
+	///
+	/// - len is the byte length of trader_id, so ids containing '|' stay unambiguous
+	/// - trade_type is "bid" or "ask"
+	/// - exec is "limit", "stop:trigger", "stop_limit:trigger",
+	///   "pegged:peg_type:offset:cap" with peg_type "primary", "market" or "midpoint"
+	///   and cap empty if there is none, or "flow:p_low:p_high:u_max"
+	/// - time_in_force is "gtc", "gtt:expire_time" with the expiry in milliseconds since
+	///   UNIX_EPOCH, "day" or "ioc"
+	/// - nonce is a decimal integer
+	///
+	/// Prices and quantities are written as the shortest decimal that reads back as the
+	/// same f64, without an exponent or a trailing ".0", e.g. 100, 99.5 or 0.0001. The
+	/// order_type isn't part of it since a revealed order always enters.
+	pub fn preimage(order: &Order, nonce: u64) -> String {
+		let trade_type = match order.trade_type {
+			TradeType::Bid => "bid",
+			TradeType::Ask => "ask",
+		};
+		let exec = match order.exec_type {
+			ExecType::Limit => String::from("limit"),
+			ExecType::Stop(trigger) => format!("stop:{}", trigger),
+			ExecType::StopLimit(trigger) => format!("stop_limit:{}", trigger),
+			ExecType::Pegged(ref peg) => {
+				let peg_type = match peg.peg_type {
+					PegType::Primary => "primary",
+					PegType::Market => "market",
+					PegType::Midpoint => "midpoint",
+				};
+				let cap = peg.cap.map(|c| c.to_string()).unwrap_or_default();
+				format!("pegged:{}:{}:{}", peg_type, peg.offset, cap)
+			},
+			ExecType::Flow(ref s) => format!("flow:{}:{}:{}", s.p_low, s.p_high, s.u_max),
+		};
+		let time_in_force = match order.time_in_force {
+			TimeInForce::GoodTillCancel => String::from("gtc"),
+			TimeInForce::GoodTillTime(expiry) => format!("gtt:{}", expiry.as_millis()),
+			TimeInForce::Day => String::from("day"),
+			TimeInForce::ImmediateOrCancel => String::from("ioc"),
+		};
+		format!("v1|{}:{}|{}|{}|{}|{}|{}|{}|{}", order.trader_id.len(), order.trader_id, trade_type, exec,
+				time_in_force, order.price, order.quantity, order.gas_price, nonce)
+	}
+
+	/// Records a trader's commitment, replacing any earlier one in the same round. The
+	/// hash must be a hex SHA-256 digest, optionally prefixed by "0x".
+	pub fn commit(&self, trader_id: String, hash: String) -> Result<(), &'static str> {
+		if *self.phase.lock().unwrap() != State::Commit {
+			return Err("ERROR: commitments are only accepted during the commit phase");
+		}
+		let hash = hash.trim_start_matches("0x").to_lowercase();
+		if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Err("ERROR: commitment isn't a hex SHA-256 digest");
+		}
+		let commitment = Commitment { trader_id: trader_id.clone(), hash, time: get_time() };
+		self.commitments.lock().unwrap().insert(trader_id, commitment);
+		Ok(())
+	}
+
+	/// Verifies the revealed order against its trader's commitment and keeps it for
+	/// the batch auction if it matches
+	pub fn reveal(&self, order: Order, nonce: u64) -> Result<(), &'static str> {
+		if *self.phase.lock().unwrap() != State::Reveal {
+			return Err("ERROR: reveals are only accepted during the reveal phase");
+		}
+		let mut commitments = self.commitments.lock().unwrap();
+		match commitments.get(&order.trader_id) {
+			Some(c) if c.hash == CommitStore::commitment(&order, nonce) => {},
+			Some(_) => return Err("ERROR: reveal doesn't match the commitment"),
+			None => return Err("ERROR: no commitment found to reveal"),
+		}
+		commitments.remove(&order.trader_id);
+		self.revealed.lock().unwrap().push(order);
+		Ok(())
+	}
+
+	/// Ends the round's reveal window. Returns the verified orders and a report for every
+	/// commitment that wasn't revealed, charging the penalty if one is set.
+	pub fn close_reveal(&self) -> (Vec<Order>, Vec<UnrevealedReport>) {
+		let revealed: Vec<Order> = self.revealed.lock().unwrap().drain(..).collect();
+		let mut unrevealed: Vec<Commitment> = self.commitments.lock().unwrap().drain().map(|(_, c)| c).collect();
+		unrevealed.sort_by_key(|c| c.time);
+
+		let penalty = self.penalty.lock().unwrap().unwrap_or(0.0);
+		let mut penalties = self.penalties.lock().unwrap();
+		let reports = unrevealed.into_iter().map(|commitment| {
+			*penalties.entry(commitment.trader_id.clone()).or_insert(0.0) += penalty;
+			UnrevealedReport { commitment, penalty }
+		}).collect();
+		(revealed, reports)
+	}
+
+	/// Sets or clears the penalty charged for each unrevealed commitment
+	pub fn set_penalty(&self, penalty: Option<f64>) {
+		*self.penalty.lock().unwrap() = penalty;
+	}
+
+	/// Total penalties charged to the trader for unrevealed commitments
+	pub fn penalties_for(&self, trader_id: &str) -> f64 {
+		*self.penalties.lock().unwrap().get(trader_id).unwrap_or(&0.0)
+	}
+
+	/// Moves the market and the store into the phase of a sealed-bid round
+	pub fn set_phase(&self, state: &Mutex<State>, phase: State) {
+		*self.phase.lock().unwrap() = phase.clone();
+		*state.lock().unwrap() = phase;
+	}
+
+	/// Closes the round: reports unrevealed commitments, admits the verified orders to the
+	/// Books without matching and uncrosses them in a batch auction, then resumes
+	/// continuous trading. Stops the auction sets off are released onto queue. Returns
+	/// the clearing price if the Books crossed and the unrevealed commitment reports.
+	pub fn close_round(&self,
+					   queue: &Queue,
+					   bids: Arc<Book>,
+					   asks: Arc<Book>,
+					   state: &Mutex<State>,
+					   trade_log: &TradeLog) -> (Option<f64>, Vec<UnrevealedReport>)
+	{
+		let (revealed, reports) = self.close_reveal();
+		for report in &reports {
+			log_event!("Unrevealed commitment: {:?}", report);
+		}
+
+		let sealed = Arc::new(Queue::new());
+		for order in revealed {
+			sealed.add(order);
+		}
+		QueueProcessor::accumulate_order_queue(sealed, Arc::clone(&bids), Arc::clone(&asks));
+		*self.phase.lock().unwrap() = State::Process;
+		(TradingSchedule::call_auction(queue, bids, asks, state, trade_log, State::Process), reports)
+	}
+}
+
+/// The windows of a sealed-bid round in milliseconds. Traders send commitments during
+/// the commit window and reveal them during the reveal window, after which the verified
+/// orders are uncrossed in a batch auction.
+/// commit: u64 -> length of the commit window
+/// reveal: u64 -> length of the reveal window
+#[derive(Debug, Clone)]
+pub struct SealedRound {
+	pub commit: u64,
+	pub reveal: u64,
+}
+
+impl SealedRound {
+	pub fn new(commit: u64, reveal: u64) -> SealedRound {
+		SealedRound {
+			commit,
+			reveal,
+		}
+	}
+
+	/// The phases of 'rounds' back to back sealed-bid rounds with the time in milliseconds
+	/// each one starts. State::Process closes the round.
+	pub fn phases(&self, rounds: u64) -> Vec<(u64, State)> {
+		let mut phases = Vec::<(u64, State)>::new();
+		for round in 0..rounds {
+			let start = round * (self.commit + self.reveal);
+			phases.push((start, State::Commit));
+			phases.push((start + self.commit, State::Reveal));
+			phases.push((start + self.commit + self.reveal, State::Process));
+		}
+		phases
+	}
+
+	/// Outputs the delayed tasks that run 'rounds' back to back sealed-bid rounds on the
+	/// Queue's CommitStore, to be dispatched asynchronously via the controller module.
+	pub fn async_round_tasks(&self,
+							 queue: Arc<Queue>,
+							 bids: Arc<Book>,
+							 asks: Arc<Book>,
+							 state: Arc<Mutex<State>>,
+							 trade_log: Arc<TradeLog>,
+							 rounds: u64) -> Vec<Task>
+	{
+		self.phases(rounds).into_iter().map(|(start, phase)| {
+			let (q, b, a, s, l) = (Arc::clone(&queue), Arc::clone(&bids), Arc::clone(&asks),
+								   Arc::clone(&state), Arc::clone(&trade_log));
+			Task::delay_task(move || {
+				SealedRound::run_phase(&phase, &q, Arc::clone(&b), Arc::clone(&a), &s, &l);
+			}, start)
+		}).collect()
+	}
+
+	/// Moves the market and the Queue's CommitStore into the phase, closing the round
+	/// when the phase is State::Process
+	pub fn run_phase(phase: &State,
+					 queue: &Queue,
+					 bids: Arc<Book>,
+					 asks: Arc<Book>,
+					 state: &Mutex<State>,
+					 trade_log: &TradeLog)
+	{
+		match phase {
+			State::Process => {
+				queue.commits.close_round(queue, bids, asks, state, trade_log);
+			},
+			phase => {
+				log_event!("{:?} phase @{:?}", phase, get_time());
+				queue.commits.set_phase(state, phase.clone());
+			},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{OrderType, Peg};
+
+	#[test]
+	fn test_sealed_round_tasks() {
+		let round = SealedRound::new(100, 50);
+		let (queue, bids, asks, state) = crate::setup_exchange();
+		let trade_log = TradeLog::new();
+		let tasks = round.async_round_tasks(Arc::clone(&queue), Arc::clone(&bids), Arc::clone(&asks),
+											Arc::clone(&state), Arc::new(TradeLog::new()), 3);
+		assert_eq!(tasks.len(), 9);
+		let phases = round.phases(3);
+		let starts: Vec<u64> = phases.iter().map(|(start, _)| *start).collect();
+		assert_eq!(starts, [0, 100, 150, 150, 250, 300, 300, 400, 450]);
+
+		// Run the first round, where "late" commits but never reveals
+		queue.commits.set_penalty(Some(2.0));
+		let bid = || Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 101.0, 5.0);
+		let ask = || Order::new(String::from("a"), OrderType::Enter, TradeType::Ask, 99.0, 5.0);
+		let mut states = Vec::<State>::new();
+		for (_, phase) in &phases[..3] {
+			SealedRound::run_phase(phase, &queue, Arc::clone(&bids), Arc::clone(&asks), &state, &trade_log);
+			states.push(state.lock().unwrap().clone());
+			match phase {
+				State::Commit => {
+					queue.commits.commit(String::from("b"), CommitStore::commitment(&bid(), 1)).unwrap();
+					queue.commits.commit(String::from("a"), CommitStore::commitment(&ask(), 2)).unwrap();
+					queue.commits.commit(String::from("late"), CommitStore::commitment(&bid(), 3)).unwrap();
+				},
+				State::Reveal => {
+					queue.commits.reveal(bid(), 1).unwrap();
+					queue.commits.reveal(ask(), 2).unwrap();
+				},
+				_ => {},
+			}
+		}
+		assert_eq!(states, [State::Commit, State::Reveal, State::Process]);
+		assert_eq!(*queue.commits.phase.lock().unwrap(), State::Process);
+		assert_eq!(trade_log.len(), 1);
+		assert_eq!(queue.commits.penalties_for("late"), 2.0);
+		assert_eq!(queue.commits.penalties_for("b"), 0.0);
+	}
+
+	#[test]
+	fn test_commit_reveal_round() {
+		let store = CommitStore::new(Some(5.0));
+		let state = Mutex::new(State::Process);
+		let bid = || Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 101.0, 5.0);
+		let ask = || Order::new(String::from("a"), OrderType::Enter, TradeType::Ask, 99.0, 5.0);
+
+		assert!(store.commit(String::from("b"), CommitStore::commitment(&bid(), 7)).is_err());
+		store.set_phase(&state, State::Commit);
+		store.commit(String::from("b"), CommitStore::commitment(&bid(), 7)).unwrap();
+		store.commit(String::from("a"), CommitStore::commitment(&ask(), 9)).unwrap();
+		store.commit(String::from("x"), format!("0x{}", "AB".repeat(32))).unwrap();
+		assert!(store.commit(String::from("y"), format!("{:x}", 42)).is_err());
+		assert!(store.reveal(bid(), 7).is_err());
+
+		store.set_phase(&state, State::Reveal);
+		assert_eq!(*state.lock().unwrap(), State::Reveal);
+		// A wrong nonce or an altered order doesn't match the commitment
+		assert!(store.reveal(bid(), 8).is_err());
+		let mut altered = ask();
+		altered.price = 98.0;
+		assert!(store.reveal(altered, 9).is_err());
+		store.reveal(bid(), 7).unwrap();
+		store.reveal(ask(), 9).unwrap();
+
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		let trade_log = TradeLog::new();
+		let (price, reports) = store.close_round(&Queue::new(), Arc::clone(&bids), Arc::clone(&asks), &state, &trade_log);
+		assert_eq!(price, Some(99.0));
+		assert_eq!(reports.len(), 1);
+		assert_eq!((reports[0].commitment.trader_id.as_str(), reports[0].penalty), ("x", 5.0));
+		assert_eq!(trade_log.len(), 1);
+		assert_eq!(*state.lock().unwrap(), State::Process);
+		assert_eq!(store.penalties_for("x"), 5.0);
+		assert_eq!(store.penalties_for("b"), 0.0);
+	}
+
+	#[test]
+	fn test_canonical_preimage() {
+		let mut order = Order::new(String::from("a|b"), OrderType::Enter, TradeType::Ask, 99.5, 5.0);
+		assert_eq!(CommitStore::preimage(&order, 7), "v1|3:a|b|ask|limit|gtc|99.5|5|0|7");
+		order.exec_type = ExecType::Pegged(Peg::new(PegType::Midpoint, 0.25, None));
+		order.time_in_force = TimeInForce::GoodTillTime(Duration::from_millis(1_500));
+		order.gas_price = 0.0001;
+		assert_eq!(CommitStore::preimage(&order, 7), "v1|3:a|b|ask|pegged:midpoint:0.25:|gtt:1500|99.5|5|0.0001|7");
+
+		// A published digest, so traders hashing outside the exchange can check theirs
+		let order = Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 101.0, 5.0);
+		assert_eq!(CommitStore::preimage(&order, 7), "v1|1:b|bid|limit|gtc|101|5|0|7");
+		assert_eq!(CommitStore::commitment(&order, 7), "01075af808e3e27f16ff7608b1330717108783a57341fdd7f46f60fca1d41d53");
+	}
+}
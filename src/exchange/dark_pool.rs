@@ -0,0 +1,184 @@
+use crate::controller::{Task, State};
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::order::{Order, OrderType, TradeType};
+
+use std::sync::{Mutex, Arc};
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// An order resting in the dark pool.
+/// order: Order -> the order, its price acting only as a limit on the midpoint
+/// min_qty: f64 -> smallest quantity it will execute in a single fill, 0 for no minimum
+pub struct DarkOrder {
+	pub order: Order,
+	pub min_qty: f64,
+}
+
+/// A non-displayed crossing venue running alongside the lit Books. Orders rest in
+/// order of arrival and are never shown: nothing is written to the lit Books and every
+/// fill is printed off-book. Crosses happen only at the lit midpoint of the best bid
+/// and best ask, between a bid whose limit is at or above it and an ask whose limit is
+/// at or below it, and only for fills meeting both orders' minimum execution sizes.
+/// bids: Mutex<Vec<DarkOrder>> -> resting buy orders in order of arrival
+/// asks: Mutex<Vec<DarkOrder>> -> resting sell orders in order of arrival
+pub struct DarkPool {
+	bids: Mutex<Vec<DarkOrder>>,
+	asks: Mutex<Vec<DarkOrder>>,
+}
+
+impl Default for DarkPool {
+	fn default() -> Self {
+		DarkPool::new()
+	}
+}
+
+impl DarkPool {
+	pub fn new() -> DarkPool {
+		DarkPool {
+			bids: Mutex::new(Vec::new()),
+			asks: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Rests an Enter order, replaces the trader's order for an Update and removes it
+	/// for a Cancel. min_qty of None means the order accepts fills of any size.
+	pub fn add_order(&self, order: Order, min_qty: Option<f64>) {
+		let mut side = self.side(&order.trade_type).lock().unwrap();
+		match order.order_type {
+			OrderType::Enter => {},
+			OrderType::Update | OrderType::Cancel => side.retain(|o| o.order.trader_id != order.trader_id),
+		}
+		if order.order_type != OrderType::Cancel {
+			side.push(DarkOrder { order, min_qty: min_qty.unwrap_or(0.0) });
+		}
+	}
+
+	/// The midpoint of the lit Books' best prices, if both have resting orders and
+	/// aren't crossed
+	pub fn midpoint(bids: &Book, asks: &Book) -> Option<f64> {
+		match (bids.peek_best_price(), asks.peek_best_price()) {
+			(Some(bid), Some(ask)) if bid <= ask => Some((bid + ask) / 2.0),
+			_ => None,
+		}
+	}
+
+	/// Crosses the resting orders at the lit midpoint. Bids are matched in order of arrival
+	/// against the asks in order of arrival, skipping pairs whose fill would be below either
+	/// order's minimum size. Returns the off-book trades.
+	pub fn cross(&self, bids: &Book, asks: &Book) -> Vec<Trade> {
+		let mid = match DarkPool::midpoint(bids, asks) {
+			Some(mid) => mid,
+			None => return Vec::new(),
+		};
+		let mut dark_bids = self.bids.lock().unwrap();
+		let mut dark_asks = self.asks.lock().unwrap();
+		let mut trades = Vec::<Trade>::new();
+
+		for bid in dark_bids.iter_mut().filter(|b| b.order.price >= mid) {
+			for ask in dark_asks.iter_mut().filter(|a| a.order.price <= mid) {
+				if bid.order.quantity <= EPSILON {
+					break;
+				}
+				let fill = bid.order.quantity.min(ask.order.quantity);
+				// A minimum larger than what remains of the order is capped at the remainder
+				if fill <= EPSILON
+					|| fill < bid.min_qty.min(bid.order.quantity) - EPSILON
+					|| fill < ask.min_qty.min(ask.order.quantity) - EPSILON {
+					continue;
+				}
+				let mut trade = Trade::new(bid.order.trader_id.clone(), ask.order.trader_id.clone(), None, mid, fill);
+				trade.off_book = true;
+				trades.push(trade);
+				bid.order.quantity -= fill;
+				ask.order.quantity -= fill;
+			}
+		}
+
+		dark_bids.retain(|o| o.order.quantity > EPSILON);
+		dark_asks.retain(|o| o.order.quantity > EPSILON);
+		trades
+	}
+
+	/// Number of orders resting on each side as (bids, asks)
+	pub fn depth(&self) -> (usize, usize) {
+		(self.bids.lock().unwrap().len(), self.asks.lock().unwrap().len())
+	}
+
+	/// Crosses the Queue's dark pool at the lit midpoint on an interval determined by the
+	/// duration parameter in milliseconds while the market is processing, appending the
+	/// off-book trades to the trade log.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_dark_task(queue: Arc<Queue>, bids: Arc<Book>, asks: Arc<Book>, state: Arc<Mutex<State>>, trade_log: Arc<TradeLog>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			if *state.lock().unwrap() != State::Process {
+				return;
+			}
+			trade_log.add(queue.dark.cross(&bids, &asks));
+		}, duration)
+	}
+
+	fn side(&self, trade_type: &TradeType) -> &Mutex<Vec<DarkOrder>> {
+		match trade_type {
+			TradeType::Bid => &self.bids,
+			TradeType::Ask => &self.asks,
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::order;
+
+	#[test]
+	fn test_midpoint_cross() {
+		let (bids, asks) = (Book::new(TradeType::Bid), Book::new(TradeType::Ask));
+		let pool = DarkPool::new();
+		pool.add_order(order("b1", TradeType::Bid, 101.0, 10.0), None);
+		pool.add_order(order("a1", TradeType::Ask, 99.0, 4.0), None);
+		// Nothing crosses without a lit market to take the midpoint from
+		assert!(pool.cross(&bids, &asks).is_empty());
+
+		bids.add_order(order("lit_b", TradeType::Bid, 99.0, 1.0)).unwrap();
+		asks.add_order(order("lit_a", TradeType::Ask, 101.0, 1.0)).unwrap();
+		pool.add_order(order("a2", TradeType::Ask, 100.5, 4.0), None);
+		let trades = pool.cross(&bids, &asks);
+		assert_eq!(trades.len(), 1);
+		assert_eq!((trades[0].ask_id.as_str(), trades[0].price, trades[0].quantity), ("a1", 100.0, 4.0));
+		assert!(trades[0].off_book);
+		assert_eq!(pool.depth(), (1, 1));
+
+		// The lit Books and the public last price are untouched
+		assert_eq!((bids.len(), asks.len()), (1, 1));
+		let trade_log = TradeLog::new();
+		trade_log.add(trades);
+		assert_eq!(trade_log.last_price(), None);
+		assert_eq!(trade_log.off_book_share(), 1.0);
+	}
+
+	#[test]
+	fn test_minimum_execution_size() {
+		let (bids, asks) = (Book::new(TradeType::Bid), Book::new(TradeType::Ask));
+		bids.add_order(order("lit_b", TradeType::Bid, 99.0, 1.0)).unwrap();
+		asks.add_order(order("lit_a", TradeType::Ask, 101.0, 1.0)).unwrap();
+		let pool = DarkPool::new();
+		pool.add_order(order("big", TradeType::Bid, 100.0, 10.0), Some(5.0));
+		pool.add_order(order("small", TradeType::Ask, 100.0, 2.0), None);
+		pool.add_order(order("large", TradeType::Ask, 100.0, 6.0), None);
+
+		// The 2 share ask is below the bid's minimum so it is skipped for the 6 share ask
+		let trades = pool.cross(&bids, &asks);
+		assert_eq!(trades.len(), 1);
+		assert_eq!((trades[0].ask_id.as_str(), trades[0].quantity), ("large", 6.0));
+
+		// The remaining 4 is below the minimum of 5, so the minimum caps at the remainder
+		// and the 2 share ask still can't fill it
+		assert!(pool.cross(&bids, &asks).is_empty());
+		pool.add_order(order("rest", TradeType::Ask, 100.0, 4.0), None);
+		assert_eq!(pool.cross(&bids, &asks).len(), 1);
+		assert_eq!(pool.depth(), (0, 1));
+	}
+}
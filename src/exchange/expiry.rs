@@ -0,0 +1,144 @@
+use crate::controller::Task;
+use crate::exchange::order_book::Book;
+use crate::order::{Order, TradeType, TimeInForce};
+use crate::utility::get_time;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Enum for matching over why the exchange cancelled a resting order
+#[derive(Debug, PartialEq, Clone)]
+pub enum CancelReason {
+	Expired,
+	SessionClose,
+	Unfilled,
+}
+
+/// A report sent for each order the exchange cancels on a trader's behalf.
+/// trader_id: String -> identifier of the trader whose order was cancelled
+/// trade_type: TradeType{Bid, Ask} -> side of the cancelled order
+/// price: f64 -> price the order was resting at
+/// quantity: f64 -> unfilled quantity that was cancelled
+/// reason: CancelReason{Expired, SessionClose, Unfilled} -> why the order was cancelled
+/// time: Duration -> time since UNIX_EPOCH of the cancellation
+#[derive(Debug, Clone, PartialEq)]
+pub struct CancelReport {
+	pub trader_id: String,
+	pub trade_type: TradeType,
+	pub price: f64,
+	pub quantity: f64,
+	pub reason: CancelReason,
+	pub time: Duration,
+}
+
+impl CancelReport {
+	pub fn new(order: &Order, reason: CancelReason, time: Duration) -> CancelReport {
+		CancelReport {
+			trader_id: order.trader_id.clone(),
+			trade_type: order.trade_type.clone(),
+			price: order.price,
+			quantity: order.quantity,
+			reason,
+			time,
+		}
+	}
+}
+
+pub struct ExpiryProcessor {}
+
+impl ExpiryProcessor {
+	/// Cancels every GoodTillTime order, resting, waiting on a trigger or waiting for a
+	/// flow batch, whose expiry is at or before 'now' and returns a report for each.
+	pub fn cancel_expired(bids: Arc<Book>, asks: Arc<Book>, now: Duration) -> Vec<CancelReport> {
+		ExpiryProcessor::purge(&bids, &asks, |o| o.is_expired(now), CancelReason::Expired, now)
+	}
+
+	/// Closes the trading session by cancelling every Day order, resting, waiting on a
+	/// trigger or waiting for a flow batch, and returns a report for each.
+	pub fn close_session(bids: Arc<Book>, asks: Arc<Book>, now: Duration) -> Vec<CancelReport> {
+		ExpiryProcessor::purge(&bids, &asks, |o| o.time_in_force == TimeInForce::Day,
+							   CancelReason::SessionClose, now)
+	}
+
+	fn purge<F>(bids: &Book, asks: &Book, pred: F, reason: CancelReason, now: Duration) -> Vec<CancelReport>
+	where F: Fn(&Order) -> bool
+	{
+		let mut removed = bids.remove_orders(&pred);
+		removed.extend(bids.stops.remove_orders(&pred));
+		removed.extend(bids.flows.remove_orders(&pred));
+		removed.extend(asks.remove_orders(&pred));
+		removed.extend(asks.stops.remove_orders(&pred));
+		removed.extend(asks.flows.remove_orders(&pred));
+		removed.iter().map(|o| CancelReport::new(o, reason.clone(), now)).collect()
+	}
+
+	/// Checks for expired orders on an interval determined by the duration parameter in milliseconds.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_expiry_task(bids: Arc<Book>, asks: Arc<Book>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			for report in ExpiryProcessor::cancel_expired(Arc::clone(&bids), Arc::clone(&asks), get_time()) {
+				log_event!("Expired order cancelled: {:?}", report);
+			}
+		}, duration)
+	}
+
+	/// Closes the session every session_length milliseconds, purging all Day orders so
+	/// the books start each simulated day the way real markets do.
+	pub fn async_session_close_task(bids: Arc<Book>, asks: Arc<Book>, session_length: u64) -> Task {
+		Task::rpt_task(move || {
+			let reports = ExpiryProcessor::close_session(Arc::clone(&bids), Arc::clone(&asks), get_time());
+			log_event!("Session closed @{:?}, cancelled {} day orders", get_time(), reports.len());
+			for report in reports {
+				log_event!("Day order cancelled: {:?}", report);
+			}
+		}, session_length)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{ExecType, FlowSchedule};
+	use crate::test_utils::{order, OrderSetup};
+
+	#[test]
+	fn test_cancel_expired() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		let now = Duration::from_secs(1_000);
+		bids.add_order(order("gtc", TradeType::Bid, 99.0, 5.0)).unwrap();
+		bids.add_order(order("gtt", TradeType::Bid, 100.0, 5.0).with_time_in_force(TimeInForce::GoodTillTime(now))).unwrap();
+		asks.stops.add_order(order("stop", TradeType::Ask, 90.0, 5.0)
+			.with_exec_type(ExecType::Stop(95.0)).with_time_in_force(TimeInForce::GoodTillTime(now)));
+		asks.flows.add_order(order("flow", TradeType::Ask, 100.0, 5.0)
+			.with_exec_type(ExecType::Flow(FlowSchedule::new(95.0, 105.0, 1.0))).with_time_in_force(TimeInForce::GoodTillTime(now)));
+		asks.add_order(order("later", TradeType::Ask, 101.0, 5.0)
+			.with_time_in_force(TimeInForce::GoodTillTime(now + Duration::from_secs(1)))).unwrap();
+
+		let reports = ExpiryProcessor::cancel_expired(Arc::clone(&bids), Arc::clone(&asks), now);
+		let ids: Vec<&str> = reports.iter().map(|r| r.trader_id.as_ref()).collect();
+		assert_eq!(ids, vec!["gtt", "stop", "flow"]);
+		assert!(reports.iter().all(|r| r.reason == CancelReason::Expired));
+
+		// The best bid falls back to the remaining order
+		assert_eq!(bids.get_max_price(), 99.0);
+		assert_eq!(asks.len(), 1);
+		assert!(asks.stops.is_empty());
+		assert!(asks.flows.is_empty());
+	}
+
+	#[test]
+	fn test_close_session() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		bids.add_order(order("day", TradeType::Bid, 99.0, 5.0).with_time_in_force(TimeInForce::Day)).unwrap();
+		asks.add_order(order("gtc", TradeType::Ask, 101.0, 5.0)).unwrap();
+
+		let reports = ExpiryProcessor::close_session(Arc::clone(&bids), Arc::clone(&asks), get_time());
+		assert_eq!(reports.len(), 1);
+		assert_eq!(reports[0].reason, CancelReason::SessionClose);
+		assert!(bids.is_empty());
+		assert_eq!(asks.len(), 1);
+	}
+}
@@ -0,0 +1,153 @@
+use crate::controller::Task;
+use crate::exchange::order_book::Book;
+use crate::exchange::flow_book::FlowBook;
+use crate::exchange::auction::Auction;
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::utility::get_time;
+
+use std::sync::Arc;
+
+const EPSILON: f64 = 0.000_000_001;
+const MAX_ITERATIONS: usize = 200;
+
+/// The outcome of a single flow market batch.
+/// price: f64 -> uniform price every flow order traded at
+/// volume: f64 -> total quantity traded by each side
+/// bid_fills: Vec<(String, f64)> -> quantity each buyer traded, by trader_id
+/// ask_fills: Vec<(String, f64)> -> quantity each seller traded, by trader_id
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowClearing {
+	pub price: f64,
+	pub volume: f64,
+	pub bid_fills: Vec<(String, f64)>,
+	pub ask_fills: Vec<(String, f64)>,
+}
+
+pub struct FlowAuction {}
+
+impl FlowAuction {
+	// Finds the price where the aggregate flow demand equals the aggregate flow supply.
+	// Excess demand never increases with price, so the root is found by bisection over
+	// the range spanned by the schedules. Steps in the schedules may leave no exact root,
+	// in which case the side of the bracket trading more volume is chosen.
+	// Returns None if either book is empty.
+	pub fn calc_clearing_price(bids: &FlowBook, asks: &FlowBook) -> Option<f64> {
+		let (bid_low, bid_high) = bids.price_bounds()?;
+		let (ask_low, ask_high) = asks.price_bounds()?;
+		let mut low = bid_low.min(ask_low);
+		let mut high = bid_high.max(ask_high);
+
+		for _ in 0..MAX_ITERATIONS {
+			if high - low < EPSILON {
+				break;
+			}
+			let mid = (low + high) / 2.0;
+			if bids.aggregate_rate(mid) > asks.aggregate_rate(mid) {
+				low = mid;
+			} else {
+				high = mid;
+			}
+		}
+		let volume = |p: f64| bids.aggregate_rate(p).min(asks.aggregate_rate(p));
+		if volume(low) >= volume(high) {
+			Some(low)
+		} else {
+			Some(high)
+		}
+	}
+
+	// Clears one batch of the flow market. Every flow order trades its schedule's rate at
+	// the clearing price, and if a step leaves one side longer it is rationed pro rata.
+	// Fills reduce each order's remaining quantity and exhausted orders leave the FlowBook.
+	// Returns None if nothing trades.
+	pub fn clear(bids: Arc<Book>, asks: Arc<Book>) -> Option<(FlowClearing, Vec<Trade>)> {
+		let price = FlowAuction::calc_clearing_price(&bids.flows, &asks.flows)?;
+		let demand = bids.flows.aggregate_rate(price);
+		let supply = asks.flows.aggregate_rate(price);
+		let volume = demand.min(supply);
+		if volume < EPSILON {
+			return None;
+		}
+
+		let bid_fills = FlowAuction::take_fills(&bids.flows, price, volume / demand);
+		let ask_fills = FlowAuction::take_fills(&asks.flows, price, volume / supply);
+		let trades = Auction::pair_fills(bid_fills.clone(), ask_fills.clone(), price);
+		Some((FlowClearing { price, volume, bid_fills, ask_fills }, trades))
+	}
+
+	// Fills each order at its rate scaled by the rationing ratio, removing exhausted orders
+	fn take_fills(book: &FlowBook, price: f64, ratio: f64) -> Vec<(String, f64)> {
+		let mut orders = book.orders.lock().expect("ERROR: Couldn't lock flow book to fill orders");
+		let mut fills = Vec::<(String, f64)>::new();
+		for order in orders.iter_mut() {
+			let fill = FlowBook::order_rate(order, price) * ratio;
+			if fill > EPSILON {
+				order.quantity -= fill;
+				fills.push((order.trader_id.clone(), fill));
+			}
+		}
+		orders.retain(|o| o.quantity > EPSILON);
+		fills
+	}
+
+	/// Clears the flow market on an interval determined by the duration parameter in milliseconds.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_flow_auction_task(bids: Arc<Book>, asks: Arc<Book>, trade_log: Arc<TradeLog>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			match FlowAuction::clear(Arc::clone(&bids), Arc::clone(&asks)) {
+				Some((clearing, trades)) => {
+					log_event!("Flow batch @{:?} cleared {} shares @{}", get_time(), clearing.volume, clearing.price);
+					trade_log.add(trades);
+				},
+				None => log_event!("Flow batch @{:?} found no trades", get_time()),
+			}
+		}, duration)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{Order, OrderType, TradeType, ExecType, FlowSchedule};
+
+	fn flow(id: &str, trade_type: TradeType, schedule: FlowSchedule, quantity: f64) -> Order {
+		let mut order = Order::new(String::from(id), OrderType::Enter, trade_type, 0.0, quantity);
+		order.exec_type = ExecType::Flow(schedule);
+		order
+	}
+
+	#[test]
+	fn test_linear_clearing() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		bids.flows.add_order(flow("b1", TradeType::Bid, FlowSchedule::new(90.0, 110.0, 10.0), 100.0));
+		bids.flows.add_order(flow("b2", TradeType::Bid, FlowSchedule::new(90.0, 110.0, 10.0), 100.0));
+		asks.flows.add_order(flow("a1", TradeType::Ask, FlowSchedule::new(95.0, 115.0, 20.0), 100.0));
+
+		// 20 * (110 - p) / 20 = 20 * (p - 95) / 20 -> p = 102.5, each bid trades 3.75
+		let (clearing, trades) = FlowAuction::clear(Arc::clone(&bids), Arc::clone(&asks)).unwrap();
+		assert!((clearing.price - 102.5).abs() < 1e-6);
+		assert!((clearing.volume - 7.5).abs() < 1e-6);
+		assert_eq!(clearing.bid_fills.len(), 2);
+		assert!((clearing.bid_fills[0].1 - 3.75).abs() < 1e-6);
+		assert!((trades.iter().map(|t| t.quantity).sum::<f64>() - 7.5).abs() < 1e-6);
+	}
+
+	#[test]
+	fn test_step_rationing() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		bids.flows.add_order(flow("b1", TradeType::Bid, FlowSchedule::new(100.0, 100.0, 10.0), 10.0));
+		asks.flows.add_order(flow("a1", TradeType::Ask, FlowSchedule::new(95.0, 95.0, 4.0), 4.0));
+		asks.flows.add_order(flow("a2", TradeType::Ask, FlowSchedule::new(95.0, 95.0, 4.0), 4.0));
+
+		// Supply of 8 is short of the demand of 10, so the sellers exhaust and the bid is rationed
+		let (clearing, _) = FlowAuction::clear(Arc::clone(&bids), Arc::clone(&asks)).unwrap();
+		assert!((clearing.volume - 8.0).abs() < 1e-6);
+		assert!(asks.flows.is_empty());
+		assert_eq!(bids.flows.len(), 1);
+		assert!((bids.flows.orders.lock().unwrap()[0].quantity - 2.0).abs() < 1e-6);
+		assert!(FlowAuction::clear(Arc::clone(&bids), Arc::clone(&asks)).is_none());
+	}
+}
@@ -0,0 +1,84 @@
+use crate::order::{Order, TradeType};
+
+use std::sync::Mutex;
+
+/// The book of flow orders waiting for the next flow market batch. Unlike the
+/// CDA Book the orders aren't sorted by price since every order with a schedule
+/// overlapping the clearing price trades in each batch.
+/// book_type: TradeType{Bid, Ask} -> Side of the flow orders held in this book
+/// orders: Mutex<Vec<Order>> -> Threadsafe vector of flow orders in arrival order
+pub struct FlowBook {
+	pub book_type: TradeType,
+	pub orders: Mutex<Vec<Order>>,
+}
+
+impl FlowBook {
+	pub fn new(book_type: TradeType) -> FlowBook {
+		FlowBook {
+			book_type,
+			orders: Mutex::new(Vec::<Order>::new()),
+		}
+	}
+
+	/// Adds a new flow order to the book
+	pub fn add_order(&self, order: Order) {
+		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock flow book to add order");
+		orders.push(order);
+	}
+
+	/// Cancels the existing flow order in the book if it exists
+	pub fn cancel_order_by_id(&self, id: &str) -> Result<(), &'static str> {
+		let mut orders = self.orders.lock().expect("couldn't acquire lock cancelling flow order");
+		match orders.iter().position(|o| o.trader_id == id) {
+			Some(i) => {
+				orders.remove(i);
+				Ok(())
+			},
+			None => Err("ERROR: flow order not found to cancel"),
+		}
+	}
+
+	/// Removes every flow order matching the predicate from the book
+	pub fn remove_orders<F>(&self, pred: F) -> Vec<Order> 
+	where F: Fn(&Order) -> bool
+	{
+		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock flow book to remove orders");
+		let (removed, rest): (Vec<Order>, Vec<Order>) = orders.drain(..).partition(|o| pred(o));
+		*orders = rest;
+		removed
+	}
+
+	/// Returns the lowest p_low and highest p_high of the book's schedules
+	pub fn price_bounds(&self) -> Option<(f64, f64)> {
+		let orders = self.orders.lock().unwrap();
+		orders.iter().filter_map(|o| o.flow()).fold(None, |bounds, s| match bounds {
+			None => Some((s.p_low, s.p_high)),
+			Some((low, high)) => Some((s.p_low.min(low), s.p_high.max(high))),
+		})
+	}
+
+	/// The total quantity the book's orders trade per batch at price. Each order
+	/// is limited by its remaining quantity.
+	pub fn aggregate_rate(&self, price: f64) -> f64 {
+		let orders = self.orders.lock().unwrap();
+		orders.iter().map(|o| FlowBook::order_rate(o, price)).sum()
+	}
+
+	/// The quantity a single flow order trades per batch at price
+	pub fn order_rate(order: &Order, price: f64) -> f64 {
+		match order.flow() {
+			Some(schedule) => schedule.rate(&order.trade_type, price).min(order.quantity),
+			None => 0.0,
+		}
+	}
+
+	/// Utility to see depth of flow book
+	pub fn len(&self) -> usize {
+		self.orders.lock().unwrap().len()
+	}
+
+	/// Utility to check if the flow book has no orders
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
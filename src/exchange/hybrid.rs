@@ -0,0 +1,154 @@
+use crate::exchange::order_book::Book;
+use crate::exchange::amm::Pool;
+use crate::exchange::trade::Trade;
+use crate::order::{Order, TradeType};
+
+use std::sync::Arc;
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// Routes orders between the order book and an AMM pool. In hybrid mode the same Pool
+/// is attached to both Books: an incoming order fills from the pool while the curve's
+/// price beats the opposite Book's best price, then from the Book, alternating until
+/// its limit price is reached. Resting orders the pool's price moves through are filled
+/// by the pool so the two sources never stay crossed.
+pub struct HybridRouter {}
+
+impl HybridRouter {
+	/// Attaches the pool to both Books and fills any resting orders it already crosses
+	pub fn attach(bids: &Book, asks: &Book, pool: Arc<Pool>) -> Vec<Trade> {
+		bids.set_pool(Some(Arc::clone(&pool)));
+		asks.set_pool(Some(pool));
+		HybridRouter::arbitrage(bids, asks)
+	}
+
+	/// Detaches the pool, returning the Books to a plain CLOB
+	pub fn detach(bids: &Book, asks: &Book) {
+		bids.set_pool(None);
+		asks.set_pool(None);
+	}
+
+	/// Fills the incoming order from the pool attached to the opposite Book while the pool
+	/// is strictly cheaper than that Book's best price and within the order's limit. Called
+	/// by the crossing functions before each fill from the Book.
+	pub fn route(opposite: &Book, order: &mut Order) -> Option<Trade> {
+		let pool = opposite.get_pool()?;
+		let (bound, better) = match order.trade_type {
+			TradeType::Bid => {
+				let bound = order.price.min(opposite.get_min_price());
+				(bound, pool.buy_price() < bound)
+			},
+			TradeType::Ask => {
+				let bound = order.price.max(opposite.get_max_price());
+				(bound, pool.sell_price() > bound)
+			},
+		};
+		if !better {
+			return None;
+		}
+
+		let trade = pool.swap_within(&order.trader_id, &order.trade_type, bound, order.quantity)?;
+		log_event!("New {:?}:{} transacted {} shares with pool:{} @{}",
+				order.trade_type, order.trader_id, trade.quantity, pool.pool_id, trade.price);
+		order.quantity -= trade.quantity;
+		Some(trade)
+	}
+
+	/// Fills resting orders priced through the pool's price from the pool, best first,
+	/// until the Books and the pool no longer cross. Returns the fills.
+	pub fn arbitrage(bids: &Book, asks: &Book) -> Vec<Trade> {
+		let pool = match bids.get_pool() {
+			Some(pool) => pool,
+			None => return Vec::new(),
+		};
+		// Filling one side moves the pool's price back toward the other, so repeat until
+		// a pass makes no fills
+		let mut trades = Vec::<Trade>::new();
+		loop {
+			let filled = trades.len();
+			HybridRouter::fill_crossed(bids, &pool, &mut trades);
+			HybridRouter::fill_crossed(asks, &pool, &mut trades);
+			if trades.len() == filled {
+				return trades;
+			}
+		}
+	}
+
+	// Fills the Book's resting orders from the pool while the best one crosses its price
+	fn fill_crossed(book: &Book, pool: &Pool, trades: &mut Vec<Trade>) {
+		loop {
+			let crosses = match (book.peek_best_price(), &book.book_type) {
+				(Some(price), TradeType::Bid) => price > pool.buy_price(),
+				(Some(price), TradeType::Ask) => price < pool.sell_price(),
+				(None, _) => false,
+			};
+			if !crosses {
+				break;
+			}
+
+			let mut order = book.pop_from_end().expect("Book emptied while arbitraging");
+			let mut trade = match pool.swap_within(&order.trader_id, &order.trade_type, order.price, order.quantity) {
+				Some(trade) => trade,
+				None => {
+					book.push_to_end(order).expect("couldn't push");
+					break;
+				}
+			};
+			// The pool came to the resting order, so there is no incoming aggressor
+			trade.aggressor = None;
+			order.quantity -= trade.quantity;
+			trades.push(trade);
+			if order.quantity > EPSILON {
+				book.push_to_end(order).expect("couldn't push");
+				break;
+			}
+		}
+		book.refresh_touch();
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::exchange::auction::Auction;
+	use crate::order::OrderType;
+
+	#[test]
+	fn test_split_between_book_and_pool() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		let pool = Arc::new(Pool::new(String::from("amm"), 100.0, 10_000.0, 0.0).unwrap());
+		assert!(HybridRouter::attach(&bids, &asks, Arc::clone(&pool)).is_empty());
+		asks.add_order(Order::new(String::from("a1"), OrderType::Enter, TradeType::Ask, 101.0, 1.0)).unwrap();
+		asks.add_order(Order::new(String::from("a2"), OrderType::Enter, TradeType::Ask, 103.0, 1.0)).unwrap();
+
+		// The pool fills up to 101, then a1, then the pool up to the 102 limit and the rest rests
+		let bid = Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 102.0, 5.0);
+		let trades = Auction::calc_bid_crossing(Arc::clone(&bids), Arc::clone(&asks), bid);
+		let sources: Vec<&str> = trades.iter().map(|t| t.ask_id.as_str()).collect();
+		assert_eq!(sources, ["amm", "a1", "amm"]);
+		assert!((pool.buy_price() - 102.0).abs() < 1e-6);
+		assert_eq!(asks.len(), 1);
+		let filled: f64 = trades.iter().map(|t| t.quantity).sum();
+		assert!((bids.orders.lock().unwrap()[0].quantity - (5.0 - filled)).abs() < 1e-9);
+	}
+
+	#[test]
+	fn test_arbitrage_on_attach() {
+		let bids = Book::new(TradeType::Bid);
+		let asks = Book::new(TradeType::Ask);
+		bids.add_order(Order::new(String::from("b"), OrderType::Enter, TradeType::Bid, 105.0, 50.0)).unwrap();
+		asks.add_order(Order::new(String::from("a"), OrderType::Enter, TradeType::Ask, 99.0, 0.1)).unwrap();
+
+		// The pool sells to the bid until its price reaches 105, buys the 0.1 ask and then
+		// sells to the bid again
+		let pool = Arc::new(Pool::new(String::from("amm"), 100.0, 10_000.0, 0.0).unwrap());
+		let trades = HybridRouter::attach(&bids, &asks, Arc::clone(&pool));
+		assert_eq!(trades.len(), 3);
+		assert!(trades[0].quantity < 50.0);
+		assert!((bids.get_max_price() - 105.0).abs() < 1e-9);
+		assert!(asks.is_empty());
+		assert!(pool.buy_price() >= bids.get_max_price() - 1e-6);
+	}
+}
@@ -1,5 +1,21 @@
 pub mod order_book;
 pub mod auction;
+pub mod allocation;
 pub mod order_processing;
 pub mod queue;
 pub mod queue_processing;
+pub mod stop_book;
+pub mod peg_processing;
+pub mod expiry;
+pub mod circuit_breaker;
+pub mod schedule;
+pub mod flow_book;
+pub mod flow_auction;
+pub mod commit_reveal;
+pub mod block_producer;
+pub mod amm;
+pub mod hybrid;
+pub mod package_auction;
+pub mod rfq;
+pub mod dark_pool;
+pub mod trade;
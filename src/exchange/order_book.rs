@@ -1,81 +1,98 @@
-use core::f64::MAX;
 use crate::order::{Order, TradeType};
-
-use std::sync::Mutex;
+use crate::exchange::stop_book::StopBook;
+use crate::exchange::flow_book::FlowBook;
+use crate::exchange::auction::{MAX_PRICE, MIN_PRICE};
+use crate::exchange::circuit_breaker::PriceBand;
+use crate::exchange::allocation::{AllocationPolicy, StrictTime};
+use crate::exchange::amm::Pool;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Arc};
 use std::io;
 
 pub fn test_order_book_mod() {
-	println!("Hello, order_book!");
+	log_event!("Hello, order_book!");
 }
 
 /// The struct for the order books in the exchange. The purpose
 /// is to keep track of bids and asks for calculating order crossings.
 /// book_type: TradeType{Bid, Ask} -> To differentiate the two order books
-/// orders: Mutex<Vec<Order>> -> Threadsafe vector to keep track of orders
+/// orders: Mutex<Vec<Box<Order>>> -> Threadsafe vector to keep track of orders, boxed so keeping it
+/// sorted moves pointers instead of whole orders
 /// min_price: Mutex<f64> -> Threadsafe minimum market price for computing clearing price
 /// max_price: Mutex<f64> -> Threadsafe maximum market price for computing clearing price
+/// stops: StopBook -> Trigger book holding this side's Stop and StopLimit orders
+/// flows: FlowBook -> Book holding this side's Flow orders until the next flow batch
+/// touch_changed: Mutex<bool> -> Set when the best price moves so pegged orders get repriced
+/// band: Mutex<Option<PriceBand>> -> Static price band new orders must be priced within
+/// allocation: Mutex<Arc<dyn AllocationPolicy>> -> Rations this side's marginal price level in batch auctions
+/// pool: Mutex<Option<Arc<Pool>>> -> AMM pool sharing the order flow in hybrid mode
+/// ids: Mutex<HashMap<String, Vec<f64>>> -> Prices each trader_id's orders entered the Book at,
+/// so they are found by searching their price level instead of the whole Book. Orders must
+/// enter the Book through its methods to be found by trader_id. Entries of orders that have
+/// since left the Book are dropped when a lookup misses them or the index is rebuilt.
+/// has_pegs: Mutex<bool> -> Set when a pegged order enters the Book, cleared once take_pegged finds none
 pub struct Book {
 	pub book_type: TradeType,
-	pub orders: Mutex<Vec<Order>>,
+	pub orders: Mutex<Vec<Box<Order>>>,
 	pub min_price: Mutex<f64>,
 	pub max_price: Mutex<f64>,
+	pub stops: StopBook,
+	pub flows: FlowBook,
+	pub touch_changed: Mutex<bool>,
+	pub band: Mutex<Option<PriceBand>>,
+	pub allocation: Mutex<Arc<dyn AllocationPolicy>>,
+	pub pool: Mutex<Option<Arc<Pool>>>,
+	ids: Mutex<HashMap<String, Vec<f64>>>,
+	has_pegs: Mutex<bool>,
 }
 
 impl Book {
     pub fn new(book_type: TradeType) -> Book {
     	Book {
+    		stops: StopBook::new(book_type.clone()),
+    		flows: FlowBook::new(book_type.clone()),
     		book_type,
-    		orders: Mutex::new(Vec::<Order>::new()),
-    		min_price: Mutex::new(MAX),
+    		orders: Mutex::new(Vec::new()),
+    		min_price: Mutex::new(f64::MAX),
     		max_price: Mutex::new(0.0),
+    		touch_changed: Mutex::new(false),
+    		band: Mutex::new(None),
+    		allocation: Mutex::new(Arc::new(StrictTime{})),
+    		pool: Mutex::new(None),
+    		ids: Mutex::new(HashMap::new()),
+    		has_pegs: Mutex::new(false),
     	}
     }
 
-    /// Adds a new order to the Book after acquiring a lock, then sorts by price
+    /// Adds a new order to the Book after acquiring a lock, in price order
     pub fn add_order(&self, order: Order) -> io::Result<()> {
     	let mut orders = self.orders.lock().expect("ERROR: Couldn't lock book to update order");
-    	match order.trade_type {
-			// Sort bids in descending order -> best bid (highest price) at end
-			TradeType::Bid => {
-				orders.push(order);
-				orders.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
-				// Update best price once book is sorted
-				let best_price = orders.last().unwrap().price;
-				self.update_best_price(best_price);
-			},
-			// Sort asks in ascending order -> best ask (lowest price) at end
-			TradeType::Ask => {
-				orders.push(order);
-				// Reverse a and b to get in ascending order
-    			orders.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap().reverse());
-				// Update best price once book is sorted
-				let best_price = orders.last().unwrap().price;
-				self.update_best_price(best_price);
-			}
-		}
+    	// The Book is already sorted, so insert where a stable sort would leave the order:
+    	// behind every order with the same price
+    	let i = match order.trade_type {
+			// Bids in ascending order -> best bid (highest price) at end
+			TradeType::Bid => orders.partition_point(|o| o.price <= order.price),
+			// Asks in descending order -> best ask (lowest price) at end
+			TradeType::Ask => orders.partition_point(|o| o.price >= order.price),
+		};
+		self.index_order(&orders, &order);
+		orders.insert(i, Box::new(order));
+		// Update best price once the order is in place
+		let best_price = orders.last().unwrap().price;
+		self.update_best_price(best_price);
 		
     	Ok(())
     }
 
-    /// Replaces the order in the order book with the supplied 'order' of the same trader_id
+    /// Replaces the order in the order book with the supplied 'order' of the same trader_id.
+    /// The new order enters at its own price level so the Book stays sorted.
     pub fn update_order(&self, order: Order) -> Result<(), &'static str> {
-    	// Acquire the lock
-        let mut orders = self.orders.lock().expect("ERROR: Couldn't lock book to update order");
-        // Search for existing order's index
-        let order_index = orders.iter().position(|o| o.trader_id == order.trader_id);
-
-        if let Some(i) = order_index {
-        	// Add new order to end of the vector
-        	orders.push(order);
-    		// Swap orders then pop off the old order that is now at the end of vector
-        	let last = orders.len() - 1;
-        	orders.swap(i, last);
-        	orders.pop();
-        } else {
-        	println!("ERROR: order not found to update: {:?}", &order.trader_id);
+    	if self.cancel_order_by_id(&order.trader_id).is_err() {
+        	log_event!("ERROR: order not found to update: {:?}", &order.trader_id);
         	return Err("ERROR: order not found to update");
         }
-
+        self.add_order(order).expect("Failed to add order");
         Ok(())
     }
 
@@ -84,18 +101,17 @@ impl Book {
     	// Acquire the lock
         let mut orders = self.orders.lock().expect("couldn't acquire lock cancelling order");
         // Search for existing order's index
-        let order_index: Option<usize> = orders.iter().position(|o| &o.trader_id == &order.trader_id);
+        let order_index: Option<usize> = self.position(&orders, &order.trader_id);
 
         if let Some(i) = order_index {
-        	orders.remove(i);
+        	self.unindex_order(&orders.remove(i));
         } else {
-        	println!("ERROR: order not found to cancel: {:?}", &order.trader_id);
+        	log_event!("ERROR: order not found to cancel: {:?}", &order.trader_id);
         	return Err("ERROR: order not found to cancel");
         }
 
 		// Update the best price 
-		let best_price = orders.last().unwrap().price;
-		self.update_best_price(best_price);
+		self.refresh_best_price(&orders);
 
         Ok(())
     }
@@ -104,18 +120,17 @@ impl Book {
 		// Acquire the lock
         let mut orders = self.orders.lock().expect("couldn't acquire lock cancelling order");
         // Search for existing order's index
-        let order_index: Option<usize> = orders.iter().position(|o| &o.trader_id == &id);
+        let order_index: Option<usize> = self.position(&orders, id);
 
 		if let Some(i) = order_index {
-        	orders.remove(i);
+        	self.unindex_order(&orders.remove(i));
         } else {
-        	println!("ERROR: order not found to cancel: {:?}", id);
+        	log_event!("ERROR: order not found to cancel: {:?}", id);
         	return Err("ERROR: order not found to cancel");
         }
 
 		// Update the best price 
-		let best_price = orders.last().unwrap().price;
-		self.update_best_price(best_price);
+		self.refresh_best_price(&orders);
 
         Ok(())
 	}
@@ -123,53 +138,208 @@ impl Book {
 	// Pushes best bid/ask to end of sorted book
 	pub fn push_to_end(&self, order: Order) -> io::Result<()> {
 		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock book to update order");
-    	orders.push(order);
+		self.index_order(&orders, &order);
+    	orders.push(Box::new(order));
 		Ok(())
 	}
 
 	// Pops best bid/ask from end of sorted book
 	pub fn pop_from_end(&self) -> Option<Order> {
 		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock book to update order");
-    	if orders.len() > 0 {
-			let order = orders.pop();
-			return order;
-		} 
-		return None
+		orders.pop().map(|o| *o)
 	}
 
     pub fn peek_id_pos(&self, trader_id: String) -> Option<usize> {
     	// Acquire the lock
         let orders = self.orders.lock().unwrap();
         // Search for existing order's index
-        orders.iter().position(|o| o.trader_id == trader_id)
+        self.position(&orders, &trader_id)
     }
 
+	// Finds the first order of the trader_id by searching the price levels it entered the
+	// Book at. Forgets the trader_id if none of its orders are still resting.
+	fn position(&self, orders: &[Box<Order>], trader_id: &str) -> Option<usize> {
+		let mut ids = self.ids.lock().unwrap();
+		let found = ids.get(trader_id)?.iter()
+			.filter_map(|price| {
+				let (start, end) = self.level(orders, *price);
+				orders[start..end].iter().position(|o| o.trader_id == trader_id).map(|i| start + i)
+			})
+			.min();
+		if found.is_none() {
+			ids.remove(trader_id);
+		}
+		found
+	}
+
+	// The range of the sorted orders resting at price
+	fn level(&self, orders: &[Box<Order>], price: f64) -> (usize, usize) {
+		match self.book_type {
+			TradeType::Bid => (orders.partition_point(|o| o.price < price), orders.partition_point(|o| o.price <= price)),
+			TradeType::Ask => (orders.partition_point(|o| o.price > price), orders.partition_point(|o| o.price >= price)),
+		}
+	}
+
+	// Records the price level the order is entering the Book at. Orders that left the Book
+	// without being cancelled leave their entries behind, so the index is rebuilt from the
+	// resting orders once it has grown to twice their number.
+	fn index_order(&self, orders: &[Box<Order>], order: &Order) {
+		let mut ids = self.ids.lock().unwrap();
+		if ids.len() > 2 * orders.len() + 64 {
+			ids.clear();
+			for o in orders {
+				Book::add_price(&mut ids, o);
+			}
+		}
+		Book::add_price(&mut ids, order);
+		if order.peg().is_some() {
+			*self.has_pegs.lock().unwrap() = true;
+		}
+	}
+
+	fn add_price(ids: &mut HashMap<String, Vec<f64>>, order: &Order) {
+		match ids.get_mut(&order.trader_id) {
+			Some(prices) if !prices.contains(&order.price) => prices.push(order.price),
+			Some(_) => {},
+			None => {
+				ids.insert(order.trader_id.clone(), vec![order.price]);
+			},
+		}
+	}
+
+	// Forgets the price level of an order cancelled out of the Book
+	fn unindex_order(&self, order: &Order) {
+		let mut ids = self.ids.lock().unwrap();
+		if let Some(prices) = ids.get_mut(&order.trader_id) {
+			prices.retain(|p| *p != order.price);
+			if prices.is_empty() {
+				ids.remove(&order.trader_id);
+			}
+		}
+	}
+
     /// Utility to see depth of order book
     pub fn len(&self) -> usize {
     	let orders = self.orders.lock().unwrap();
     	orders.len()
     }
 
-	/// Atomically updates Book's best bid/ask
+    /// Utility to check if the order book has no resting orders
+    pub fn is_empty(&self) -> bool {
+    	self.len() == 0
+    }
+
+	/// Sets or clears the static price band new orders are checked against
+	pub fn set_band(&self, band: Option<PriceBand>) {
+		*self.band.lock().unwrap() = band;
+	}
+
+	/// Checks the order against the price band if one is set
+	pub fn check_band(&self, order: &mut Order) -> Result<(), &'static str> {
+		match *self.band.lock().unwrap() {
+			Some(ref band) => band.check(order),
+			None => Ok(()),
+		}
+	}
+
+	/// Sets the policy rationing the marginal price level when this Book is uncrossed
+	pub fn set_allocation(&self, policy: Arc<dyn AllocationPolicy>) {
+		*self.allocation.lock().unwrap() = policy;
+	}
+
+	/// Returns the Book's current allocation policy
+	pub fn get_allocation(&self) -> Arc<dyn AllocationPolicy> {
+		Arc::clone(&self.allocation.lock().unwrap())
+	}
+
+	/// Sets or clears the AMM pool incoming orders are routed to in hybrid mode
+	pub fn set_pool(&self, pool: Option<Arc<Pool>>) {
+		*self.pool.lock().unwrap() = pool;
+	}
+
+	/// Returns the attached AMM pool if the Book is in hybrid mode
+	pub fn get_pool(&self) -> Option<Arc<Pool>> {
+		self.pool.lock().unwrap().clone()
+	}
+
+	/// Atomically updates Book's best bid/ask, flagging the touch as changed if it moved
 	pub fn update_best_price(&self, price: f64) {
-		match self.book_type {
-			TradeType::Bid => {
-				let mut max_p = self.max_price.lock().unwrap();
-				*max_p = price;
-			},
-			TradeType::Ask => {
-				let mut min_p = self.min_price.lock().unwrap();
-				*min_p = price;
-			}
+		let mut best_p = match self.book_type {
+			TradeType::Bid => self.max_price.lock().unwrap(),
+			TradeType::Ask => self.min_price.lock().unwrap(),
+		};
+		if *best_p != price {
+			*self.touch_changed.lock().unwrap() = true;
 		}
+		*best_p = price;
 	}
 
-	pub fn peek_best_price(&self) -> Option<f64> {
+	/// Returns whether the touch moved since the last call and resets the flag
+	pub fn take_touch_changed(&self) -> bool {
+		let mut changed = self.touch_changed.lock().unwrap();
+		let was_changed = *changed;
+		*changed = false;
+		was_changed
+	}
+
+	/// Returns the best price among the non-pegged orders in the Book. Pegged orders
+	/// are excluded so they never reference their own price.
+	pub fn best_lit_price(&self) -> Option<f64> {
 		let orders = self.orders.lock().unwrap();
-		if orders.len() > 0 {
-			return Some(orders.last().expect("Couldn't peek best price").price);
+		orders.iter().rev().find(|o| o.peg().is_none()).map(|o| o.price)
+	}
+
+	/// Removes every pegged order from the Book, preserving their relative order
+	pub fn take_pegged(&self) -> Vec<Order> {
+		let pegged = self.remove_orders(|o| o.peg().is_some());
+		// Pegs re-entering the Book set the flag again
+		*self.has_pegs.lock().unwrap() = false;
+		pegged
+	}
+
+	/// Whether a pegged order may be resting in the Book. Only false once take_pegged
+	/// found none and no pegged order has entered since.
+	pub fn has_pegs(&self) -> bool {
+		*self.has_pegs.lock().unwrap()
+	}
+
+	/// Removes every order matching the predicate from the Book, preserving the
+	/// relative order of both the removed and the remaining orders
+	pub fn remove_orders<F>(&self, pred: F) -> Vec<Order> 
+	where F: Fn(&Order) -> bool
+	{
+		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock book to remove orders");
+		// Most calls match nothing, so avoid rebuilding the Book for them
+		if !orders.iter().any(|o| pred(o)) {
+			self.refresh_best_price(&orders);
+			return Vec::new();
 		}
-		None
+		let (removed, rest): (Vec<Box<Order>>, Vec<Box<Order>>) = orders.drain(..).partition(|o| pred(o));
+		*orders = rest;
+		self.refresh_best_price(&orders);
+		removed.into_iter().map(|o| *o).collect()
+	}
+
+	/// Recalculates the best price after orders were popped from or pushed to the Book
+	pub fn refresh_touch(&self) {
+		let orders = self.orders.lock().unwrap();
+		self.refresh_best_price(&orders);
+	}
+
+	/// Sets the best price from the end of the sorted orders, resetting the touch
+	/// to the default if the Book is now empty
+	fn refresh_best_price(&self, orders: &[Box<Order>]) {
+		let best_price = match (orders.last(), &self.book_type) {
+			(Some(order), _) => order.price,
+			(None, TradeType::Bid) => MIN_PRICE,
+			(None, TradeType::Ask) => MAX_PRICE,
+		};
+		self.update_best_price(best_price);
+	}
+
+	pub fn peek_best_price(&self) -> Option<f64> {
+		let orders = self.orders.lock().unwrap();
+		orders.last().map(|o| o.price)
 	}
 
     /// Atomically updates the Book's max price
@@ -191,13 +361,13 @@ impl Book {
     /// Returns the Book's min price
     pub fn get_min_price(&self) -> f64 {
     	let price = self.min_price.lock().expect("Error getting min price");
-    	price.clone() as f64
+    	*price
     }
 
     /// Returns the Book's max price
     pub fn get_max_price(&self) -> f64 {
     	let price = self.max_price.lock().expect("Error getting max price");
-    	price.clone() as f64
+    	*price
     }
 
     /// Finds a new maximum Book price in the event that the previous was
@@ -219,7 +389,7 @@ impl Book {
     	let orders = self.orders.lock().unwrap();
 
     	// Iterates over all orders until a minimum is found
-    	let new_min = orders.iter().fold(MAX, |min, order| if order.price < min {order.price} else {min});
+    	let new_min = orders.iter().fold(f64::MAX, |min, order| if order.price < min {order.price} else {min});
 
     	// Update the book with new min price
     	let mut min_price = self.min_price.lock().unwrap();
@@ -239,7 +409,7 @@ mod tests {
 	fn test_new_book() {
 		let book = Book::new(TradeType::Bid);
 		assert_eq!(book.book_type, TradeType::Bid);
-		assert_eq!(*book.min_price.lock().unwrap(), MAX);
+		assert_eq!(*book.min_price.lock().unwrap(), f64::MAX);
 		assert_eq!(*book.max_price.lock().unwrap(), 0.0);
 	}
 
@@ -1,9 +1,12 @@
 use tokio::net::tcp::TcpStream;
-use crate::order::{Order, OrderType, TradeType};
+use crate::order::{Order, OrderType, TradeType, ExecType, Peg, PegType, FlowSchedule, TimeInForce};
 use crate::exchange::queue::Queue;
+use crate::exchange::package_auction::{Leg, PackageOrder};
+use crate::utility::get_time;
 
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 use std::thread::JoinHandle;
 
 extern crate serde;
@@ -42,6 +45,58 @@ pub struct JsonOrder{
     trade_type: String,  
     price: f64,              
     quantity: f64, 
+    // Optional "limit", "stop", "stop_limit", "pegged" or "flow", defaults to "limit"
+    exec_type: Option<String>,
+    // Trigger price required by stop and stop_limit orders
+    trigger: Option<f64>,
+    // "primary", "market" or "midpoint" reference required by pegged orders
+    peg: Option<String>,
+    // Optional offset and cap of pegged orders
+    offset: Option<f64>,
+    cap: Option<f64>,
+    // Piecewise linear schedule required by flow orders
+    p_low: Option<f64>,
+    p_high: Option<f64>,
+    u_max: Option<f64>,
+    // Optional "gtc", "gtt" or "day", defaults to "gtc"
+    time_in_force: Option<String>,
+    // Expiry in milliseconds since UNIX_EPOCH required by gtt orders
+    expire_time: Option<u64>,
+    // Optional fee bid for priority in block producer mode, defaults to 0
+    gas_price: Option<f64>,
+}
+
+// The commitment message of a sealed-bid round
+#[derive(Deserialize, Debug)]
+pub struct JsonCommit {
+	trader_id: String,
+	// Hex SHA-256 digest of the order's commitment
+	commitment: String,
+}
+
+// The request-for-quote messages. register_maker needs only the trader_id, rfq the
+// trade_type and quantity, quote the request_id and price and accept the request_id
+// and maker_id.
+#[derive(Deserialize, Debug)]
+pub struct JsonRfq {
+	trader_id: String,
+	order_type: String,
+	trade_type: Option<String>,
+	quantity: Option<f64>,
+	request_id: Option<u64>,
+	price: Option<f64>,
+	maker_id: Option<String>,
+}
+
+// A package order's message, cancel_package needing only the trader_id
+#[derive(Deserialize, Debug)]
+pub struct JsonPackage {
+	trader_id: String,
+	order_type: String,
+	// Instruments and quantities, positive to buy and negative to sell
+	legs: Option<Vec<Leg>>,
+	// Most the trader will pay for the whole package, negative if it must be paid
+	limit: Option<f64>,
 }
 
 impl JsonOrder {
@@ -50,9 +105,7 @@ impl JsonOrder {
 	    let length_delimited = FramedWrite::new(socket, LengthDelimitedCodec::new());
 
 	    // Serialize frames
-	    let serializer = WriteJson::new(length_delimited);
-
-	    serializer
+	    WriteJson::new(length_delimited)
 	}
 
 	pub fn deserialize(socket: TcpStream) ->  DeserializedStream {
@@ -60,12 +113,30 @@ impl JsonOrder {
 	    let length_delimited = FramedRead::new(socket, LengthDelimitedCodec::new());
 
 	    // Deserialize frames
-	    let deserialized = ReadJson::<_, Value>::new(length_delimited);
-
-	    deserialized
+	    ReadJson::<_, Value>::new(length_delimited)
 	}
-	// Deserialize the JSON, create an Order type, and push onto the queue
+	// Deserialize the JSON, create an Order type, and push onto the queue.
+	// Commit and reveal messages of sealed-bid rounds go to the Queue's CommitStore,
+	// request-for-quote messages to its RfqDesk, dark orders to its DarkPool and package
+	// orders to its PackageBook.
 	pub fn process_new(msg: serde_json::Value, queue: Arc<Queue>) {
+		match msg["order_type"].as_str().map(|ot| ot.to_lowercase()).as_deref() {
+			Some("commit") => return JsonOrder::process_commit(msg, &queue),
+			Some("reveal") => return JsonOrder::process_reveal(msg, &queue),
+			Some("register_maker") | Some("rfq") | Some("quote") | Some("accept") => return JsonOrder::process_rfq(msg, &queue),
+			Some("package") | Some("cancel_package") => return JsonOrder::process_package(msg, &queue),
+			_ => {},
+		}
+
+		// Orders with venue "dark" rest in the dark pool with an optional min_qty instead
+		if msg["venue"].as_str().map(|v| v.to_lowercase()).as_deref() == Some("dark") {
+			let min_qty = msg["min_qty"].as_f64();
+			return match JsonOrder::order_from_json(msg) {
+				Some(order) => queue.dark.add_order(order, min_qty),
+				None => log_event!("Unsuccessful json parsing"),
+			};
+		}
+
 		// create Order from JSON
 		let order = JsonOrder::order_from_json(msg);
 
@@ -74,20 +145,114 @@ impl JsonOrder {
 			let handle = OrderProcessor::conc_recv_order(o, Arc::clone(&queue));
 			handle.join().unwrap();
 		} else {
-			println!("Unsuccessful json parsing");
+			log_event!("Unsuccessful json parsing");
+		}
+	}
+
+	// Store a sealed order's commitment, sent as a hex string of CommitStore::commitment
+	fn process_commit(msg: serde_json::Value, queue: &Queue) {
+		let typed_json: JsonCommit = match serde_json::from_value(msg) {
+			Ok(c) => c,
+			Err(_) => return log_event!("Unsuccessful commit parsing"),
+		};
+		if let Err(e) = queue.commits.commit(typed_json.trader_id, typed_json.commitment) {
+			log_event!("{}", e);
+		}
+	}
+
+	// Verify a revealed order, which is the committed order's JSON with order_type
+	// "reveal" and the secret nonce. The order itself is entered as an Enter order.
+	fn process_reveal(mut msg: serde_json::Value, queue: &Queue) {
+		let nonce = match msg["nonce"].as_u64() {
+			Some(nonce) => nonce,
+			None => return log_event!("Reveal is missing its nonce"),
+		};
+		msg["order_type"] = json!("enter");
+		match JsonOrder::order_from_json(msg) {
+			Some(order) => {
+				if let Err(e) = queue.commits.reveal(order, nonce) {
+					log_event!("{}", e);
+				}
+			},
+			None => log_event!("Unsuccessful reveal parsing"),
+		}
+	}
+
+	// Pass a request-for-quote message to the desk, which routes the resulting quote
+	// requests, quotes and fills to the sessions' outboxes
+	fn process_rfq(msg: serde_json::Value, queue: &Queue) {
+		let typed_json: JsonRfq = match serde_json::from_value(msg) {
+			Ok(r) => r,
+			Err(_) => return log_event!("Unsuccessful rfq parsing"),
+		};
+		let desk = &queue.rfq;
+		let result = match (typed_json.order_type.to_lowercase().as_ref(), typed_json.request_id) {
+			("register_maker", _) => {
+				desk.register_maker(typed_json.trader_id);
+				Ok(())
+			},
+			("rfq", _) => {
+				let tt = match typed_json.trade_type.as_ref().map(|tt| tt.to_lowercase()).as_deref() {
+					Some("bid") => TradeType::Bid,
+					Some("ask") => TradeType::Ask,
+					_ => return log_event!("Entered an invalid tradetype!"),
+				};
+				match typed_json.quantity {
+					Some(quantity) if quantity > 0.0 => {
+						desk.request(typed_json.trader_id, tt, quantity, get_time());
+						Ok(())
+					},
+					_ => Err("ERROR: quote requests need a positive quantity"),
+				}
+			},
+			("quote", Some(request_id)) => match typed_json.price {
+				Some(price) => desk.quote(typed_json.trader_id, request_id, price, get_time()),
+				None => Err("ERROR: quotes need a price"),
+			},
+			("accept", Some(request_id)) => match typed_json.maker_id {
+				Some(maker_id) => desk.accept(&typed_json.trader_id, request_id, &maker_id, get_time()).map(|_| ()),
+				None => Err("ERROR: accepting needs the quote's maker_id"),
+			},
+			_ => Err("ERROR: quotes and accepts need a request_id"),
+		};
+		if let Err(e) = result {
+			log_event!("{}", e);
+		}
+	}
+
+	// Rest a package order in the PackageBook until the next package auction, or cancel it
+	fn process_package(msg: serde_json::Value, queue: &Queue) {
+		let typed_json: JsonPackage = match serde_json::from_value(msg) {
+			Ok(p) => p,
+			Err(_) => return log_event!("Unsuccessful package parsing"),
+		};
+		let book = &queue.packages;
+		let result = match (typed_json.order_type.to_lowercase().as_ref(), typed_json.legs, typed_json.limit) {
+			("cancel_package", _, _) => book.cancel_order_by_id(&typed_json.trader_id),
+			(_, Some(legs), Some(limit)) => book.add_order(PackageOrder::new(typed_json.trader_id, legs, limit)),
+			_ => Err("ERROR: packages need legs and a limit"),
+		};
+		if let Err(e) = result {
+			log_event!("{}", e);
 		}
 	}
 
 	// Make an Order from a JSON
 	fn order_from_json(msg: serde_json::Value) -> Option<Order> {
-		let typed_json: JsonOrder = serde_json::from_value(msg).expect("Couldn't make JSON");
+		let typed_json: JsonOrder = match serde_json::from_value(msg) {
+			Ok(o) => o,
+			Err(e) => {
+				log_event!("Unsuccessful order parsing: {}", e);
+				return None;
+			},
+		};
 		// Parse JSON body into enums compatible with flow market
 		let ot = match typed_json.order_type.to_lowercase().as_ref() {
 			"enter" => OrderType::Enter,
 			"update" => OrderType::Update,
 			"cancel" => OrderType::Cancel,
 			_ => {
-				println!("Entered an invalid ordertype!");
+				log_event!("Entered an invalid ordertype!");
 				return None;
 				},
 		};
@@ -96,23 +261,84 @@ impl JsonOrder {
 			"bid" => TradeType::Bid,
 			"ask" => TradeType::Ask,
 			_ => {
-				println!("Entered an invalid tradetype");
+				log_event!("Entered an invalid tradetype");
 				return None;
 			},
 		};
 
-		// let func = match tt {
-		// 	TradeType::Bid => p_wise_dem(typed_json.p_low, typed_json.p_high, typed_json.u_max),
-		// 	TradeType::Ask => p_wise_sup(typed_json.p_low, typed_json.p_high, typed_json.u_max),
-		// };
+		let et = match JsonOrder::exec_type_from_json(&typed_json) {
+			Some(et) => et,
+			None => {
+				log_event!("Entered an invalid exectype or missing its parameters");
+				return None;
+			},
+		};
 
-		Some(Order::new(
+		let tif = match JsonOrder::time_in_force_from_json(&typed_json) {
+			Some(tif) => tif,
+			None => {
+				log_event!("Entered an invalid time in force or missing its expiry");
+				return None;
+			},
+		};
+
+		let mut order = Order::new(
 			typed_json.trader_id,
 			ot, 
 			tt, 
 			typed_json.price, 
 			typed_json.quantity, 
-			))
+			);
+		order.exec_type = et;
+		order.time_in_force = tif;
+		order.gas_price = typed_json.gas_price.unwrap_or(0.0);
+		Some(order)
+	}
+
+	// Parse the optional execution fields, None if they are invalid or incomplete
+	fn exec_type_from_json(typed_json: &JsonOrder) -> Option<ExecType> {
+		let exec_type = match typed_json.exec_type {
+			Some(ref e) => e.to_lowercase(),
+			None => return Some(ExecType::Limit),
+		};
+		match exec_type.as_ref() {
+			"limit" => Some(ExecType::Limit),
+			"stop" => Some(ExecType::Stop(typed_json.trigger?)),
+			"stop_limit" => Some(ExecType::StopLimit(typed_json.trigger?)),
+			"pegged" => {
+				let peg_type = match typed_json.peg.as_ref()?.to_lowercase().as_ref() {
+					"primary" => PegType::Primary,
+					"market" => PegType::Market,
+					"midpoint" => PegType::Midpoint,
+					_ => return None,
+				};
+				Some(ExecType::Pegged(Peg::new(peg_type, typed_json.offset.unwrap_or(0.0), typed_json.cap)))
+			},
+			"flow" => {
+				let (p_low, p_high) = (typed_json.p_low?, typed_json.p_high?);
+				// The demand and supply schedules are only defined for p_low <= p_high
+				if p_low > p_high {
+					return None;
+				}
+				Some(ExecType::Flow(FlowSchedule::new(p_low, p_high, typed_json.u_max?)))
+			},
+			_ => None,
+		}
+	}
+
+	// Parse the optional time in force fields, None if they are invalid or incomplete
+	fn time_in_force_from_json(typed_json: &JsonOrder) -> Option<TimeInForce> {
+		let tif = match typed_json.time_in_force {
+			Some(ref tif) => tif.to_lowercase(),
+			None => return Some(TimeInForce::GoodTillCancel),
+		};
+		match tif.as_ref() {
+			"gtc" => Some(TimeInForce::GoodTillCancel),
+			"gtt" => Some(TimeInForce::GoodTillTime(Duration::from_millis(typed_json.expire_time?))),
+			"day" => Some(TimeInForce::Day),
+			"ioc" => Some(TimeInForce::ImmediateOrCancel),
+			_ => None,
+		}
 	}
 
 	// Turn an order into JSON from its params
@@ -128,12 +354,44 @@ impl JsonOrder {
             TradeType::Ask => "ask",
         };
 
+        let et = match order.exec_type {
+            ExecType::Limit => "limit",
+            ExecType::Stop(_) => "stop",
+            ExecType::StopLimit(_) => "stop_limit",
+            ExecType::Pegged(_) => "pegged",
+            ExecType::Flow(_) => "flow",
+        };
+
+        let peg = order.peg().map(|p| match p.peg_type {
+            PegType::Primary => "primary",
+            PegType::Market => "market",
+            PegType::Midpoint => "midpoint",
+        });
+
+        let (tif, expire_time) = match order.time_in_force {
+            TimeInForce::GoodTillCancel => ("gtc", None),
+            TimeInForce::GoodTillTime(expiry) => ("gtt", Some(expiry.as_millis() as u64)),
+            TimeInForce::Day => ("day", None),
+            TimeInForce::ImmediateOrCancel => ("ioc", None),
+        };
+
 		json!({
                 "trader_id": order.trader_id.clone(),
                 "order_type": ot,
                 "trade_type": tt,
-                "price": order.price.clone(),
-                "quantity": order.quantity.clone(),
+                "exec_type": et,
+                "time_in_force": tif,
+                "expire_time": expire_time,
+                "trigger": order.trigger_price(),
+                "peg": peg,
+                "offset": order.peg().map(|p| p.offset),
+                "cap": order.peg().and_then(|p| p.cap),
+                "p_low": order.flow().map(|f| f.p_low),
+                "p_high": order.flow().map(|f| f.p_high),
+                "u_max": order.flow().map(|f| f.u_max),
+                "price": order.price,
+                "quantity": order.quantity,
+                "gas_price": order.gas_price,
             })
 	}
 
@@ -165,3 +423,102 @@ impl JsonOrder {
 
 
 
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_exec_type_json_round_trip() {
+		let mut order = Order::new(String::from("peg"), OrderType::Enter, TradeType::Ask, 101.0, 5.0);
+		order.exec_type = ExecType::Pegged(Peg::new(PegType::Midpoint, 0.25, Some(100.0)));
+		let parsed = JsonOrder::order_from_json(JsonOrder::order_to_json(&order)).unwrap();
+		assert_eq!(parsed.exec_type, order.exec_type);
+
+		order.exec_type = ExecType::Flow(FlowSchedule::new(95.0, 105.0, 2.0));
+		let parsed = JsonOrder::order_from_json(JsonOrder::order_to_json(&order)).unwrap();
+		assert_eq!(parsed.exec_type, order.exec_type);
+
+		order.exec_type = ExecType::StopLimit(95.0);
+		order.time_in_force = TimeInForce::GoodTillTime(Duration::from_millis(1_500));
+		let parsed = JsonOrder::order_from_json(JsonOrder::order_to_json(&order)).unwrap();
+		assert_eq!(parsed.exec_type, ExecType::StopLimit(95.0));
+		assert_eq!(parsed.time_in_force, order.time_in_force);
+
+		// Stops need a trigger
+		let msg = json!({"trader_id": "s", "order_type": "enter", "trade_type": "bid",
+						 "exec_type": "stop", "price": 1.0, "quantity": 1.0});
+		assert!(JsonOrder::order_from_json(msg).is_none());
+	}
+
+	#[test]
+	fn test_commit_reveal_messages() {
+		let queue = Arc::new(Queue::new());
+		let sealed = |id: &str| Order::new(String::from(id), OrderType::Enter, TradeType::Ask, 101.0, 5.0);
+		let reveal = |id: &str, nonce: u64| {
+			let mut msg = JsonOrder::order_to_json(&sealed(id));
+			msg["order_type"] = json!("reveal");
+			msg["nonce"] = json!(nonce);
+			msg
+		};
+		*queue.commits.phase.lock().unwrap() = crate::controller::State::Commit;
+		for id in ["wrong", "right"].iter() {
+			let hash = crate::exchange::commit_reveal::CommitStore::commitment(&sealed(id), 3);
+			JsonOrder::process_new(json!({"trader_id": id, "order_type": "commit", "commitment": hash}), Arc::clone(&queue));
+		}
+
+		// Reveals are verified against the hex commitment, so a wrong nonce reveals nothing
+		// and a malformed reveal is dropped without taking the processor down
+		*queue.commits.phase.lock().unwrap() = crate::controller::State::Reveal;
+		JsonOrder::process_new(reveal("wrong", 4), Arc::clone(&queue));
+		JsonOrder::process_new(json!({"trader_id": "right", "order_type": "reveal", "nonce": 3, "price": "high"}), Arc::clone(&queue));
+		JsonOrder::process_new(reveal("right", 3), Arc::clone(&queue));
+		let (revealed, unrevealed) = queue.commits.close_reveal();
+		assert_eq!(revealed.len(), 1);
+		assert_eq!((revealed[0].trader_id.as_str(), revealed[0].price), ("right", 101.0));
+		assert_eq!(unrevealed.len(), 1);
+		assert_eq!(unrevealed[0].commitment.trader_id, "wrong");
+		assert!(queue.pop_all().is_empty());
+	}
+
+	#[test]
+	fn test_rfq_messages() {
+		let queue = Arc::new(Queue::new());
+		JsonOrder::process_new(json!({"trader_id": "mm", "order_type": "register_maker"}), Arc::clone(&queue));
+		JsonOrder::process_new(json!({"trader_id": "taker", "order_type": "rfq", "trade_type": "ask", "quantity": 3.0}), Arc::clone(&queue));
+		let request = &queue.rfq.drain_messages("mm")[0];
+		assert_eq!(request["trade_type"], "ask");
+
+		let request_id = request["request_id"].as_u64().unwrap();
+		JsonOrder::process_new(json!({"trader_id": "mm", "order_type": "quote", "request_id": request_id, "price": 99.5}), Arc::clone(&queue));
+		JsonOrder::process_new(json!({"trader_id": "taker", "order_type": "accept", "request_id": request_id, "maker_id": "mm"}), Arc::clone(&queue));
+		let trades = queue.rfq.take_trades();
+		assert_eq!(trades.len(), 1);
+		assert_eq!((trades[0].bid_id.as_str(), trades[0].ask_id.as_str(), trades[0].price), ("mm", "taker", 99.5));
+		assert!(queue.pop_all().is_empty());
+	}
+
+	#[test]
+	fn test_dark_messages() {
+		// Dark orders skip the lit queue
+		let queue = Arc::new(Queue::new());
+		JsonOrder::process_new(json!({"trader_id": "d", "order_type": "enter", "trade_type": "bid",
+									  "price": 100.0, "quantity": 5.0, "venue": "dark", "min_qty": 2.0}), Arc::clone(&queue));
+		assert!(queue.pop_all().is_empty());
+		assert_eq!(queue.dark.depth(), (1, 0));
+	}
+
+	#[test]
+	fn test_package_messages() {
+		// Package orders skip the lit queue and rest until the package auction
+		let queue = Arc::new(Queue::new());
+		JsonOrder::process_new(json!({"trader_id": "swap", "order_type": "package", "limit": 5.0,
+									  "legs": [{"instrument": "A", "quantity": 10.0}, {"instrument": "B", "quantity": -10.0}]}), Arc::clone(&queue));
+		JsonOrder::process_new(json!({"trader_id": "nolimit", "order_type": "package", "legs": [{"instrument": "A", "quantity": 1.0}]}), Arc::clone(&queue));
+		JsonOrder::process_new(json!({"trader_id": "empty", "order_type": "package", "limit": 1.0, "legs": []}), Arc::clone(&queue));
+		assert!(queue.pop_all().is_empty());
+		assert_eq!(queue.packages.len(), 1);
+
+		JsonOrder::process_new(json!({"trader_id": "swap", "order_type": "cancel_package"}), Arc::clone(&queue));
+		assert!(queue.packages.is_empty());
+	}
+}
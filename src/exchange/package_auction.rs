@@ -0,0 +1,399 @@
+use crate::controller::{Task, State};
+use crate::exchange::queue::Queue;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, Arc};
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// Packages up to this many are cleared exactly, larger instances by the greedy heuristic
+pub const EXACT_LIMIT: usize = 20;
+
+/// One instrument of a package.
+/// instrument: String -> identifier of the instrument traded
+/// quantity: f64 -> shares bought if positive, sold if negative
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Leg {
+	pub instrument: String,
+	pub quantity: f64,
+}
+
+impl Leg {
+	pub fn new(instrument: &str, quantity: f64) -> Leg {
+		Leg {
+			instrument: String::from(instrument),
+			quantity,
+		}
+	}
+}
+
+/// An all-or-none order over several instruments, e.g. buy A and sell B.
+/// trader_id: String -> trader_id of the submitting trader
+/// legs: Vec<Leg> -> the instruments and quantities traded together
+/// limit: f64 -> most the trader will pay for the whole package, negative if it must be paid
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageOrder {
+	pub trader_id: String,
+	pub legs: Vec<Leg>,
+	pub limit: f64,
+}
+
+impl PackageOrder {
+	pub fn new(trader_id: String, legs: Vec<Leg>, limit: f64) -> PackageOrder {
+		PackageOrder {
+			trader_id,
+			legs,
+			limit,
+		}
+	}
+
+	/// Checks the package can be cleared: it needs at least one leg, a finite limit and
+	/// finite, non-zero leg quantities
+	pub fn validate(&self) -> Result<(), &'static str> {
+		if self.legs.is_empty() {
+			return Err("ERROR: packages need at least one leg");
+		}
+		if !self.limit.is_finite() {
+			return Err("ERROR: package limits must be finite");
+		}
+		if self.legs.iter().any(|l| !l.quantity.is_finite() || l.quantity.abs() <= EPSILON) {
+			return Err("ERROR: package legs need a finite, non-zero quantity");
+		}
+		Ok(())
+	}
+
+	// Total shares across the legs, used to rank packages by value per share
+	fn size(&self) -> f64 {
+		self.legs.iter().map(|l| l.quantity.abs()).sum()
+	}
+}
+
+/// The result of a package auction.
+/// winners: Vec<usize> -> indices of the accepted packages in ascending order
+/// surplus: f64 -> sum of the accepted packages' limits, the value the auction creates
+/// exact: bool -> whether the winners were found by the exact solver
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageClearing {
+	pub winners: Vec<usize>,
+	pub surplus: f64,
+	pub exact: bool,
+}
+
+/// Batch clearing of combinatorial package orders. Winner determination picks the set of
+/// packages with the greatest total limit such that, for every instrument, the shares
+/// bought equal the shares sold. Packages are all-or-none on both sides, so a seller is
+/// never left partly filled.
+///
+/// Small instances are solved exactly by branch and bound. Larger ones use a greedy
+/// heuristic that ranks packages by limit per share traded. Starting from every package,
+/// the lowest ranked package on the heavy side of an unbalanced instrument, buying one in
+/// excess demand or selling one in excess supply, is dropped until all instruments
+/// balance. Finally each dropped package that adds value and keeps the balance is
+/// re-added, best first. The heuristic isn't guaranteed to find the optimum.
+///
+/// Traders submit packages as JSON with order_type "package", which rest in the Queue's
+/// PackageBook until a batch clears them. The exchange runs a single instrument today,
+/// so package orders don't reach the Books and clearing only reports the winners.
+/// Settling them needs a Book per instrument.
+pub struct PackageAuction {}
+
+impl PackageAuction {
+	/// Clears the packages with the exact solver up to EXACT_LIMIT packages, otherwise
+	/// with the greedy heuristic
+	pub fn clear(packages: &[PackageOrder]) -> PackageClearing {
+		if packages.len() <= EXACT_LIMIT {
+			PackageAuction::solve_exact(packages)
+		} else {
+			PackageAuction::solve_greedy(packages)
+		}
+	}
+
+	/// Whether the packages at indices buy exactly as much as they sell of every instrument
+	pub fn is_feasible(packages: &[PackageOrder], indices: &[usize]) -> bool {
+		PackageAuction::imbalances(packages, indices).is_empty()
+	}
+
+	// The instruments the packages at indices don't net to zero in, with the net shares
+	// bought, positive for excess demand and negative for excess supply
+	fn imbalances<'a>(packages: &'a [PackageOrder], indices: &[usize]) -> Vec<(&'a str, f64)> {
+		let mut net = HashMap::<&str, f64>::new();
+		for &i in indices {
+			for leg in packages[i].legs.iter() {
+				*net.entry(&leg.instrument).or_insert(0.0) += leg.quantity;
+			}
+		}
+		net.into_iter().filter(|(_, q)| q.abs() > EPSILON).collect()
+	}
+
+	/// Finds the optimal winners by depth first search over accepting or rejecting each
+	/// package, pruning branches that can't beat the best set found so far
+	pub fn solve_exact(packages: &[PackageOrder]) -> PackageClearing {
+		// The most the packages from i onward could still add
+		let mut bounds = vec![0.0; packages.len() + 1];
+		for i in (0..packages.len()).rev() {
+			bounds[i] = bounds[i + 1] + packages[i].limit.max(0.0);
+		}
+
+		let mut best = (Vec::new(), 0.0);
+		let mut chosen = Vec::new();
+		PackageAuction::search(packages, &bounds, 0, &mut chosen, 0.0, &mut best);
+		PackageClearing { winners: best.0, surplus: best.1, exact: true }
+	}
+
+	fn search(packages: &[PackageOrder],
+			  bounds: &[f64],
+			  i: usize,
+			  chosen: &mut Vec<usize>,
+			  value: f64,
+			  best: &mut (Vec<usize>, f64))
+	{
+		if value + bounds[i] <= best.1 + EPSILON {
+			return;
+		}
+		if i == packages.len() {
+			if PackageAuction::is_feasible(packages, chosen) {
+				*best = (chosen.clone(), value);
+			}
+			return;
+		}
+		chosen.push(i);
+		PackageAuction::search(packages, bounds, i + 1, chosen, value + packages[i].limit, best);
+		chosen.pop();
+		PackageAuction::search(packages, bounds, i + 1, chosen, value, best);
+	}
+
+	/// Finds winners with the greedy heuristic described on PackageAuction
+	pub fn solve_greedy(packages: &[PackageOrder]) -> PackageClearing {
+		let ratio = |i: usize| packages[i].limit / packages[i].size().max(EPSILON);
+		let by_ratio = |a: &usize, b: &usize| ratio(*b).total_cmp(&ratio(*a));
+		let mut accepted: Vec<usize> = (0..packages.len()).collect();
+		accepted.sort_by(by_ratio);
+
+		// Drop the lowest ranked package on the heavy side of an unbalanced instrument
+		// until every instrument balances
+		let mut dropped = Vec::new();
+		loop {
+			let imbalances = PackageAuction::imbalances(packages, &accepted);
+			if imbalances.is_empty() {
+				break;
+			}
+			let pos = accepted.iter().rposition(|&i| {
+				packages[i].legs.iter().any(|l| {
+					imbalances.iter().any(|(instrument, net)| *instrument == l.instrument && l.quantity * net > 0.0)
+				})
+			}).expect("an imbalance needs a leg on its heavy side");
+			dropped.push(accepted.remove(pos));
+		}
+
+		// Then re-add, best first, any dropped package that adds value and keeps the balance
+		dropped.sort_by(by_ratio);
+		for i in dropped.into_iter().filter(|&i| packages[i].limit > 0.0) {
+			accepted.push(i);
+			if !PackageAuction::is_feasible(packages, &accepted) {
+				accepted.pop();
+			}
+		}
+
+		let surplus: f64 = accepted.iter().map(|&i| packages[i].limit).sum();
+		if surplus < 0.0 {
+			return PackageClearing { winners: Vec::new(), surplus: 0.0, exact: false };
+		}
+		accepted.sort_unstable();
+		PackageClearing { winners: accepted, surplus, exact: false }
+	}
+}
+
+/// The package orders waiting for the next package auction, at most one per trader.
+/// packages: Mutex<Vec<PackageOrder>> -> resting packages in order of arrival
+pub struct PackageBook {
+	packages: Mutex<Vec<PackageOrder>>,
+}
+
+impl Default for PackageBook {
+	fn default() -> Self {
+		PackageBook::new()
+	}
+}
+
+impl PackageBook {
+	pub fn new() -> PackageBook {
+		PackageBook {
+			packages: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Rests a valid package, replacing any package the trader already has resting
+	pub fn add_order(&self, package: PackageOrder) -> Result<(), &'static str> {
+		package.validate()?;
+		let mut packages = self.packages.lock().unwrap();
+		packages.retain(|p| p.trader_id != package.trader_id);
+		packages.push(package);
+		Ok(())
+	}
+
+	/// Removes the trader's resting package
+	pub fn cancel_order_by_id(&self, trader_id: &str) -> Result<(), &'static str> {
+		let mut packages = self.packages.lock().unwrap();
+		let before = packages.len();
+		packages.retain(|p| p.trader_id != trader_id);
+		if packages.len() == before {
+			return Err("ERROR: trader has no resting package");
+		}
+		Ok(())
+	}
+
+	/// Clears the resting packages with PackageAuction::clear. The winners are removed and
+	/// returned, the rest stay for the next auction.
+	pub fn clear(&self) -> Vec<PackageOrder> {
+		let mut packages = self.packages.lock().unwrap();
+		let clearing = PackageAuction::clear(&packages);
+		let mut winners = Vec::with_capacity(clearing.winners.len());
+		// Winner indices are ascending, so removing from the back keeps the rest valid
+		for &i in clearing.winners.iter().rev() {
+			winners.push(packages.remove(i));
+		}
+		winners.reverse();
+		winners
+	}
+
+	/// Number of resting packages
+	pub fn len(&self) -> usize {
+		self.packages.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Clears the Queue's package book on an interval determined by the duration parameter
+	/// in milliseconds while the market is processing, logging the winning packages.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_package_task(queue: Arc<Queue>, state: Arc<Mutex<State>>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			if *state.lock().unwrap() != State::Process {
+				return;
+			}
+			for package in queue.packages.clear() {
+				log_event!("Package of {} accepted: {:?} @{}", package.trader_id, package.legs, package.limit);
+			}
+		}, duration)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn package(id: &str, legs: Vec<Leg>, limit: f64) -> PackageOrder {
+		PackageOrder::new(String::from(id), legs, limit)
+	}
+
+	#[test]
+	fn test_exact_winner_determination() {
+		let packages = vec![
+			// Buys A and sells B, paying up to 5 for the swap
+			package("swap", vec![Leg::new("A", 10.0), Leg::new("B", -10.0)], 5.0),
+			// Sells A for at least 98 and buys B for at most 101
+			package("sell_a", vec![Leg::new("A", -10.0)], -98.0),
+			package("buy_b", vec![Leg::new("B", 10.0)], 101.0),
+			// Buys A alone for up to 99, which doesn't beat the swap and B's buyer together
+			package("buy_a", vec![Leg::new("A", 10.0)], 99.0),
+		];
+		let clearing = PackageAuction::clear(&packages);
+		assert!(clearing.exact);
+		assert_eq!(clearing.winners, vec![0, 1, 2]);
+		assert_eq!(clearing.surplus, 8.0);
+		assert!(PackageAuction::is_feasible(&packages, &clearing.winners));
+
+		// Nothing trades without a seller of A
+		assert_eq!(PackageAuction::clear(&packages[2..]).winners, Vec::<usize>::new());
+	}
+
+	#[test]
+	fn test_greedy_is_feasible() {
+		let mut packages = Vec::new();
+		for i in 0..30 {
+			let instrument = if i % 2 == 0 { "A" } else { "B" };
+			packages.push(package(&format!("s{}", i), vec![Leg::new(instrument, -1.0)], -(90.0 + i as f64)));
+			packages.push(package(&format!("b{}", i), vec![Leg::new(instrument, 1.0)], 80.0 + i as f64));
+		}
+		let clearing = PackageAuction::clear(&packages);
+		assert!(!clearing.exact);
+		assert!(PackageAuction::is_feasible(&packages, &clearing.winners));
+		assert!(clearing.surplus >= 0.0);
+
+		// The heuristic can miss the optimum: it keeps the buyer of A alone over the swap
+		let packages = vec![
+			package("swap", vec![Leg::new("A", 10.0), Leg::new("B", -10.0)], 5.0),
+			package("sell_a", vec![Leg::new("A", -10.0)], -98.0),
+			package("buy_b", vec![Leg::new("B", 10.0)], 101.0),
+			package("buy_a", vec![Leg::new("A", 10.0)], 99.0),
+		];
+		let greedy = PackageAuction::solve_greedy(&packages);
+		assert_eq!((greedy.winners, greedy.surplus), (vec![1, 3], 1.0));
+		assert!(PackageAuction::solve_exact(&packages).surplus > 1.0);
+	}
+
+	#[test]
+	fn test_no_partial_fills() {
+		// Half of the seller's all-or-none package can't be filled
+		let packages = vec![
+			package("sell_a", vec![Leg::new("A", -10.0)], -98.0),
+			package("buy_a", vec![Leg::new("A", 5.0)], 99.0),
+		];
+		let clearing = PackageAuction::clear(&packages);
+		assert_eq!((clearing.winners, clearing.surplus), (Vec::<usize>::new(), 0.0));
+		assert!(!PackageAuction::is_feasible(&packages, &[0, 1]));
+		assert_eq!(PackageAuction::solve_greedy(&packages).winners, Vec::<usize>::new());
+
+		// A second buyer takes the other half
+		let mut packages = packages;
+		packages.push(package("buy_a2", vec![Leg::new("A", 5.0)], 50.0));
+		let clearing = PackageAuction::solve_greedy(&packages);
+		assert_eq!((clearing.winners, clearing.surplus), (vec![0, 1, 2], 51.0));
+	}
+
+	#[test]
+	fn test_non_finite_packages() {
+		let nan_limit = package("nan", vec![Leg::new("A", 1.0)], f64::NAN);
+		let inf_leg = package("inf", vec![Leg::new("A", f64::INFINITY)], 1.0);
+		assert!(nan_limit.validate().is_err());
+		assert!(inf_leg.validate().is_err());
+		assert!(package("empty", Vec::new(), 1.0).validate().is_err());
+		assert!(package("zero", vec![Leg::new("A", 0.0)], 1.0).validate().is_err());
+
+		// The book turns them away
+		let book = PackageBook::new();
+		assert!(book.add_order(nan_limit.clone()).is_err());
+		assert!(book.is_empty());
+
+		// And ranking a NaN limit no longer panics the greedy solver
+		let mut packages = vec![nan_limit];
+		for i in 0..EXACT_LIMIT {
+			packages.push(package(&format!("b{}", i), vec![Leg::new("A", 1.0)], 100.0));
+		}
+		assert!(PackageAuction::solve_greedy(&packages).winners.is_empty());
+	}
+
+	#[test]
+	fn test_package_book() {
+		let book = PackageBook::new();
+		book.add_order(package("sell_a", vec![Leg::new("A", -10.0)], -98.0)).unwrap();
+		book.add_order(package("buy_a", vec![Leg::new("A", 10.0)], 90.0)).unwrap();
+		book.add_order(package("buy_b", vec![Leg::new("B", 10.0)], 101.0)).unwrap();
+		assert!(book.clear().is_empty());
+		assert_eq!(book.len(), 3);
+
+		// Resubmitting replaces the trader's package, which now beats the seller's limit
+		book.add_order(package("buy_a", vec![Leg::new("A", 10.0)], 99.0)).unwrap();
+		let winners = book.clear();
+		let ids: Vec<&str> = winners.iter().map(|p| p.trader_id.as_str()).collect();
+		assert_eq!(ids, vec!["sell_a", "buy_a"]);
+		assert_eq!(book.len(), 1);
+
+		assert!(book.cancel_order_by_id("buy_b").is_ok());
+		assert!(book.cancel_order_by_id("buy_b").is_err());
+		assert!(book.is_empty());
+	}
+}
@@ -0,0 +1,152 @@
+use crate::exchange::order_book::Book;
+use crate::exchange::auction::Auction;
+use crate::exchange::trade::Trade;
+use crate::order::{Order, TradeType};
+
+use std::sync::Arc;
+
+pub struct PegProcessor {}
+
+impl PegProcessor {
+	/// Prices a pegged order against the current lit touch. If the peg's reference
+	/// doesn't exist yet the order keeps the price it was submitted or last repriced with.
+	pub fn price_order(bids: &Book, asks: &Book, order: &mut Order) {
+		let price = order.peg()
+			.and_then(|peg| peg.price(&order.trade_type, bids.best_lit_price(), asks.best_lit_price()));
+		if let Some(p) = price {
+			order.price = p;
+		}
+	}
+
+	/// Reprices every pegged order whenever update_best_price moved the touch of either
+	/// Book. Repriced orders are re-entered through the crossing logic, so a peg that
+	/// becomes marketable trades, and midpoint pegs on both sides match at the mid.
+	/// Returns the trades caused by the repricing.
+	pub fn reprice_pegs(bids: Arc<Book>, asks: Arc<Book>) -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		let mut last_touch = None;
+
+		// Without pegs resting there is nothing to reprice, most Books never hold any
+		if !bids.has_pegs() && !asks.has_pegs() {
+			bids.take_touch_changed();
+			asks.take_touch_changed();
+			return trades;
+		}
+
+		// Take both flags every iteration so neither is left set
+		while bids.take_touch_changed() | asks.take_touch_changed() {
+			let touch = (bids.best_lit_price(), asks.best_lit_price());
+			// Repricing against an unchanged lit touch would only reshuffle the pegs
+			if last_touch == Some(touch) {
+				break;
+			}
+			last_touch = Some(touch);
+
+			let mut pegged = bids.take_pegged();
+			pegged.extend(asks.take_pegged());
+			for mut order in pegged {
+				PegProcessor::price_order(&bids, &asks, &mut order);
+				trades.extend(match order.trade_type {
+					TradeType::Bid => Auction::calc_bid_crossing(Arc::clone(&bids), Arc::clone(&asks), order),
+					TradeType::Ask => Auction::calc_ask_crossing(Arc::clone(&bids), Arc::clone(&asks), order),
+				});
+			}
+		}
+
+		// Re-entering the pegs flags the touch again, which has already been handled
+		bids.take_touch_changed();
+		asks.take_touch_changed();
+		trades
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{ExecType, Peg, PegType};
+	use crate::test_utils::{order, OrderSetup};
+
+	fn pegged(id: &str, trade_type: TradeType, peg: Peg) -> Order {
+		order(id, trade_type, 0.0, 5.0).with_exec_type(ExecType::Pegged(peg))
+	}
+
+	#[test]
+	fn test_primary_peg_follows_touch() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		bids.add_order(order("lit", TradeType::Bid, 99.0, 5.0)).unwrap();
+		asks.add_order(order("ask", TradeType::Ask, 101.0, 5.0)).unwrap();
+
+		let mut peg = pegged("peg", TradeType::Bid, Peg::new(PegType::Primary, 0.5, None));
+		PegProcessor::price_order(&bids, &asks, &mut peg);
+		bids.add_order(peg).unwrap();
+		PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks));
+
+		// A better lit bid moves the peg up with it
+		bids.add_order(order("better", TradeType::Bid, 100.0, 5.0)).unwrap();
+		assert!(PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks)).is_empty());
+		let i = bids.peek_id_pos(String::from("peg")).unwrap();
+		assert_eq!(bids.orders.lock().unwrap()[i].price, 99.5);
+	}
+
+	#[test]
+	fn test_midpoint_pegs_match_at_mid() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		bids.add_order(pegged("mid_bid", TradeType::Bid, Peg::new(PegType::Midpoint, 0.0, None))).unwrap();
+		asks.add_order(pegged("mid_ask", TradeType::Ask, Peg::new(PegType::Midpoint, 0.0, None))).unwrap();
+
+		bids.add_order(order("bid", TradeType::Bid, 98.0, 5.0)).unwrap();
+		asks.add_order(order("ask", TradeType::Ask, 102.0, 5.0)).unwrap();
+		let trades = PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks));
+
+		assert_eq!(trades.len(), 1);
+		assert_eq!(trades[0].bid_id, "mid_bid");
+		assert_eq!(trades[0].ask_id, "mid_ask");
+		assert_eq!(trades[0].price, 100.0);
+		assert_eq!(bids.len(), 1);
+		assert_eq!(asks.len(), 1);
+	}
+
+	#[test]
+	fn test_peg_keeps_price_without_reference() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		bids.add_order(order("bid", TradeType::Bid, 98.0, 5.0)).unwrap();
+		asks.add_order(order("ask", TradeType::Ask, 102.0, 5.0)).unwrap();
+		let mut peg = pegged("mid", TradeType::Bid, Peg::new(PegType::Midpoint, 0.0, None));
+		PegProcessor::price_order(&bids, &asks, &mut peg);
+		bids.add_order(peg).unwrap();
+		PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks));
+
+		// With the ask side gone there is no midpoint, so the peg rests at its last price
+		asks.cancel_order_by_id("ask").unwrap();
+		assert!(PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks)).is_empty());
+		let i = bids.peek_id_pos(String::from("mid")).unwrap();
+		assert_eq!(bids.orders.lock().unwrap()[i].price, 100.0);
+		assert_eq!(bids.len(), 2);
+	}
+
+	#[test]
+	fn test_capped_peg_trades_when_touch_moves() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		asks.add_order(order("far", TradeType::Ask, 101.0, 5.0)).unwrap();
+		let mut peg = pegged("peg", TradeType::Bid, Peg::new(PegType::Market, 0.0, Some(100.5)));
+		PegProcessor::price_order(&bids, &asks, &mut peg);
+		// The cap holds the peg below the ask it follows
+		assert_eq!(peg.price, 100.5);
+		bids.add_order(peg).unwrap();
+		PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks));
+
+		// A lower ask reprices the peg onto it, so it trades and leaves no peg resting
+		asks.add_order(order("near", TradeType::Ask, 100.0, 5.0)).unwrap();
+		let trades = PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks));
+		assert_eq!(trades.len(), 1);
+		assert_eq!((trades[0].bid_id.as_str(), trades[0].ask_id.as_str()), ("peg", "near"));
+		assert_eq!(trades[0].price, 100.0);
+		assert!(bids.is_empty());
+		assert!(!bids.has_pegs());
+	}
+}
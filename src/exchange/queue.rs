@@ -1,16 +1,37 @@
 use crate::order::Order;
+use crate::exchange::commit_reveal::CommitStore;
+use crate::exchange::rfq::RfqDesk;
+use crate::exchange::dark_pool::DarkPool;
+use crate::exchange::package_auction::PackageBook;
 use std::sync::Mutex;
 
 
 /// A threadsafe FIFO queue to store unprocessed messages arriving from traders.
+/// Sealed orders sent during a commit-reveal round are held in commits instead,
+/// request-for-quote messages go to the rfq desk, dark orders rest in the dark pool
+/// and package orders rest in the package book.
 pub struct Queue {
     items: Mutex<Vec<Order>>,
+    pub commits: CommitStore,
+    pub rfq: RfqDesk,
+    pub dark: DarkPool,
+    pub packages: PackageBook,
+}
+
+impl Default for Queue {
+	fn default() -> Self {
+		Queue::new()
+	}
 }
 
 impl Queue {
 	pub fn new() -> Queue {
 		Queue {
 			items: Mutex::new(Vec::<Order>::new()),
+			commits: CommitStore::default(),
+			rfq: RfqDesk::default(),
+			dark: DarkPool::default(),
+			packages: PackageBook::default(),
 		}
 	}
 
@@ -25,6 +46,15 @@ impl Queue {
 		items.pop()
 	}
 
+	// Gives the closure the pending orders in order of arrival. Used by agents that
+	// watch the queue and insert their own orders around others'.
+	pub fn with_pending<F, R>(&self, f: F) -> R
+	where F: FnOnce(&mut Vec<Order>) -> R
+	{
+		let mut items = self.items.lock().unwrap();
+		f(&mut items)
+	}
+
 	// Empties the Queue into a vector of Orders. Drain() pops the items
 	// out in the order of arrival, so once iterated upon, orders will be 
 	// processed first -> last.
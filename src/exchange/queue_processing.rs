@@ -1,8 +1,13 @@
-use crate::order::{Order, OrderType, TradeType};
+use crate::order::{Order, OrderType, TradeType, TimeInForce};
 use crate::exchange::queue::Queue;
 use crate::exchange::order_book::Book;
 use crate::controller::{Task, State};
 use crate::exchange::auction::{Auction};
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::exchange::peg_processing::PegProcessor;
+use crate::exchange::hybrid::HybridRouter;
+use crate::exchange::expiry::{CancelReport, CancelReason};
+use crate::utility::get_time;
 
 use std::thread;
 use std::thread::JoinHandle;
@@ -14,143 +19,266 @@ impl QueueProcessor {
 	// Concurrently process orders in the queue. Each order is
 	// either of OrderType::{Enter, Update, Cancel}. Each order will
 	// modify the state of either the Bids or Asks Book, but must
-	// first acquire a lock on the respective book. 
+	// first acquire a lock on the respective book. Each handle
+	// returns the trades its order produced. Fills happen in whatever
+	// order the threads take the locks, so only use this where the
+	// ordering doesn't matter. The exchange's queue task is sequential.
 	pub fn conc_process_order_queue(queue: Arc<Queue>, 
 									bids: Arc<Book>, 
 									asks: Arc<Book>) 
-									-> Vec<JoinHandle<()>>{
+									-> Vec<JoinHandle<Vec<Trade>>>{
 		// Acquire lock of Queue
 		// Pop off contents of Queue
-		// match over the OrderType
-		// process each order based on OrderType
-		let mut handles = Vec::<JoinHandle<()>>::new();
+		// process each order in its own thread
+		let mut handles = Vec::<JoinHandle<Vec<Trade>>>::new();
 		for order in queue.pop_all() {
-			let handle = match order.order_type {
-				OrderType::Enter => QueueProcessor::process_enter(Arc::clone(&bids), Arc::clone(&asks), order),
-				OrderType::Update => QueueProcessor::process_update(Arc::clone(&bids), Arc::clone(&asks), order),
-				OrderType::Cancel => QueueProcessor::process_cancel(Arc::clone(&bids), Arc::clone(&asks), order),
-			};
-			handles.push(handle);
+			let (bids, asks, queue) = (Arc::clone(&bids), Arc::clone(&asks), Arc::clone(&queue));
+			handles.push(thread::spawn(move || {
+				QueueProcessor::process_order(bids, asks, queue, order)
+			}));
 		}
 		handles
 	}
 
+	// Processes the orders in the queue one at a time in order of arrival. Unlike
+	// conc_process_order_queue the resulting trades are deterministic, so stops
+	// released into the queue by a trade are processed in the order they were released.
+	pub fn seq_process_order_queue(queue: Arc<Queue>, 
+								   bids: Arc<Book>, 
+								   asks: Arc<Book>) 
+								   -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		for order in queue.pop_all() {
+			trades.extend(QueueProcessor::process_order(Arc::clone(&bids), 
+														Arc::clone(&asks), 
+														Arc::clone(&queue), 
+														order));
+		}
+		trades
+	}
+
+	// Match over the OrderType and process the order accordingly. Afterwards pegged
+	// orders follow any change to the touch, resting orders crossing a hybrid pool are
+	// filled from it and stops set off by the trades are released.
+	fn process_order(bids: Arc<Book>, asks: Arc<Book>, queue: Arc<Queue>, order: Order) -> Vec<Trade> {
+		let mut trades = match order.order_type {
+			OrderType::Enter => QueueProcessor::process_enter(Arc::clone(&bids), Arc::clone(&asks), Arc::clone(&queue), order),
+			OrderType::Update => QueueProcessor::process_update(Arc::clone(&bids), Arc::clone(&asks), Arc::clone(&queue), order),
+			OrderType::Cancel => {
+				QueueProcessor::process_cancel(Arc::clone(&bids), Arc::clone(&asks), order);
+				Vec::new()
+			},
+		};
+		trades.extend(PegProcessor::reprice_pegs(Arc::clone(&bids), Arc::clone(&asks)));
+		trades.extend(HybridRouter::arbitrage(&bids, &asks));
+		QueueProcessor::release_stops(&bids, &asks, &queue, &trades);
+		trades
+	}
 
-	// Checks if the new order crosses. Modifies orders in book then calculates new max price
-	fn process_enter(bids: Arc<Book>, asks: Arc<Book>, order: Order) -> JoinHandle<()> {
-		// Spawn a new thread to process the order
-	    thread::spawn(move || {
-			// Since CDA we will check if the order transacts here:
-			match order.trade_type {
-				TradeType::Ask => {
-					// Only check for cross if this ask price is lower than best ask
-					if order.price < asks.get_min_price() {
-						// This will add the new ask to the book if it doesn't fully transact
-						Auction::calc_ask_crossing(bids, asks, order);
-					} else {
-						// We need to add the ask to the book, best price will be updated in add_order
-						asks.add_order(order).expect("Failed to add order");
+	// Processes the orders in the queue without matching them, used while orders accumulate
+	// ahead of a call auction. The Books may be left crossed until the auction uncrosses them.
+	pub fn accumulate_order_queue(queue: Arc<Queue>, bids: Arc<Book>, asks: Arc<Book>) {
+		for order in queue.pop_all() {
+			let book = match order.trade_type {
+				TradeType::Ask => &asks,
+				TradeType::Bid => &bids,
+			};
+			match order.order_type {
+				OrderType::Cancel => QueueProcessor::process_cancel(Arc::clone(&bids), Arc::clone(&asks), order),
+				OrderType::Enter | OrderType::Update => {
+					if order.order_type == OrderType::Update {
+						QueueProcessor::cancel_resting(book, &order.trader_id);
 					}
-				},
-				TradeType::Bid => {
-					// Only check for cross if this bid price is higher than best bid
-					if order.price > bids.get_max_price() {
-						// This will add the new bid to the book if it doesn't fully transact
-						Auction::calc_bid_crossing(bids, asks, order);
-					} else {
-						// We need to add the ask to the book, best price will be updated in add_order
-						bids.add_order(order).expect("Failed to add order...");
+					if let Some(order) = QueueProcessor::admit_order(&bids, &asks, &queue, order) {
+						book.add_order(order).expect("Failed to add order");
 					}
 				}
 			}
-	    })
+		}
 	}
 
-	// Cancels the previous order and then enters this as a new one
-	// Updates an order in the Bids or Asks Book in it's own thread
-	fn process_update(bids: Arc<Book>, asks: Arc<Book>, order: Order) -> JoinHandle<()> {
-		// update books min/max price if this overwrites current min/max OR this order contains new min/max
-	    thread::spawn(move || {
-			match order.trade_type {
-				TradeType::Ask => {
-					// Cancel the orginal order:
-					println!("Cancelling!");
-					match asks.cancel_order_by_id(&order.trader_id) {
-						Ok(()) => {},
-						Err(e) => println!("{:?}", e),
-					}
-					// Only check for cross if this ask price is lower than best ask
-					if order.price < asks.get_min_price() {
-						println!("Gonna auction!");
-						// This will add the new ask to the book if it doesn't fully transact
-						Auction::calc_ask_crossing(bids, asks, order);
-					} else {
-						println!("Adding to ask book");
-						// We need to add the ask to the book, best price will be updated in add_order
-						asks.add_order(order).expect("Failed to add order");
-					}
-				},
-				TradeType::Bid => {
-					// Cancel the orginal order:
-					println!("Cancelling!");
-					match bids.cancel_order_by_id(&order.trader_id) {
-						Ok(()) => {},
-						Err(e) => println!("{:?}", e),
-					}
-					// Only check for cross if this bid price is higher than best bid
-					if order.price > bids.get_max_price() {
-						println!("Gonna auction!");
-						// This will add the new bid to the book if it doesn't fully transact
-						Auction::calc_bid_crossing(bids, asks, order);
-					} else {
-						println!("Adding to ask book");
-						// We need to add the ask to the book, best price will be updated in add_order
-						bids.add_order(order).expect("Failed to add order...");
-					}
+	// Checks if the new order crosses. Modifies orders in book then calculates new max price.
+	fn process_enter(bids: Arc<Book>, asks: Arc<Book>, queue: Arc<Queue>, order: Order) -> Vec<Trade> {
+		let order = match QueueProcessor::admit_order(&bids, &asks, &queue, order) {
+			Some(order) => order,
+			None => return Vec::new(),
+		};
+
+		// Immediate-or-cancel orders are matched like any other, then their remainder is pulled
+		let immediate = match order.time_in_force {
+			TimeInForce::ImmediateOrCancel => Some((order.trade_type.clone(), order.trader_id.clone())),
+			_ => None,
+		};
+
+		// Since CDA we will check if the order transacts here:
+		let trades = match order.trade_type {
+			TradeType::Ask => {
+				// Only check for cross if this ask price is lower than best ask
+				if order.price < asks.get_min_price() {
+					// This will add the new ask to the book if it doesn't fully transact
+					Auction::calc_ask_crossing(Arc::clone(&bids), Arc::clone(&asks), order)
+				} else {
+					// We need to add the ask to the book, best price will be updated in add_order
+					asks.add_order(order).expect("Failed to add order");
+					Vec::new()
+				}
+			},
+			TradeType::Bid => {
+				// Only check for cross if this bid price is higher than best bid
+				if order.price > bids.get_max_price() {
+					// This will add the new bid to the book if it doesn't fully transact
+					Auction::calc_bid_crossing(Arc::clone(&bids), Arc::clone(&asks), order)
+				} else {
+					// We need to add the bid to the book, best price will be updated in add_order
+					bids.add_order(order).expect("Failed to add order...");
+					Vec::new()
 				}
 			}
-	    })
-	}
+		};
 
-	// Cancels the order living in the Bids or Asks Book
-	fn process_cancel(bids: Arc<Book>, asks: Arc<Book>, order: Order) -> JoinHandle<()> {
-	    thread::spawn(move || {
-			let book = match order.trade_type {
-				TradeType::Ask => asks,
-				TradeType::Bid => bids,
+		if let Some((trade_type, trader_id)) = immediate {
+			let book = match trade_type {
+				TradeType::Ask => &asks,
+				TradeType::Bid => &bids,
 			};
+			QueueProcessor::cancel_unfilled(book, &trader_id);
+		}
+		trades
+	}
+
+	/// Cancels every immediate-or-cancel order resting in the Books. Batch auctions use it
+	/// once the Books are uncrossed, since those orders only take part in one auction.
+	pub fn cancel_immediate(bids: &Book, asks: &Book) {
+		for book in [bids, asks].iter() {
+			for order in book.remove_orders(|o| o.time_in_force == TimeInForce::ImmediateOrCancel) {
+				log_event!("Unfilled order cancelled: {:?}", CancelReport::new(&order, CancelReason::Unfilled, get_time()));
+			}
+		}
+	}
+
+	// Removes the unfilled remainder of an immediate-or-cancel order from the book
+	fn cancel_unfilled(book: &Book, trader_id: &str) {
+		let unfilled = book.remove_orders(|o| o.trader_id == trader_id && o.time_in_force == TimeInForce::ImmediateOrCancel);
+		for order in unfilled {
+			log_event!("Unfilled order cancelled: {:?}", CancelReport::new(&order, CancelReason::Unfilled, get_time()));
+		}
+	}
+
+	// Validates a new order before it can enter the Books. Expired orders and orders
+	// outside the price band are dropped, stop orders are parked in the StopBook until
+	// a trade triggers them and pegged orders are priced off the touch. Returns the
+	// order if it should enter the Books.
+	fn admit_order(bids: &Book, asks: &Book, queue: &Queue, mut order: Order) -> Option<Order> {
+		// Orders that expired before reaching the books are dropped
+		if order.is_expired(get_time()) {
+			log_event!("Expired order cancelled: {:?}", CancelReport::new(&order, CancelReason::Expired, get_time()));
+			return None;
+		}
+
+		let book = match order.trade_type {
+			TradeType::Ask => asks,
+			TradeType::Bid => bids,
+		};
+
+		if order.flow().is_some() {
+			// Flow orders wait for the next flow market batch instead of matching
+			book.flows.add_order(order);
+			return None;
+		}
+
+		if order.trigger_price().is_some() {
+			// The stop is released straight away if the last trade already crossed its trigger
+			if let Some(triggered) = book.stops.add_order(order) {
+				queue.add(triggered);
+			}
+			return None;
+		}
+		PegProcessor::price_order(bids, asks, &mut order);
+
+		if let Err(e) = book.check_band(&mut order) {
+			log_event!("{} rejected: {}", order.trader_id, e);
+			return None;
+		}
+		Some(order)
+	}
+
+	// Cancels the previous order and then enters this as a new one
+	fn process_update(bids: Arc<Book>, asks: Arc<Book>, queue: Arc<Queue>, order: Order) -> Vec<Trade> {
+		match order.trade_type {
+			TradeType::Ask => QueueProcessor::cancel_resting(&asks, &order.trader_id),
+			TradeType::Bid => QueueProcessor::cancel_resting(&bids, &order.trader_id),
+		}
+		// Update books min/max price if this order contains new min/max
+		QueueProcessor::process_enter(bids, asks, queue, order)
+	}
+
+	// Cancels the orginal order of an update, which may be resting in the Book, StopBook or FlowBook
+	fn cancel_resting(book: &Book, trader_id: &str) {
+		if book.stops.cancel_order_by_id(trader_id).is_ok() || book.flows.cancel_order_by_id(trader_id).is_ok() {
+			return;
+		}
+		if let Err(e) = book.cancel_order_by_id(trader_id) {
+			log_event!("{:?}", e);
+		}
+	}
+
+	// Cancels the order living in the Bids or Asks Book or their StopBooks and FlowBooks
+	fn process_cancel(bids: Arc<Book>, asks: Arc<Book>, order: Order) {
+		let book = match order.trade_type {
+			TradeType::Ask => asks,
+			TradeType::Bid => bids,
+		};
+
+		if book.stops.cancel_order_by_id(&order.trader_id).is_ok() || book.flows.cancel_order_by_id(&order.trader_id).is_ok() {
+			return;
+		}
 
-			// If the cancel fails bubble error up.
-			match book.cancel_order(order) {
-	    		Ok(()) => {},
-	    		Err(e) => {
-	    			println!("ERROR: {}", e);
-	    			// TODO send an error response over TCP
-	    		}
-	    	}
-	    })
+		// If the cancel fails bubble error up.
+		match book.cancel_order(order) {
+    		Ok(()) => {},
+    		Err(e) => {
+    			log_event!("ERROR: {}", e);
+    			// TODO send an error response over TCP
+    		}
+    	}
 	}
 
+	/// Feeds the trades to both StopBooks and pushes any stops they set off onto the
+	/// Queue, buy stops before sell stops, each in the order the StopBook released them.
+	pub fn release_stops(bids: &Book, asks: &Book, queue: &Queue, trades: &[Trade]) {
+		if trades.is_empty() {
+			return;
+		}
+		for order in bids.stops.trigger(trades).into_iter().chain(asks.stops.trigger(trades)) {
+			log_event!("Stop triggered for {} @{:?}", order.trader_id, trades.last().map(|t| t.price));
+			queue.add(order);
+		}
+	}
+
+	// Processes the queue on an interval determined by the duration parameter in milliseconds
+	// according to the market State, appending every trade to the trade log.
 	pub fn async_queue_task(queue: Arc<Queue>, 
 							bids: Arc<Book>, 
 							asks: Arc<Book>, 
 							state: Arc<Mutex<State>>, 
+							trade_log: Arc<TradeLog>,
 							duration: u64) -> Task
 	{
 	    Task::rpt_task(move || {
 	    	match *state.lock().expect("Couldn't lock state in queue task") {
-				State::Process => {
-					let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+				// Sequential so released stops cascade in a deterministic order
+				State::Process => trade_log.add(QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
 								Arc::clone(&bids),
-								Arc::clone(&asks));
-
-					for h in handles {
-						h.join().expect("Couldn't join queue tasks");
-					}
-					// println!("Processing order queue");
-				},
-				State::Auction => println!("Can't process order queue because auction!"),
-				State::PreAuction => println!("Can't process order queue because pre-auction!"),
+								Arc::clone(&asks))),
+				// Orders rest in the Books without matching until the call auction
+				State::PreAuction => QueueProcessor::accumulate_order_queue(Arc::clone(&queue), 
+								Arc::clone(&bids),
+								Arc::clone(&asks)),
+				State::Auction => log_event!("Can't process order queue because auction!"),
+				State::Halted => log_event!("Can't process order queue because halted!"),
+				State::Closed => log_event!("Can't process order queue because market closed!"),
+				// Only revealed orders enter the Books during a sealed-bid round
+				State::Commit | State::Reveal => log_event!("Can't process order queue during sealed-bid round!"),
 			}
 	    }, duration)
 	}
@@ -0,0 +1,310 @@
+use crate::controller::Task;
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::order::TradeType;
+use crate::utility::get_time;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, Arc};
+use std::time::Duration;
+
+/// A firm quote a market maker answered a request with.
+/// maker_id: String -> trader_id of the quoting market maker
+/// price: f64 -> price the maker will trade the full requested quantity at
+/// time: Duration -> time the quote was received
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+	pub maker_id: String,
+	pub price: f64,
+	pub time: Duration,
+}
+
+/// A taker's request for quotes.
+/// request_id: u64 -> identifier makers and the taker refer to the request by
+/// taker_id: String -> trader_id of the taker
+/// trade_type: TradeType{Bid, Ask} -> side the taker wants to trade
+/// quantity: f64 -> size to be quoted
+/// time: Duration -> time the request was broadcast
+/// quotes: Vec<Quote> -> quotes received so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteRequest {
+	pub request_id: u64,
+	pub taker_id: String,
+	pub trade_type: TradeType,
+	pub quantity: f64,
+	pub time: Duration,
+	pub quotes: Vec<Quote>,
+}
+
+/// The request-for-quote desk. A taker's request is broadcast to every registered market
+/// maker session, makers answer with firm quotes within quote_timeout and the taker may
+/// accept one within accept_timeout after the quoting window closes. An accepted quote
+/// trades outside the Books. Messages for each session wait in its outbox until the
+/// session's connection collects them.
+/// quote_timeout: Duration -> how long makers have to quote after a request
+/// accept_timeout: Duration -> how long the taker has to accept after quoting closes
+pub struct RfqDesk {
+	pub quote_timeout: Mutex<Duration>,
+	pub accept_timeout: Mutex<Duration>,
+	makers: Mutex<HashSet<String>>,
+	requests: Mutex<HashMap<u64, QuoteRequest>>,
+	next_id: Mutex<u64>,
+	outboxes: Mutex<HashMap<String, Vec<serde_json::Value>>>,
+	trades: Mutex<Vec<Trade>>,
+}
+
+impl Default for RfqDesk {
+	fn default() -> Self {
+		RfqDesk::new(Duration::from_millis(500), Duration::from_millis(500))
+	}
+}
+
+impl RfqDesk {
+	pub fn new(quote_timeout: Duration, accept_timeout: Duration) -> RfqDesk {
+		RfqDesk {
+			quote_timeout: Mutex::new(quote_timeout),
+			accept_timeout: Mutex::new(accept_timeout),
+			makers: Mutex::new(HashSet::new()),
+			requests: Mutex::new(HashMap::new()),
+			next_id: Mutex::new(0),
+			outboxes: Mutex::new(HashMap::new()),
+			trades: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Changes how long makers have to quote and takers have to accept
+	pub fn set_timeouts(&self, quote_timeout: Duration, accept_timeout: Duration) {
+		*self.quote_timeout.lock().unwrap() = quote_timeout;
+		*self.accept_timeout.lock().unwrap() = accept_timeout;
+	}
+
+	/// Registers a market maker session to receive quote requests
+	pub fn register_maker(&self, maker_id: String) {
+		self.makers.lock().unwrap().insert(maker_id);
+	}
+
+	/// Stops sending quote requests to the market maker
+	pub fn unregister_maker(&self, maker_id: &str) {
+		self.makers.lock().unwrap().remove(maker_id);
+	}
+
+	/// Broadcasts a new quote request to every registered maker, returning its request_id
+	pub fn request(&self, taker_id: String, trade_type: TradeType, quantity: f64, now: Duration) -> u64 {
+		let request_id = {
+			let mut next_id = self.next_id.lock().unwrap();
+			*next_id += 1;
+			*next_id
+		};
+		let msg = json!({
+			"msg_type": "quote_request",
+			"request_id": request_id,
+			"trade_type": RfqDesk::side_str(&trade_type),
+			"quantity": quantity,
+		});
+		let makers: Vec<String> = self.makers.lock().unwrap().iter().filter(|m| **m != taker_id).cloned().collect();
+		for maker in makers {
+			self.send(&maker, msg.clone());
+		}
+
+		let request = QuoteRequest { request_id, taker_id, trade_type, quantity, time: now, quotes: Vec::new() };
+		self.requests.lock().unwrap().insert(request_id, request);
+		request_id
+	}
+
+	/// Records a registered maker's firm quote and forwards it to the taker. A maker's
+	/// later quote replaces its earlier one.
+	pub fn quote(&self, maker_id: String, request_id: u64, price: f64, now: Duration) -> Result<(), &'static str> {
+		if !self.makers.lock().unwrap().contains(&maker_id) {
+			return Err("ERROR: quotes are only accepted from registered market makers");
+		}
+		let quote_timeout = *self.quote_timeout.lock().unwrap();
+		let mut requests = self.requests.lock().unwrap();
+		let request = requests.get_mut(&request_id).ok_or("ERROR: quote request not found")?;
+		if now > request.time + quote_timeout {
+			return Err("ERROR: quote arrived after the quoting window closed");
+		}
+
+		request.quotes.retain(|q| q.maker_id != maker_id);
+		request.quotes.push(Quote { maker_id: maker_id.clone(), price, time: now });
+		let taker_id = request.taker_id.clone();
+		drop(requests);
+		self.send(&taker_id, json!({
+			"msg_type": "quote",
+			"request_id": request_id,
+			"maker_id": maker_id,
+			"price": price,
+		}));
+		Ok(())
+	}
+
+	/// The taker accepts a maker's quote, trading the requested quantity at the quoted
+	/// price outside the Books. Both sides are sent a fill and the request is closed.
+	pub fn accept(&self, taker_id: &str, request_id: u64, maker_id: &str, now: Duration) -> Result<Trade, &'static str> {
+		let deadline = *self.quote_timeout.lock().unwrap() + *self.accept_timeout.lock().unwrap();
+		let mut requests = self.requests.lock().unwrap();
+		let request = requests.get(&request_id).ok_or("ERROR: quote request not found")?;
+		if request.taker_id != taker_id {
+			return Err("ERROR: only the requesting taker can accept a quote");
+		}
+		if now > request.time + deadline {
+			return Err("ERROR: quote request expired before it was accepted");
+		}
+		let quote = request.quotes.iter().find(|q| q.maker_id == maker_id).ok_or("ERROR: maker didn't quote this request")?;
+
+		let (bid_id, ask_id) = match request.trade_type {
+			TradeType::Bid => (request.taker_id.clone(), quote.maker_id.clone()),
+			TradeType::Ask => (quote.maker_id.clone(), request.taker_id.clone()),
+		};
+		let mut trade = Trade::new(bid_id, ask_id, Some(request.trade_type.clone()), quote.price, request.quantity);
+		trade.off_book = true;
+		requests.remove(&request_id);
+		drop(requests);
+
+		let fill = json!({
+			"msg_type": "rfq_fill",
+			"request_id": request_id,
+			"bid_id": trade.bid_id,
+			"ask_id": trade.ask_id,
+			"price": trade.price,
+			"quantity": trade.quantity,
+		});
+		self.send(taker_id, fill.clone());
+		self.send(maker_id, fill);
+		self.trades.lock().unwrap().push(trade.clone());
+		Ok(trade)
+	}
+
+	/// Closes every request whose accept window has passed, returning their ids
+	pub fn expire(&self, now: Duration) -> Vec<u64> {
+		let deadline = *self.quote_timeout.lock().unwrap() + *self.accept_timeout.lock().unwrap();
+		let mut requests = self.requests.lock().unwrap();
+		let expired: Vec<u64> = requests.values().filter(|r| now > r.time + deadline).map(|r| r.request_id).collect();
+		for id in expired.iter() {
+			requests.remove(id);
+		}
+		expired
+	}
+
+	/// Returns the open request if it hasn't been accepted or expired
+	pub fn get_request(&self, request_id: u64) -> Option<QuoteRequest> {
+		self.requests.lock().unwrap().get(&request_id).cloned()
+	}
+
+	/// Removes and returns the messages waiting for the session
+	pub fn drain_messages(&self, trader_id: &str) -> Vec<serde_json::Value> {
+		self.outboxes.lock().unwrap().remove(trader_id).unwrap_or_default()
+	}
+
+	/// Puts messages that couldn't be delivered back at the front of the session's outbox,
+	/// ahead of any that arrived since they were drained
+	pub fn requeue_messages(&self, trader_id: &str, mut messages: Vec<serde_json::Value>) {
+		let mut outboxes = self.outboxes.lock().unwrap();
+		let outbox = outboxes.entry(String::from(trader_id)).or_default();
+		messages.append(outbox);
+		*outbox = messages;
+	}
+
+	/// Removes and returns the trades made since the last call, for the trade log
+	pub fn take_trades(&self) -> Vec<Trade> {
+		self.trades.lock().unwrap().drain(..).collect()
+	}
+
+	/// The average price of taking quantity from the opposite Book, to compare an RFQ
+	/// execution against. Returns None if the Book is too shallow.
+	pub fn book_vwap(opposite: &Book, quantity: f64) -> Option<f64> {
+		let orders = opposite.orders.lock().unwrap();
+		let mut remaining = quantity;
+		let mut cost = 0.0;
+		for order in orders.iter().rev() {
+			let fill = order.quantity.min(remaining);
+			cost += fill * order.price;
+			remaining -= fill;
+			if remaining <= 0.0 {
+				return Some(cost / quantity);
+			}
+		}
+		None
+	}
+
+	/// How much better the trade did than taking the same quantity from the opposite Book,
+	/// per share. Positive when the RFQ was cheaper for the taker.
+	pub fn price_improvement(trade: &Trade, opposite: &Book) -> Option<f64> {
+		let vwap = RfqDesk::book_vwap(opposite, trade.quantity)?;
+		match trade.aggressor {
+			Some(TradeType::Ask) => Some(trade.price - vwap),
+			_ => Some(vwap - trade.price),
+		}
+	}
+
+	/// Appends accepted RFQ trades to the trade log and closes expired requests on an
+	/// interval determined by the duration parameter in milliseconds. RFQs trade in every
+	/// market State since they never touch the Books.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_rfq_task(queue: Arc<Queue>, trade_log: Arc<TradeLog>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			trade_log.add(queue.rfq.take_trades());
+			for request_id in queue.rfq.expire(get_time()) {
+				log_event!("Quote request {} expired", request_id);
+			}
+		}, duration)
+	}
+
+	fn send(&self, trader_id: &str, msg: serde_json::Value) {
+		self.outboxes.lock().unwrap().entry(String::from(trader_id)).or_default().push(msg);
+	}
+
+	fn side_str(trade_type: &TradeType) -> &'static str {
+		match trade_type {
+			TradeType::Bid => "bid",
+			TradeType::Ask => "ask",
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{Order, OrderType};
+
+	#[test]
+	fn test_rfq_workflow() {
+		let desk = RfqDesk::new(Duration::from_millis(100), Duration::from_millis(100));
+		let t0 = Duration::from_millis(1_000);
+		desk.register_maker(String::from("mm1"));
+		desk.register_maker(String::from("mm2"));
+
+		let id = desk.request(String::from("taker"), TradeType::Bid, 10.0, t0);
+		assert_eq!(desk.drain_messages("mm1")[0]["request_id"], id);
+		assert!(desk.quote(String::from("outsider"), id, 99.0, t0).is_err());
+		desk.quote(String::from("mm1"), id, 101.0, t0 + Duration::from_millis(50)).unwrap();
+		desk.quote(String::from("mm2"), id, 100.5, t0 + Duration::from_millis(60)).unwrap();
+		assert!(desk.quote(String::from("mm2"), id, 100.0, t0 + Duration::from_millis(150)).is_err());
+		assert_eq!(desk.drain_messages("taker").len(), 2);
+
+		let trade = desk.accept("taker", id, "mm2", t0 + Duration::from_millis(150)).unwrap();
+		assert_eq!((trade.bid_id.as_str(), trade.ask_id.as_str(), trade.price), ("taker", "mm2", 100.5));
+		assert_eq!(desk.drain_messages("mm2").last().unwrap()["msg_type"], "rfq_fill");
+		assert_eq!(desk.take_trades().len(), 1);
+		assert!(desk.get_request(id).is_none());
+
+		// Taking 10 from the book would have cost 5 @100 and 5 @102
+		let asks = Book::new(TradeType::Ask);
+		asks.add_order(Order::new(String::from("a1"), OrderType::Enter, TradeType::Ask, 100.0, 5.0)).unwrap();
+		asks.add_order(Order::new(String::from("a2"), OrderType::Enter, TradeType::Ask, 102.0, 5.0)).unwrap();
+		assert_eq!(RfqDesk::price_improvement(&trade, &asks), Some(0.5));
+	}
+
+	#[test]
+	fn test_rfq_expiry() {
+		let desk = RfqDesk::new(Duration::from_millis(100), Duration::from_millis(100));
+		let t0 = Duration::from_millis(1_000);
+		desk.register_maker(String::from("mm"));
+		let id = desk.request(String::from("taker"), TradeType::Ask, 1.0, t0);
+		desk.quote(String::from("mm"), id, 99.0, t0).unwrap();
+		assert!(desk.accept("mm", id, "mm", t0).is_err());
+		assert!(desk.accept("taker", id, "mm", t0 + Duration::from_millis(250)).is_err());
+		assert_eq!(desk.expire(t0 + Duration::from_millis(250)), vec![id]);
+	}
+}
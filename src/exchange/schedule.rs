@@ -0,0 +1,271 @@
+use crate::controller::{Task, State};
+use crate::exchange::order_book::Book;
+use crate::exchange::auction::Auction;
+use crate::exchange::expiry::ExpiryProcessor;
+use crate::exchange::queue::Queue;
+use crate::exchange::queue_processing::QueueProcessor;
+use crate::exchange::trade::TradeLog;
+use crate::utility::get_time;
+
+use std::sync::{Mutex, Arc};
+
+/// The steps of a trading day, each run by TradingSchedule::run_phase.
+/// PreOpen: orders accumulate for the opening auction
+/// OpeningAuction: the Books are uncrossed and continuous trading starts
+/// ClosingCall: orders accumulate for the closing auction
+/// ClosingAuction: the Books are uncrossed, the market closes and day orders are cancelled
+#[derive(Debug, Clone, PartialEq)]
+pub enum Phase {
+	PreOpen,
+	OpeningAuction,
+	ClosingCall,
+	ClosingAuction,
+}
+
+/// The phases of a trading day in milliseconds. Orders accumulate without matching
+/// during the pre-open, are uncrossed by the opening auction, trade continuously,
+/// accumulate again for the closing auction and the market then stays State::Closed,
+/// which nothing but the next pre-open leaves.
+/// pre_open: u64 -> length of the call phase before the opening auction
+/// continuous: u64 -> length of continuous trading
+/// closing_call: u64 -> length of the call phase before the closing auction
+/// closed: u64 -> time between the closing auction and the next pre-open
+#[derive(Debug, Clone)]
+pub struct TradingSchedule {
+	pub pre_open: u64,
+	pub continuous: u64,
+	pub closing_call: u64,
+	pub closed: u64,
+}
+
+impl TradingSchedule {
+	pub fn new(pre_open: u64, continuous: u64, closing_call: u64, closed: u64) -> TradingSchedule {
+		TradingSchedule {
+			pre_open,
+			continuous,
+			closing_call,
+			closed,
+		}
+	}
+
+	pub fn day_length(&self) -> u64 {
+		self.pre_open + self.continuous + self.closing_call + self.closed
+	}
+
+	/// The phases of 'days' trading days with the time in milliseconds each one starts
+	pub fn phases(&self, days: u64) -> Vec<(u64, Phase)> {
+		let mut phases = Vec::<(u64, Phase)>::new();
+		for day in 0..days {
+			let open = day * self.day_length();
+			let opening_auction = open + self.pre_open;
+			let closing_call = opening_auction + self.continuous;
+			let closing_auction = closing_call + self.closing_call;
+			phases.push((open, Phase::PreOpen));
+			phases.push((opening_auction, Phase::OpeningAuction));
+			phases.push((closing_call, Phase::ClosingCall));
+			phases.push((closing_auction, Phase::ClosingAuction));
+		}
+		phases
+	}
+
+	/// Outputs the delayed tasks that drive the market State through 'days' trading
+	/// days, to be dispatched asynchronously via the controller module. Day orders
+	/// are purged after each closing auction.
+	pub fn async_trading_day_tasks(&self,
+								   queue: Arc<Queue>,
+								   bids: Arc<Book>,
+								   asks: Arc<Book>,
+								   state: Arc<Mutex<State>>,
+								   trade_log: Arc<TradeLog>,
+								   days: u64) -> Vec<Task>
+	{
+		self.phases(days).into_iter().map(|(start, phase)| {
+			let (q, b, a, s, l) = (Arc::clone(&queue), Arc::clone(&bids), Arc::clone(&asks),
+								   Arc::clone(&state), Arc::clone(&trade_log));
+			Task::delay_task(move || {
+				TradingSchedule::run_phase(&phase, &q, Arc::clone(&b), Arc::clone(&a), &s, &l);
+			}, start)
+		}).collect()
+	}
+
+	/// Moves the market into the phase. Call phases accumulate orders without matching,
+	/// the opening auction uncrosses the Books into continuous trading and the closing
+	/// auction uncrosses them, closes the market and cancels the day orders.
+	pub fn run_phase(phase: &Phase,
+					 queue: &Queue,
+					 bids: Arc<Book>,
+					 asks: Arc<Book>,
+					 state: &Mutex<State>,
+					 trade_log: &TradeLog)
+	{
+		match phase {
+			Phase::PreOpen => {
+				log_event!("Pre-open @{:?}", get_time());
+				*state.lock().unwrap() = State::PreAuction;
+			},
+			Phase::OpeningAuction => {
+				TradingSchedule::call_auction(queue, bids, asks, state, trade_log, State::Process);
+			},
+			Phase::ClosingCall => {
+				log_event!("Closing call @{:?}", get_time());
+				*state.lock().unwrap() = State::PreAuction;
+			},
+			Phase::ClosingAuction => {
+				TradingSchedule::call_auction(queue, Arc::clone(&bids), Arc::clone(&asks), state, trade_log, State::Closed);
+				let reports = ExpiryProcessor::close_session(bids, asks, get_time());
+				log_event!("Market closed @{:?}, cancelled {} day orders", get_time(), reports.len());
+			},
+		}
+	}
+
+	/// Uncrosses the Books at a single equilibrium price, logs the trades, releases the
+	/// stops they set off onto the queue and moves the market to next_state. Returns the
+	/// clearing price if the Books crossed.
+	pub fn call_auction(queue: &Queue,
+						bids: Arc<Book>,
+						asks: Arc<Book>,
+						state: &Mutex<State>,
+						trade_log: &TradeLog,
+						next_state: State) -> Option<f64>
+	{
+		*state.lock().unwrap() = State::Auction;
+		let price = match Auction::uncross(Arc::clone(&bids), Arc::clone(&asks)) {
+			Some((price, trades)) => {
+				log_event!("Call auction cleared {} trades @{}", trades.len(), price);
+				QueueProcessor::release_stops(&bids, &asks, queue, &trades);
+				trade_log.add(trades);
+				Some(price)
+			},
+			None => {
+				log_event!("Call auction found no cross");
+				None
+			}
+		};
+		*state.lock().unwrap() = next_state;
+		price
+	}
+
+	/// The indicative price, matched volume and imbalance the call auction would clear
+	/// at if it ran now, formatted as a market data message.
+	pub fn indicative_json(bids: &Book, asks: &Book) -> Option<serde_json::Value> {
+		let eq = Auction::calc_equilibrium(bids, asks)?;
+		Some(json!({
+			"msg_type": "indicative",
+			"price": eq.price,
+			"volume": eq.volume,
+			"imbalance": eq.imbalance,
+		}))
+	}
+
+	/// Publishes indicative messages on an interval determined by the duration parameter in
+	/// milliseconds while the market is in a call phase.
+	/// Outputs a task that will be dispatched asynchronously via the controller module.
+	pub fn async_indicative_task(bids: Arc<Book>, asks: Arc<Book>, state: Arc<Mutex<State>>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			if *state.lock().unwrap() != State::PreAuction {
+				return;
+			}
+			if let Some(msg) = TradingSchedule::indicative_json(&bids, &asks) {
+				log_event!("{}", msg);
+			}
+		}, duration)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::exchange::circuit_breaker::{CircuitBreaker, BreakerAction};
+	use crate::exchange::queue::Queue;
+	use crate::order::{ExecType, TradeType, TimeInForce};
+	use crate::test_utils::{order, OrderSetup};
+	use std::time::Duration;
+
+	#[test]
+	fn test_call_auction() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		let (queue, state, trade_log) = (Queue::new(), Mutex::new(State::PreAuction), TradeLog::new());
+		bids.add_order(order("b", TradeType::Bid, 101.0, 10.0)).unwrap();
+		asks.add_order(order("a", TradeType::Ask, 101.0, 4.0)).unwrap();
+		bids.stops.add_order(order("stop", TradeType::Bid, 102.0, 1.0).with_exec_type(ExecType::Stop(100.5)));
+
+		let msg = TradingSchedule::indicative_json(&bids, &asks).unwrap();
+		assert_eq!(msg["price"], 101.0);
+		assert_eq!(msg["volume"], 4.0);
+		assert_eq!(msg["imbalance"], 6.0);
+
+		let price = TradingSchedule::call_auction(&queue, Arc::clone(&bids), Arc::clone(&asks), &state, &trade_log, State::Process);
+		assert_eq!(price, Some(101.0));
+		assert_eq!(trade_log.len(), 1);
+		assert_eq!(*state.lock().unwrap(), State::Process);
+		assert!(TradingSchedule::indicative_json(&bids, &asks).is_none());
+
+		// The auction price set off the buy stop, which waits in the queue to be processed
+		let released = queue.pop_all();
+		assert_eq!(released.len(), 1);
+		assert_eq!(released[0].trader_id, "stop");
+		assert!(bids.stops.is_empty());
+	}
+
+	#[test]
+	fn test_day_tasks() {
+		let schedule = TradingSchedule::new(10, 100, 10, 30);
+		assert_eq!(schedule.day_length(), 150);
+		let (queue, bids, asks, state) = crate::setup_exchange();
+		let trade_log = TradeLog::new();
+		let tasks = schedule.async_trading_day_tasks(Arc::clone(&queue), Arc::clone(&bids), Arc::clone(&asks),
+													 Arc::clone(&state), Arc::new(TradeLog::new()), 2);
+		assert_eq!(tasks.len(), 8);
+		let phases = schedule.phases(2);
+		let starts: Vec<u64> = phases.iter().map(|(start, _)| *start).collect();
+		assert_eq!(starts, [0, 10, 110, 120, 150, 160, 260, 270]);
+
+		// Run the first day's phases in order, resting a crossing pair before the opening
+		// auction and a day order during the closing call
+		let mut states = Vec::<State>::new();
+		for (_, phase) in &phases[..4] {
+			match phase {
+				Phase::PreOpen => {
+					bids.add_order(order("b", TradeType::Bid, 101.0, 10.0)).unwrap();
+					asks.add_order(order("a", TradeType::Ask, 101.0, 4.0)).unwrap();
+				},
+				Phase::ClosingCall => {
+					bids.add_order(order("day", TradeType::Bid, 99.0, 1.0).with_time_in_force(TimeInForce::Day)).unwrap();
+				},
+				_ => {},
+			}
+			TradingSchedule::run_phase(phase, &queue, Arc::clone(&bids), Arc::clone(&asks), &state, &trade_log);
+			states.push(state.lock().unwrap().clone());
+		}
+		assert_eq!(states, [State::PreAuction, State::Process, State::PreAuction, State::Closed]);
+
+		// Only the opening auction crossed and the close cancelled the day order
+		assert_eq!(trade_log.len(), 1);
+		assert!(bids.peek_id_pos(String::from("day")).is_none());
+		assert_eq!(bids.len(), 1);
+	}
+
+	#[test]
+	fn test_breaker_keeps_market_closed() {
+		let bids = Arc::new(Book::new(TradeType::Bid));
+		let asks = Arc::new(Book::new(TradeType::Ask));
+		let state = Mutex::new(State::Process);
+		let breaker = CircuitBreaker::new(0.05, Duration::from_millis(1_000),
+										  Duration::from_millis(500), BreakerAction::Halt);
+
+		// The closing auction runs while the breaker is tripped
+		breaker.trip(&state, Duration::from_millis(1_000));
+		TradingSchedule::call_auction(&Queue::new(), Arc::clone(&bids), Arc::clone(&asks), &state, &TradeLog::new(), State::Closed);
+		assert!(breaker.try_resume(&state, &Queue::new(), Arc::clone(&bids), Arc::clone(&asks), Duration::from_millis(2_000)).is_none());
+		assert_eq!(*state.lock().unwrap(), State::Closed);
+		assert!(!breaker.is_tripped());
+
+		// And a breaker tripping after the close doesn't reopen it either
+		breaker.trip(&state, Duration::from_millis(3_000));
+		assert_eq!(*state.lock().unwrap(), State::Closed);
+		assert!(breaker.try_resume(&state, &Queue::new(), bids, asks, Duration::from_millis(4_000)).is_none());
+		assert_eq!(*state.lock().unwrap(), State::Closed);
+	}
+}
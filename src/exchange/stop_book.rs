@@ -0,0 +1,175 @@
+use crate::order::{Order, OrderType, TradeType, ExecType, TimeInForce};
+use crate::exchange::auction::{MAX_PRICE, MIN_PRICE};
+use crate::exchange::trade::Trade;
+
+use std::sync::Mutex;
+
+/// The trigger book that holds Stop and StopLimit orders until the last trade
+/// price crosses their trigger. Buy stops trigger once the last price rises to
+/// or above their trigger, sell stops once it falls to or below theirs.
+/// book_type: TradeType{Bid, Ask} -> Side of the stops held in this book
+/// orders: Mutex<Vec<Order>> -> Threadsafe vector of untriggered stops in arrival order
+/// last_price: Mutex<Option<f64>> -> Threadsafe price of the last trade seen by the book
+pub struct StopBook {
+	pub book_type: TradeType,
+	pub orders: Mutex<Vec<Order>>,
+	pub last_price: Mutex<Option<f64>>,
+}
+
+impl StopBook {
+	pub fn new(book_type: TradeType) -> StopBook {
+		StopBook {
+			book_type,
+			orders: Mutex::new(Vec::<Order>::new()),
+			last_price: Mutex::new(None),
+		}
+	}
+
+	/// Adds a stop order to the trigger book. If the last trade price already crosses
+	/// the order's trigger it is converted and returned to be released immediately.
+	pub fn add_order(&self, order: Order) -> Option<Order> {
+		let last_price = *self.last_price.lock().unwrap();
+		if let Some(price) = last_price {
+			if self.is_triggered(&order, price) {
+				return Some(StopBook::convert_triggered(order));
+			}
+		}
+		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock stop book to add order");
+		// Orders are kept in arrival order so ties in trigger price release first come first serve
+		orders.push(order);
+		None
+	}
+
+	/// Replaces the stop order in the trigger book with the supplied 'order' of the same trader_id
+	pub fn update_order(&self, order: Order) -> Result<Option<Order>, &'static str> {
+		self.cancel_order_by_id(&order.trader_id)?;
+		Ok(self.add_order(order))
+	}
+
+	/// Cancels the existing stop order in the trigger book if it exists
+	pub fn cancel_order_by_id(&self, id: &str) -> Result<(), &'static str> {
+		let mut orders = self.orders.lock().expect("couldn't acquire lock cancelling stop order");
+		match orders.iter().position(|o| o.trader_id == id) {
+			Some(i) => {
+				orders.remove(i);
+				Ok(())
+			},
+			None => Err("ERROR: stop order not found to cancel"),
+		}
+	}
+
+	/// Records the trades' prices in order and returns every stop they set off. Stops
+	/// are released by proximity of their trigger to the market, buy stops lowest
+	/// trigger first and sell stops highest trigger first, with ties broken by arrival.
+	pub fn trigger(&self, trades: &[Trade]) -> Vec<Order> {
+		let mut released = Vec::<Order>::new();
+		for trade in trades {
+			*self.last_price.lock().unwrap() = Some(trade.price);
+			let mut orders = self.orders.lock().expect("ERROR: Couldn't lock stop book to trigger");
+			// Most trades set nothing off, so avoid rebuilding the book for them
+			if !orders.iter().any(|o| self.is_triggered(o, trade.price)) {
+				continue;
+			}
+			// Drain the triggered orders while preserving the arrival order of the rest
+			let (mut triggered, resting): (Vec<Order>, Vec<Order>) = orders.drain(..)
+				.partition(|o| self.is_triggered(o, trade.price));
+			*orders = resting;
+
+			// Stable sort keeps arrival order for stops with equal triggers
+			match self.book_type {
+				TradeType::Bid => triggered.sort_by(|a, b| a.trigger_price().partial_cmp(&b.trigger_price()).unwrap()),
+				TradeType::Ask => triggered.sort_by(|a, b| b.trigger_price().partial_cmp(&a.trigger_price()).unwrap()),
+			}
+			released.extend(triggered.into_iter().map(StopBook::convert_triggered));
+		}
+		released
+	}
+
+	/// Removes every stop matching the predicate from the trigger book
+	pub fn remove_orders<F>(&self, pred: F) -> Vec<Order> 
+	where F: Fn(&Order) -> bool
+	{
+		let mut orders = self.orders.lock().expect("ERROR: Couldn't lock stop book to remove orders");
+		let (removed, rest): (Vec<Order>, Vec<Order>) = orders.drain(..).partition(|o| pred(o));
+		*orders = rest;
+		removed
+	}
+
+	/// Utility to see depth of trigger book
+	pub fn len(&self) -> usize {
+		self.orders.lock().unwrap().len()
+	}
+
+	/// Utility to check if the trigger book has no resting stops
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	fn is_triggered(&self, order: &Order, last_price: f64) -> bool {
+		match (order.trigger_price(), &self.book_type) {
+			(Some(trigger), TradeType::Bid) => last_price >= trigger,
+			(Some(trigger), TradeType::Ask) => last_price <= trigger,
+			(None, _) => false,
+		}
+	}
+
+	/// A triggered Stop becomes an immediate-or-cancel market order priced to cross the
+	/// whole book, so whatever the book can't fill is cancelled rather than resting at
+	/// an extreme price. A triggered StopLimit becomes a limit order at its price. Both
+	/// enter as new orders.
+	fn convert_triggered(mut order: Order) -> Order {
+		if let ExecType::Stop(_) = order.exec_type {
+			order.price = match order.trade_type {
+				TradeType::Bid => MAX_PRICE,
+				TradeType::Ask => MIN_PRICE,
+			};
+			order.time_in_force = TimeInForce::ImmediateOrCancel;
+		}
+		order.exec_type = ExecType::Limit;
+		order.order_type = OrderType::Enter;
+		order
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{order, OrderSetup};
+
+	fn trade_at(price: f64) -> Trade {
+		Trade::new(String::from("b"), String::from("a"), Some(TradeType::Bid), price, 1.0)
+	}
+
+	#[test]
+	fn test_sell_stops_release_in_trigger_order() {
+		let stops = StopBook::new(TradeType::Ask);
+		assert!(stops.add_order(order("low", TradeType::Ask, 90.0, 5.0).with_exec_type(ExecType::Stop(95.0))).is_none());
+		assert!(stops.add_order(order("high_1", TradeType::Ask, 90.0, 5.0).with_exec_type(ExecType::StopLimit(98.0))).is_none());
+		assert!(stops.add_order(order("high_2", TradeType::Ask, 90.0, 5.0).with_exec_type(ExecType::Stop(98.0))).is_none());
+		assert!(stops.add_order(order("never", TradeType::Ask, 90.0, 5.0).with_exec_type(ExecType::Stop(80.0))).is_none());
+
+		assert!(stops.trigger(&[trade_at(99.0)]).is_empty());
+
+		let released = stops.trigger(&[trade_at(94.0)]);
+		let ids: Vec<&str> = released.iter().map(|o| o.trader_id.as_ref()).collect();
+		assert_eq!(ids, vec!["high_1", "high_2", "low"]);
+		assert_eq!(stops.len(), 1);
+
+		// StopLimit keeps its limit, Stop becomes a market order
+		assert_eq!(released[0].price, 90.0);
+		assert_eq!(released[1].price, MIN_PRICE);
+		assert!(released.iter().all(|o| o.exec_type == ExecType::Limit));
+		assert_eq!(released[0].time_in_force, TimeInForce::GoodTillCancel);
+		assert_eq!(released[1].time_in_force, TimeInForce::ImmediateOrCancel);
+	}
+
+	#[test]
+	fn test_buy_stop_triggers_on_entry() {
+		let stops = StopBook::new(TradeType::Bid);
+		stops.trigger(&[trade_at(101.0)]);
+		let order = stops.add_order(order("late", TradeType::Bid, 90.0, 5.0).with_exec_type(ExecType::Stop(100.0)));
+		assert_eq!(order.unwrap().price, MAX_PRICE);
+		assert!(stops.is_empty());
+	}
+}
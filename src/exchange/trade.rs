@@ -0,0 +1,92 @@
+use crate::order::TradeType;
+use crate::utility::get_time;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A record of a single fill between a resting order and an incoming order.
+/// bid_id: String -> trader_id of the buying side
+/// ask_id: String -> trader_id of the selling side
+/// aggressor: Option<TradeType> -> side of the incoming order that crossed the book, None in auctions
+/// price: f64 -> price the shares transacted at
+/// quantity: f64 -> amount of shares transacted
+/// time: Duration -> time since UNIX_EPOCH the fill occurred
+/// off_book: bool -> whether the fill happened away from the lit Books, e.g. in the dark pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+	pub bid_id: String,
+	pub ask_id: String,
+	pub aggressor: Option<TradeType>,
+	pub price: f64,
+	pub quantity: f64,
+	pub time: Duration,
+	pub off_book: bool,
+}
+
+impl Trade {
+	pub fn new(bid_id: String, ask_id: String, aggressor: Option<TradeType>, price: f64, quantity: f64) -> Trade {
+		Trade {
+			bid_id,
+			ask_id,
+			aggressor,
+			price,
+			quantity,
+			time: get_time(),
+			off_book: false,
+		}
+	}
+}
+
+/// A threadsafe append-only log of every trade the exchange has made, read by
+/// tasks that react to the trade stream.
+pub struct TradeLog {
+	trades: Mutex<Vec<Trade>>,
+}
+
+impl Default for TradeLog {
+	fn default() -> Self {
+		TradeLog::new()
+	}
+}
+
+impl TradeLog {
+	pub fn new() -> TradeLog {
+		TradeLog {
+			trades: Mutex::new(Vec::<Trade>::new()),
+		}
+	}
+
+	// New trades are appended to the end of the log
+	pub fn add(&self, trades: Vec<Trade>) {
+		let mut log = self.trades.lock().unwrap();
+		log.extend(trades);
+	}
+
+	// Returns a copy of every trade logged at or after index
+	pub fn since(&self, index: usize) -> Vec<Trade> {
+		let log = self.trades.lock().unwrap();
+		log.iter().skip(index).cloned().collect()
+	}
+
+	// Returns the price of the most recent lit trade. Off-book prints aren't public
+	// market data.
+	pub fn last_price(&self) -> Option<f64> {
+		self.trades.lock().unwrap().iter().rev().find(|t| !t.off_book).map(|t| t.price)
+	}
+
+	// Returns the fraction of the logged volume that traded off-book
+	pub fn off_book_share(&self) -> f64 {
+		let log = self.trades.lock().unwrap();
+		let total: f64 = log.iter().map(|t| t.quantity).sum();
+		let off_book: f64 = log.iter().filter(|t| t.off_book).map(|t| t.quantity).sum();
+		if total > 0.0 { off_book / total } else { 0.0 }
+	}
+
+	pub fn len(&self) -> usize {
+		self.trades.lock().unwrap().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
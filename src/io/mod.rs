@@ -1,5 +1,6 @@
 extern crate env_logger;
 extern crate ws;
 
+pub mod session;
 pub mod tcp_json;
 pub mod ws_json;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use crate::exchange::queue::Queue;
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Mutex;
+
+/// The sessions of a server routed to by trader_id. A trader_id belongs to the first
+/// connection that sends a message under it until that connection closes, so no other
+/// connection can claim it and receive its messages.
+/// sessions: Mutex<HashMap<String, (u64, T)>> -> connection id and sender per trader_id
+pub struct Sessions<T> {
+	sessions: Mutex<HashMap<String, (u64, T)>>,
+}
+
+impl<T> Default for Sessions<T> {
+	fn default() -> Self {
+		Sessions::new()
+	}
+}
+
+impl<T> Sessions<T> {
+	pub fn new() -> Sessions<T> {
+		Sessions {
+			sessions: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Binds the trader_id to the connection if no other connection holds it. Returns
+	/// false if the trader_id belongs to another connection.
+	pub fn bind(&self, trader_id: &str, connection: u64, sender: T) -> bool {
+		let mut sessions = self.sessions.lock().unwrap();
+		match sessions.get(trader_id) {
+			Some((owner, _)) => *owner == connection,
+			None => {
+				sessions.insert(String::from(trader_id), (connection, sender));
+				true
+			},
+		}
+	}
+
+	/// Frees every trader_id held by the closed connection
+	pub fn close(&self, connection: u64) {
+		self.sessions.lock().unwrap().retain(|_, (owner, _)| *owner != connection);
+	}
+
+	/// Sends every session the messages waiting for it at the RFQ desk. A message that
+	/// can't be sent goes back to the desk with the rest of the session's messages, in
+	/// order, for the trader's next connection, and the session is dropped.
+	pub fn route<F>(&self, queue: &Queue, send: F)
+	where F: Fn(&T, Value) -> Result<(), Value>
+	{
+		self.sessions.lock().unwrap().retain(|trader_id, (_, sender)| {
+			let mut messages = queue.rfq.drain_messages(trader_id).into_iter();
+			while let Some(msg) = messages.next() {
+				if let Err(msg) = send(sender, msg) {
+					queue.rfq.requeue_messages(trader_id, iter::once(msg).chain(messages).collect());
+					return false;
+				}
+			}
+			true
+		});
+	}
+
+	/// Utility to see how many trader_ids are bound
+	pub fn len(&self) -> usize {
+		self.sessions.lock().unwrap().len()
+	}
+
+	/// Utility to check if no trader_id is bound
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::TradeType;
+	use std::time::Duration;
+
+	#[test]
+	fn test_sessions_bind_and_route() {
+		let queue = Queue::new();
+		let sessions = Sessions::<bool>::new();
+		assert!(sessions.bind("mm", 1, false));
+		assert!(sessions.bind("mm", 1, false));
+		// Another connection can't take over the trader_id
+		assert!(!sessions.bind("mm", 2, true));
+		assert!(sessions.bind("taker", 2, true));
+
+		queue.rfq.register_maker(String::from("mm"));
+		queue.rfq.request(String::from("taker"), TradeType::Ask, 3.0, Duration::from_millis(0));
+		queue.rfq.request(String::from("taker"), TradeType::Bid, 3.0, Duration::from_millis(0));
+
+		// The closed connection's messages go back to the desk instead of being lost
+		sessions.route(&queue, |open, msg| if *open { Ok(()) } else { Err(msg) });
+		assert_eq!(sessions.len(), 1);
+		let waiting = queue.rfq.drain_messages("mm");
+		assert_eq!(waiting.len(), 2);
+		assert_eq!((&waiting[0]["trade_type"], &waiting[1]["trade_type"]), (&json!("ask"), &json!("bid")));
+
+		// Once its connection closes the trader_id is free again
+		assert!(!sessions.bind("taker", 3, true));
+		sessions.close(2);
+		assert!(sessions.is_empty());
+		assert!(sessions.bind("taker", 3, true));
+	}
+}
@@ -1,18 +1,35 @@
 use crate::exchange::order_processing::JsonOrder;
 use crate::exchange::queue::Queue;
 use crate::controller::Task;
+use crate::io::session::Sessions;
 
 use tokio::net::{TcpListener, TcpStream};
+use tokio::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 use tokio::prelude::*;
+use tokio_serde_json::{ReadJson, WriteJson};
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use serde_json::Value;
 use std::sync::Arc;
 
+// Sends every session the messages waiting for it at the RFQ desk, dropping the
+// sessions whose connection has closed
+fn route_messages(queue: &Queue, sessions: &Sessions<UnboundedSender<Value>>) {
+	sessions.route(queue, |tx, msg| tx.unbounded_send(msg).map_err(|e| e.into_inner()));
+}
+
 /// A simple tcp server that listens for incoming messages asynchronously. Each message
 /// is parsed from a JSON into the internal Order type used in the exchange. This function
-/// returns an AsnycTask to be used by the Controller module running Tokio.
+/// returns an AsnycTask to be used by the Controller module running Tokio. Each connection
+/// that has sent a message is a session routed to by its trader_id, which is sent its
+/// RFQ messages over the same connection. A trader_id belongs to the first connection
+/// that uses it until that connection closes, messages claiming it from any other
+/// connection are rejected.
 pub fn tcp_listener(queue: Arc<Queue>, address: String) -> Task { 
 	 // Bind a TcpListener to a local port
 	let addr = address.parse().unwrap();
 	let listener = TcpListener::bind(&addr).unwrap();
+	let sessions = Arc::new(Sessions::new());
+	let mut next_connection = 0;
 
     println!("Running server on {}", addr);
 
@@ -20,13 +37,36 @@ pub fn tcp_listener(queue: Arc<Queue>, address: String) -> Task {
 	let tcp_server = listener.incoming().for_each(move |socket| {
 		// Clone the queue into the closure
 		let queue = Arc::clone(&queue);
-
-		// Deserialize the stream from the socket
-        let deserialized = JsonOrder::deserialize(socket).map_err(|e| println!("ERR: {:?}", e));
+		let sessions = Arc::clone(&sessions);
+		let closed = Arc::clone(&sessions);
+		next_connection += 1;
+		let connection = next_connection;
+
+		// Deserialize the stream from the read half of the socket
+		let (reader, writer) = socket.split();
+        let deserialized = ReadJson::<_, Value>::new(FramedRead::new(reader, LengthDelimitedCodec::new()))
+        	.map_err(|e| log_event!("ERR: {:?}", e));
+
+        // Messages routed to the session are serialized onto the write half
+        let (tx, rx) = unbounded::<Value>();
+        let serialized = WriteJson::new(FramedWrite::new(writer, LengthDelimitedCodec::new()))
+        	.sink_map_err(|e| log_event!("ERR: {:?}", e));
+        tokio::spawn(rx.forward(serialized).map(|_| ()));
 
         // Spawn a task that converts JSON to an Order and adds to queue
         tokio::spawn(deserialized.for_each(move |msg| {
+            if let Some(trader_id) = msg["trader_id"].as_str() {
+            	if !sessions.bind(trader_id, connection, tx.clone()) {
+            		log_event!("Rejected message for {} from another connection", trader_id);
+            		return Ok(());
+            	}
+            }
             JsonOrder::process_new(msg, Arc::clone(&queue));
+            route_messages(&queue, &sessions);
+            Ok(())
+        }).then(move |_| {
+            // The connection's trader_ids are free once it closes
+            closed.close(connection);
             Ok(())
         }));
 
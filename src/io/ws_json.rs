@@ -1,5 +1,6 @@
 use crate::exchange::order_processing::JsonOrder;
 use crate::exchange::queue::Queue;
+use crate::io::session::Sessions;
 
 use std::thread;
 use std::sync::Arc;
@@ -7,10 +8,26 @@ use std::sync::Arc;
 use ws::{connect, listen, CloseCode, Sender, Handler, Message, Result, Handshake};
 use serde_json;
 
-// WebSocket handler
+// WebSocket handler. Every connection that has sent a message is a session routed to
+// by its trader_id. A trader_id belongs to the first connection that uses it until that
+// connection closes.
 struct Server {
     out: Sender,
     queue: Arc<Queue>,
+    sessions: Arc<Sessions<Sender>>,
+}
+
+impl Server {
+	// Sends every session the messages waiting for it at the RFQ desk, putting back the
+	// messages of sessions whose connection has closed
+	fn route_messages(&self) {
+		self.sessions.route(&self.queue, |out, msg| {
+			out.send(msg.to_string()).map_err(|e| {
+				log_event!("Couldn't route message: {:?}", e);
+				msg
+			})
+		});
+	}
 }
 
 /// A simple websocket server that listens for incoming messages asynchronously. Each message
@@ -26,7 +43,14 @@ impl Handler for Server {
 		if let Ok(text) = msg.into_text() {
 			match serde_json::from_str::<serde_json::Value>(&text) {
 				Ok(json) => {
+					if let Some(trader_id) = json["trader_id"].as_str() {
+						let connection = u64::from(self.out.connection_id());
+						if !self.sessions.bind(trader_id, connection, self.out.clone()) {
+							return self.out.send("trader_id belongs to another connection");
+						}
+					}
 		            JsonOrder::process_new(json, Arc::clone(&queue));
+		            self.route_messages();
 				},
 				Err(e) => {
 					println!("Could not parse JSON: {:?}", e);
@@ -36,15 +60,22 @@ impl Handler for Server {
 
         self.out.send("Parsed message!")
     }
+
+    fn on_close(&mut self, _: CloseCode, _: &str) {
+    	// The connection's trader_ids are free once it closes
+    	self.sessions.close(u64::from(self.out.connection_id()));
+    }
 }
 
 pub fn ws_listener(queue: Arc<Queue>, addr: &'static str) -> thread::JoinHandle<()> { 
 	env_logger::init();
+	let sessions = Arc::new(Sessions::new());
     thread::spawn(move || {
     	listen(addr, |out| {
 	         Server {
-	         	out: out,
+	         	out,
 	         	queue: Arc::clone(&queue),
+	         	sessions: Arc::clone(&sessions),
 	         }
 	    }).expect("Error with WS Server...");
     })
@@ -70,9 +101,9 @@ impl Handler for Client {
 /// A simple websocket server that sends jsons. Each message
 /// is parsed from a JSON into the internal Order type used in the exchange. 
 pub fn ws_send_json(json: serde_json::Value, address: &'static str) {
-	let _ws_client = connect(address, move |out| {
+	connect(address, move |out| {
 		Client { 
-			out: out, 
+			out, 
 			json: json.clone() 
 		}
     }).expect("Error with Client");
@@ -1,9 +1,22 @@
+/// println! for the exchange's messages, which in-process simulations can switch off on
+/// their thread with utility::set_logging since printing dominates long runs
+#[macro_export]
+macro_rules! log_event {
+	($($arg:tt)*) => {
+		if $crate::utility::logging() {
+			println!($($arg)*);
+		}
+	};
+}
+
 pub mod io;
 pub mod exchange;
 pub mod simulation;
 pub mod order;
 pub mod controller;
 pub mod utility;
+#[cfg(test)]
+mod test_utils;
 
 use crate::exchange::order_book::Book;
 use crate::order::TradeType;
@@ -4,7 +4,9 @@ extern crate tokio;
 use flow_rs::io::ws_json::ws_listener;
 use flow_rs::io::tcp_json::tcp_listener;
 use flow_rs::exchange::queue_processing::QueueProcessor;
-use flow_rs::exchange::auction::Auction;
+use flow_rs::exchange::expiry::ExpiryProcessor;
+use flow_rs::exchange::rfq::RfqDesk;
+use flow_rs::exchange::trade::TradeLog;
 use flow_rs::controller::Controller;
 
 use std::sync::Arc;
@@ -27,21 +29,110 @@ fn main() {
 
 	// create a task that processes order queue every queue_interval (milliseconds)
 	let queue_interval = 10;
+	let trade_log = Arc::new(TradeLog::new());
 	let queue_task = QueueProcessor::async_queue_task(Arc::clone(&queue), 
 		                                             Arc::clone(&bids_book), 
 		                                             Arc::clone(&asks_book),
 		                                             Arc::clone(&state),
+		                                             Arc::clone(&trade_log),
 		                                             queue_interval);
 	controller.push(queue_task);
 
+	// or, in block producer mode, replace the queue task with one that collects the queue into
+	// a block every block_interval (milliseconds) and sequences each block by gas price
+	// let block_interval = 1000;
+	// let producer = Arc::new(BlockProducer::new(Box::new(GasPriority{})));
+	// let block_task = BlockProducer::async_block_task(producer, Arc::clone(&queue), 
+	// 	                                             Arc::clone(&bids_book), Arc::clone(&asks_book),
+	// 	                                             Arc::clone(&state), Arc::clone(&trade_log), block_interval);
+	// controller.push(block_task);
+
+	// or, to run the exchange as an AMM venue, replace the queue task with one that swaps the
+	// queued orders against a constant product pool with a 0.3% fee
+	// let pool = Arc::new(Pool::new(String::from("amm"), 1_000.0, 100_000.0, 0.003).unwrap());
+	// let amm_task = AmmProcessor::async_amm_task(Arc::clone(&queue), pool, Arc::clone(&state),
+	// 	                                        Arc::clone(&trade_log), queue_interval);
+	// controller.push(amm_task);
+
+	// or, in hybrid mode, keep the queue task and let incoming orders split between the book
+	// and the pool at whichever gives the better price
+	// HybridRouter::attach(&bids_book, &asks_book, Arc::new(Pool::new(String::from("amm"), 1_000.0, 100_000.0, 0.003).unwrap()));
+
+	// create a task that cancels expired good-till-time orders every expiry_interval (milliseconds)
+	let expiry_interval = 1000;
+	let expiry_task = ExpiryProcessor::async_expiry_task(Arc::clone(&bids_book), 
+		                                                 Arc::clone(&asks_book), 
+		                                                 expiry_interval);
+	controller.push(expiry_task);
+
+	// create a task that halts the market for 30 seconds if the price moves 10% within a minute
+	// let breaker = Arc::new(CircuitBreaker::new(0.1, Duration::from_secs(60), 
+	// 	                                       Duration::from_secs(30), BreakerAction::Halt));
+	// let breaker_task = CircuitBreaker::async_breaker_task(breaker, Arc::clone(&queue), Arc::clone(&trade_log), 
+	// 	                                                  Arc::clone(&bids_book), Arc::clone(&asks_book),
+	// 	                                                  Arc::clone(&state), queue_interval);
+	// controller.push(breaker_task);
+
+	// create tasks that run the trading day: a 1 minute pre-open, opening auction, 8 hours of
+	// continuous trading, a 5 minute closing call and closing auction, repeated for 5 days
+	// let schedule = TradingSchedule::new(60 * 1000, 8 * 60 * 60 * 1000, 5 * 60 * 1000, 16 * 60 * 60 * 1000);
+	// for task in schedule.async_trading_day_tasks(Arc::clone(&queue), Arc::clone(&bids_book), Arc::clone(&asks_book), 
+	// 	                                         Arc::clone(&state), Arc::clone(&trade_log), 5) {
+	// 	controller.push(task);
+	// }
+	// let indicative_task = TradingSchedule::async_indicative_task(Arc::clone(&bids_book), 
+	// 	                                                         Arc::clone(&asks_book), 
+	// 	                                                         Arc::clone(&state), 1000);
+	// controller.push(indicative_task);
+
+	// create a task that closes the session and purges day orders every session_length (milliseconds)
+	// let session_length = 60 * 60 * 1000;
+	// let session_task = ExpiryProcessor::async_session_close_task(Arc::clone(&bids_book), 
+	// 	                                                         Arc::clone(&asks_book), 
+	// 	                                                         session_length);
+	// controller.push(session_task);
+
+	// create a task that clears the flow market's piecewise linear orders every flow_interval (milliseconds)
+	// let flow_interval = 500;
+	// let flow_task = FlowAuction::async_flow_auction_task(Arc::clone(&bids_book),
+	// 	                                                 Arc::clone(&asks_book),
+	// 	                                                 Arc::clone(&trade_log), flow_interval);
+	// controller.push(flow_task);
+
+	// create tasks that run 10 sealed-bid rounds, each a 2 second commit window followed by a
+	// 2 second reveal window and a batch auction, penalizing unrevealed commitments by 10
+	// queue.commits.set_penalty(Some(10.0));
+	// let sealed_round = SealedRound::new(2000, 2000);
+	// for task in sealed_round.async_round_tasks(Arc::clone(&queue), Arc::clone(&bids_book), Arc::clone(&asks_book),
+	// 	                                       Arc::clone(&state), Arc::clone(&trade_log), 10) {
+	// 	controller.push(task);
+	// }
+
+	// create a task that logs accepted RFQ trades and closes expired quote requests every
+	// rfq_interval (milliseconds), makers having 500ms to quote and takers 500ms more to accept
+	let rfq_interval = 100;
+	let rfq_task = RfqDesk::async_rfq_task(Arc::clone(&queue), Arc::clone(&trade_log), rfq_interval);
+	controller.push(rfq_task);
+
+	// create a task that crosses the dark pool's orders at the lit midpoint every dark_interval (milliseconds)
+	// let dark_interval = 100;
+	// let dark_task = DarkPool::async_dark_task(Arc::clone(&queue), Arc::clone(&bids_book), Arc::clone(&asks_book),
+	// 	                                      Arc::clone(&state), Arc::clone(&trade_log), dark_interval);
+	// controller.push(dark_task);
+
+	// create a task that clears the resting package orders every package_interval (milliseconds)
+	// let package_interval = 1000;
+	// let package_task = PackageBook::async_package_task(Arc::clone(&queue), Arc::clone(&state), package_interval);
+	// controller.push(package_task);
+
 	// Spawn the tcp server task that listens for incoming orders in JSON format
-	let tcp_server = tcp_listener(Arc::clone(&queue), format!("127.0.0.1:5000"));
+	let tcp_server = tcp_listener(Arc::clone(&queue), String::from("127.0.0.1:5000"));
 	controller.push(tcp_server);
 
 
 	// Spawn the websocket server thread that listens for incoming orders in JSON format
 	let address: &'static str = "127.0.0.1:3015";
-	let _ws_server = ws_listener(Arc::clone(&queue), &address);
+	let _ws_server = ws_listener(Arc::clone(&queue), address);
 	
 	// Loop forever asynchronously running tasks
 	controller.run();
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 /// Enum for matching over order types
 #[derive(Debug, PartialEq)]
 pub enum OrderType {
@@ -33,18 +35,139 @@ impl Clone for TradeType {
 	}
 }
 
+/// Enum for matching over how an order executes once it reaches the exchange.
+/// Stop(trigger) becomes a market order once the last trade price crosses trigger,
+/// StopLimit(trigger) becomes a limit order at the order's price instead.
+/// Pegged(peg) rests in the Book at a price that follows the lit touch.
+/// Flow(schedule) trades a piecewise linear schedule in the flow market's batches.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExecType {
+	Limit,
+	Stop(f64),
+	StopLimit(f64),
+	Pegged(Peg),
+	Flow(FlowSchedule),
+}
+
+/// The piecewise linear demand or supply schedule of a flow order. A bid trades
+/// at u_max at or below p_low, falling linearly to nothing at p_high. An ask trades
+/// nothing at or below p_low, rising linearly to u_max at p_high.
+/// p_low: f64 -> lower price of the linear segment
+/// p_high: f64 -> upper price of the linear segment
+/// u_max: f64 -> maximum quantity traded in a single batch
+#[derive(Debug, PartialEq, Clone)]
+pub struct FlowSchedule {
+	pub p_low: f64,
+	pub p_high: f64,
+	pub u_max: f64,
+}
+
+impl FlowSchedule {
+	pub fn new(p_low: f64, p_high: f64, u_max: f64) -> FlowSchedule {
+		FlowSchedule {
+			p_low,
+			p_high,
+			u_max,
+		}
+	}
+
+	/// The quantity the trade_type side of this schedule trades per batch at price.
+	/// A schedule with p_low == p_high is a step at that price.
+	pub fn rate(&self, trade_type: &TradeType, price: f64) -> f64 {
+		match trade_type {
+			TradeType::Bid if price <= self.p_low => self.u_max,
+			TradeType::Bid if price >= self.p_high => 0.0,
+			TradeType::Bid => self.u_max * (self.p_high - price) / (self.p_high - self.p_low),
+			TradeType::Ask if price >= self.p_high => self.u_max,
+			TradeType::Ask if price <= self.p_low => 0.0,
+			TradeType::Ask => self.u_max * (price - self.p_low) / (self.p_high - self.p_low),
+		}
+	}
+}
+
+/// Enum for matching over the reference price a pegged order follows
+/// Primary -> best price on the order's own side
+/// Market -> best price on the opposite side
+/// Midpoint -> halfway between the best bid and best ask
+#[derive(Debug, PartialEq, Clone)]
+pub enum PegType {
+	Primary,
+	Market,
+	Midpoint,
+}
+
+/// The parameters of a pegged order.
+/// peg_type: PegType{Primary, Market, Midpoint} -> reference price to follow
+/// offset: f64 -> distance from the reference, positive values make the order less aggressive
+/// cap: Option<f64> -> most aggressive price the order may be repriced to
+#[derive(Debug, PartialEq, Clone)]
+pub struct Peg {
+	pub peg_type: PegType,
+	pub offset: f64,
+	pub cap: Option<f64>,
+}
+
+impl Peg {
+	pub fn new(peg_type: PegType, offset: f64, cap: Option<f64>) -> Peg {
+		Peg {
+			peg_type,
+			offset,
+			cap,
+		}
+	}
+
+	/// Calculates the pegged price of an order on the trade_type side given the
+	/// best lit bid and ask. Returns None if the reference price doesn't exist.
+	pub fn price(&self, trade_type: &TradeType, best_bid: Option<f64>, best_ask: Option<f64>) -> Option<f64> {
+		let reference = match (&self.peg_type, trade_type) {
+			(PegType::Primary, TradeType::Bid) | (PegType::Market, TradeType::Ask) => best_bid?,
+			(PegType::Primary, TradeType::Ask) | (PegType::Market, TradeType::Bid) => best_ask?,
+			(PegType::Midpoint, _) => (best_bid? + best_ask?) / 2.0,
+		};
+		let price = match trade_type {
+			TradeType::Bid => reference - self.offset,
+			TradeType::Ask => reference + self.offset,
+		};
+		// The cap bounds how aggressive the order is allowed to become
+		Some(match (self.cap, trade_type) {
+			(Some(cap), TradeType::Bid) => price.min(cap),
+			(Some(cap), TradeType::Ask) => price.max(cap),
+			(None, _) => price,
+		})
+	}
+}
+
+/// Enum for matching over how long an order rests before the exchange cancels it
+/// GoodTillCancel -> rests until filled or cancelled by the trader
+/// GoodTillTime(expiry) -> cancelled once the time since UNIX_EPOCH passes expiry
+/// Day -> cancelled when the trading session closes
+/// ImmediateOrCancel -> trades what it can on entry and the remainder is cancelled
+#[derive(Debug, PartialEq, Clone)]
+pub enum TimeInForce {
+	GoodTillCancel,
+	GoodTillTime(Duration),
+	Day,
+	ImmediateOrCancel,
+}
+
 /// The internal data structure that the CDA market operates on. 
 /// trader_id: String -> identifier of the trader and their order
 /// order_type: OrderType{Enter, Update, Cancel} -> identifies how the order is used by the exchange
 /// trade_type: TradeType{Bid, Ask} -> decides which order book the order is placed in 
+/// exec_type: ExecType{Limit, Stop, StopLimit, Pegged, Flow} -> decides how the order is priced and where it rests
+/// time_in_force: TimeInForce{GoodTillCancel, GoodTillTime, Day, ImmediateOrCancel} -> decides when a resting order expires
 /// price: f64 -> trader's willing ness to buy or sell
 /// quantity: f64 -> amount of shares to buy/sell
+/// gas_price: f64 -> fee bid for inclusion priority when orders are sequenced into blocks
 pub struct Order {
 	pub trader_id: String,		
 	pub order_type: OrderType,	
 	pub trade_type: TradeType,  
+	pub exec_type: ExecType,
+	pub time_in_force: TimeInForce,
 	pub price: f64,				
 	pub quantity: f64,			
+	pub gas_price: f64,
 }
 
 impl Order {
@@ -54,8 +177,43 @@ impl Order {
     		trader_id: t_id,		
 			order_type: o_t,	
 			trade_type: t_t,  
+			exec_type: ExecType::Limit,
+			time_in_force: TimeInForce::GoodTillCancel,
 			price: p,				
 			quantity: q,	
+			gas_price: 0.0,
+    	}
+    }
+
+    /// Returns the trigger price if this is a Stop or StopLimit order
+    pub fn trigger_price(&self) -> Option<f64> {
+    	match self.exec_type {
+    		ExecType::Limit | ExecType::Pegged(_) | ExecType::Flow(_) => None,
+    		ExecType::Stop(trigger) | ExecType::StopLimit(trigger) => Some(trigger),
+    	}
+    }
+
+    /// Returns the peg if this is a Pegged order
+    pub fn peg(&self) -> Option<&Peg> {
+    	match self.exec_type {
+    		ExecType::Pegged(ref peg) => Some(peg),
+    		_ => None,
+    	}
+    }
+
+    /// Returns the flow schedule if this is a Flow order
+    pub fn flow(&self) -> Option<&FlowSchedule> {
+    	match self.exec_type {
+    		ExecType::Flow(ref schedule) => Some(schedule),
+    		_ => None,
+    	}
+    }
+
+    /// Returns true if the order is GoodTillTime and its expiry is at or before 'now'
+    pub fn is_expired(&self, now: Duration) -> bool {
+    	match self.time_in_force {
+    		TimeInForce::GoodTillTime(expiry) => expiry <= now,
+    		_ => false,
     	}
     }
 
@@ -87,6 +245,38 @@ mod tests {
 		assert_eq!(order.trade_type, TradeType::Bid);
 		assert_eq!(order.price, 50.0);
 		assert_eq!(order.quantity, 500.0);
+		assert_eq!(order.exec_type, ExecType::Limit);
+		assert_eq!(order.trigger_price(), None);
+		assert_eq!(order.time_in_force, TimeInForce::GoodTillCancel);
+		assert!(!order.is_expired(Duration::from_secs(u64::MAX)));
+	}
+
+	#[test]
+	fn test_peg_price() {
+		let mid = Peg::new(PegType::Midpoint, 0.0, None);
+		assert_eq!(mid.price(&TradeType::Bid, Some(99.0), Some(101.0)), Some(100.0));
+		assert_eq!(mid.price(&TradeType::Ask, Some(99.0), None), None);
+
+		let primary = Peg::new(PegType::Primary, 0.5, None);
+		assert_eq!(primary.price(&TradeType::Ask, Some(99.0), Some(101.0)), Some(101.5));
+
+		let market = Peg::new(PegType::Market, 1.0, Some(98.0));
+		assert_eq!(market.price(&TradeType::Bid, Some(95.0), Some(101.0)), Some(98.0));
+		assert_eq!(market.price(&TradeType::Bid, Some(95.0), Some(98.5)), Some(97.5));
+	}
+
+	#[test]
+	fn test_flow_rate() {
+		let schedule = FlowSchedule::new(90.0, 110.0, 10.0);
+		assert_eq!(schedule.rate(&TradeType::Bid, 80.0), 10.0);
+		assert_eq!(schedule.rate(&TradeType::Bid, 105.0), 2.5);
+		assert_eq!(schedule.rate(&TradeType::Ask, 105.0), 7.5);
+		assert_eq!(schedule.rate(&TradeType::Ask, 120.0), 10.0);
+
+		let step = FlowSchedule::new(100.0, 100.0, 10.0);
+		assert_eq!(step.rate(&TradeType::Bid, 100.0), 10.0);
+		assert_eq!(step.rate(&TradeType::Ask, 100.0), 10.0);
+		assert_eq!(step.rate(&TradeType::Ask, 99.9), 0.0);
 	}
 }
 
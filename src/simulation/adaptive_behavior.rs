@@ -0,0 +1,420 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::simulation::strategy::{MarketEvent, Strategy};
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Number of candidate prices a GD agent evaluates between its limit and the price bound
+const GD_GRID: usize = 200;
+
+/// Cliff's Zero Intelligence Plus trader with one unit to trade. The agent shouts at its
+/// limit adjusted by a profit margin, price = limit * (1 + margin), and moves the margin
+/// after every shout it observes. A seller raises its margin when a trade happens at or
+/// above its price and lowers it when an ask at or below its price is rejected or a bid
+/// below its price is accepted, mirrored for a buyer. Each adjustment moves the price
+/// toward a perturbed target around the observed price by the Widrow-Hoff rule with
+/// momentum. When the best bid changes, a seller priced below it raises toward it, and
+/// when the best ask changes a buyer priced above it lowers toward it. Margins never let
+/// the price cross the limit.
+/// trader_id: String -> trader_id the agent's orders are sent under
+/// trade_type: TradeType -> Bid for a buyer, Ask for a seller
+/// limit: f64 -> the buyer's private value or the seller's cost
+/// margin: f64 -> at least 0 for a seller, between -1 and 0 for a buyer
+/// beta: f64 -> learning rate, drawn from 0.1 to 0.5
+/// gamma: f64 -> momentum, drawn from 0 to 0.1
+/// traded: bool -> whether the agent's unit has traded
+pub struct ZipAgent {
+	pub trader_id: String,
+	pub trade_type: TradeType,
+	pub limit: f64,
+	pub margin: f64,
+	pub beta: f64,
+	pub gamma: f64,
+	pub traded: bool,
+	momentum: f64,
+	resting: bool,
+	quote: Option<f64>,
+	rng: StdRng,
+}
+
+impl ZipAgent {
+	pub fn new(trader_id: String, trade_type: TradeType, limit: f64, mut rng: StdRng) -> ZipAgent {
+		let margin = match trade_type {
+			TradeType::Bid => -rng.gen_range(0.05, 0.35),
+			TradeType::Ask => rng.gen_range(0.05, 0.35),
+		};
+		ZipAgent {
+			trader_id,
+			trade_type,
+			limit,
+			margin,
+			beta: rng.gen_range(0.1, 0.5),
+			gamma: rng.gen_range(0.0, 0.1),
+			traded: false,
+			momentum: 0.0,
+			resting: false,
+			quote: None,
+			rng,
+		}
+	}
+
+	/// The price the agent currently shouts at
+	pub fn price(&self) -> f64 {
+		self.limit * (1.0 + self.margin)
+	}
+
+	// Moves the price toward a target above (raise) or below the observed price q
+	fn adjust(&mut self, q: f64, raise: bool) {
+		let relative = if raise { self.rng.gen_range(1.0, 1.05) } else { self.rng.gen_range(0.95, 1.0) };
+		let absolute = if raise { self.rng.gen_range(0.0, 0.05) } else { -self.rng.gen_range(0.0, 0.05) };
+		let target = relative * q + absolute;
+		let delta = self.beta * (target - self.price());
+		self.momentum = self.gamma * self.momentum + (1.0 - self.gamma) * delta;
+		let margin = (self.price() + self.momentum) / self.limit - 1.0;
+		self.margin = match self.trade_type {
+			TradeType::Bid => margin.clamp(-1.0, 0.0),
+			TradeType::Ask => margin.max(0.0),
+		};
+	}
+
+	// Cliff's update rules for a shout of side at price q that was accepted or rejected
+	fn observe(&mut self, side: &TradeType, q: f64, accepted: bool) {
+		let p = self.price();
+		match (&self.trade_type, accepted) {
+			(TradeType::Ask, true) if p <= q => self.adjust(q, true),
+			(TradeType::Ask, true) if *side == TradeType::Bid && !self.traded => self.adjust(q, false),
+			(TradeType::Ask, false) if *side == TradeType::Ask && p >= q && !self.traded => self.adjust(q, false),
+			(TradeType::Bid, true) if p >= q => self.adjust(q, false),
+			(TradeType::Bid, true) if *side == TradeType::Ask && !self.traded => self.adjust(q, true),
+			(TradeType::Bid, false) if *side == TradeType::Bid && p <= q && !self.traded => self.adjust(q, true),
+			_ => {},
+		}
+	}
+
+	// A resting quote on the other side at q that the agent's price gives away surplus to
+	fn observe_quote(&mut self, q: f64) {
+		if self.traded {
+			return;
+		}
+		match self.trade_type {
+			TradeType::Ask if self.price() < q => self.adjust(q, true),
+			TradeType::Bid if self.price() > q => self.adjust(q, false),
+			_ => {},
+		}
+	}
+}
+
+impl Strategy for ZipAgent {
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		if self.traded {
+			return Vec::new();
+		}
+		let order_type = if self.resting { OrderType::Update } else { OrderType::Enter };
+		self.resting = true;
+		vec![Order::new(self.trader_id.clone(), order_type, self.trade_type.clone(), self.price(), 1.0)]
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		match event {
+			MarketEvent::Trade(trade) => {
+				if trade.bid_id == self.trader_id || trade.ask_id == self.trader_id {
+					self.traded = true;
+					self.resting = false;
+				}
+				if let Some(side) = &trade.aggressor {
+					self.observe(side, trade.price, true);
+				}
+			},
+			MarketEvent::Shout { trade_type, price } => self.observe(trade_type, *price, false),
+			MarketEvent::Quote { bid, ask } => {
+				let opposite = match self.trade_type {
+					TradeType::Ask => bid,
+					TradeType::Bid => ask,
+				};
+				// Only a new quote is news, the same quote is repeated after every step
+				if *opposite != self.quote {
+					self.quote = *opposite;
+					if let Some(q) = self.quote {
+						self.observe_quote(q);
+					}
+				}
+			},
+			_ => {},
+		}
+	}
+
+	fn active(&self) -> bool {
+		!self.traded
+	}
+}
+
+// A shout remembered by a GD agent
+struct Observation {
+	trade_type: TradeType,
+	price: f64,
+	accepted: bool,
+}
+
+/// A Gjerstad-Dickhaut trader with one unit to trade. The agent forms a belief of how
+/// likely a shout at each price is to be accepted from the most recent shouts and trades,
+/// and shouts at the price maximizing its expected profit. A seller believes an ask at a
+/// is accepted with probability (TA + B) / (TA + B + RA), where TA counts accepted asks at
+/// or above a, B bids at or above a and RA rejected asks at or below a. Mirrored for a
+/// buyer. The best bid and ask currently resting count as unaccepted shouts alongside the
+/// history. Where nothing was seen the belief is even, so without any history the agent
+/// shouts at the price bound.
+/// trader_id: String -> trader_id the agent's orders are sent under
+/// trade_type: TradeType -> Bid for a buyer, Ask for a seller
+/// limit: f64 -> the buyer's private value or the seller's cost
+/// min_price, max_price: f64 -> the range shouts are chosen from
+/// memory: usize -> number of recent shouts the belief is formed from
+/// traded: bool -> whether the agent's unit has traded
+pub struct GdAgent {
+	pub trader_id: String,
+	pub trade_type: TradeType,
+	pub limit: f64,
+	pub min_price: f64,
+	pub max_price: f64,
+	pub memory: usize,
+	pub traded: bool,
+	history: VecDeque<Observation>,
+	quotes: Vec<Observation>,
+	resting: bool,
+}
+
+impl GdAgent {
+	pub fn new(trader_id: String, trade_type: TradeType, limit: f64, bounds: (f64, f64), memory: usize) -> GdAgent {
+		GdAgent {
+			trader_id,
+			trade_type,
+			limit,
+			min_price: bounds.0,
+			max_price: bounds.1,
+			memory,
+			traded: false,
+			history: VecDeque::new(),
+			quotes: Vec::new(),
+			resting: false,
+		}
+	}
+
+	/// The agent's belief that its shout at price would be accepted
+	pub fn belief(&self, price: f64) -> f64 {
+		let count = |pred: &dyn Fn(&Observation) -> bool| self.observations().filter(|o| pred(o)).count() as f64;
+		let (taken, opposite, rejected) = match self.trade_type {
+			TradeType::Ask => (count(&|o| o.trade_type == TradeType::Ask && o.accepted && o.price >= price),
+							   count(&|o| o.trade_type == TradeType::Bid && o.price >= price),
+							   count(&|o| o.trade_type == TradeType::Ask && !o.accepted && o.price <= price)),
+			TradeType::Bid => (count(&|o| o.trade_type == TradeType::Bid && o.accepted && o.price <= price),
+							   count(&|o| o.trade_type == TradeType::Ask && o.price <= price),
+							   count(&|o| o.trade_type == TradeType::Bid && !o.accepted && o.price >= price)),
+		};
+		if taken + opposite + rejected == 0.0 {
+			return 0.5;
+		}
+		(taken + opposite) / (taken + opposite + rejected)
+	}
+
+	/// The price maximizing expected profit, surplus times belief, over a grid of prices
+	/// between the agent's limit and the price bound and the prices remembered within it
+	pub fn price(&self) -> f64 {
+		let (from, to) = match self.trade_type {
+			TradeType::Bid => (self.limit, self.min_price),
+			TradeType::Ask => (self.limit, self.max_price),
+		};
+		let grid = (0..=GD_GRID).map(|i| from + (to - from) * i as f64 / GD_GRID as f64);
+		let (low, high) = (from.min(to), from.max(to));
+		let seen = self.observations().map(|o| o.price).filter(|p| *p >= low && *p <= high);
+		let mut best = (from, 0.0);
+		for price in grid.chain(seen) {
+			let expected = (price - self.limit).abs() * self.belief(price);
+			if expected > best.1 {
+				best = (price, expected);
+			}
+		}
+		best.0
+	}
+
+	// The remembered shouts followed by the resting quotes
+	fn observations(&self) -> impl Iterator<Item = &Observation> {
+		self.history.iter().chain(self.quotes.iter())
+	}
+
+	fn remember(&mut self, trade_type: TradeType, price: f64, accepted: bool) {
+		self.history.push_back(Observation { trade_type, price, accepted });
+		while self.history.len() > self.memory {
+			self.history.pop_front();
+		}
+	}
+}
+
+impl Strategy for GdAgent {
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		if self.traded {
+			return Vec::new();
+		}
+		let order_type = if self.resting { OrderType::Update } else { OrderType::Enter };
+		self.resting = true;
+		vec![Order::new(self.trader_id.clone(), order_type, self.trade_type.clone(), self.price(), 1.0)]
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		match event {
+			MarketEvent::Trade(trade) => {
+				if trade.bid_id == self.trader_id || trade.ask_id == self.trader_id {
+					self.traded = true;
+					self.resting = false;
+				}
+				// A trade is both a bid and an ask accepted at its price
+				self.remember(TradeType::Bid, trade.price, true);
+				self.remember(TradeType::Ask, trade.price, true);
+			},
+			MarketEvent::Shout { trade_type, price } => self.remember(trade_type.clone(), *price, false),
+			// Replaced on every quote rather than remembered, so a quote resting for many
+			// steps isn't counted again each time
+			MarketEvent::Quote { bid, ask } => {
+				self.quotes = bid.iter().map(|&price| Observation { trade_type: TradeType::Bid, price, accepted: false })
+								 .chain(ask.iter().map(|&price| Observation { trade_type: TradeType::Ask, price, accepted: false }))
+								 .collect();
+			},
+			_ => {},
+		}
+	}
+
+	fn active(&self) -> bool {
+		!self.traded
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::exchange::trade::Trade;
+	use crate::simulation::harness::Harness;
+	use crate::simulation::seed::ExperimentSeed;
+	use crate::simulation::strategy::StrategyRunner;
+	use crate::simulation::zic_behavior::SupplyDemand;
+
+	// Total profit of the traders in the trades, given each trader's side and limit
+	fn surplus(trades: &[Trade], limits: &[(String, TradeType, f64)]) -> f64 {
+		trades.iter().map(|t| {
+			limits.iter().map(|(id, side, limit)| match side {
+				TradeType::Bid if *id == t.bid_id => limit - t.price,
+				TradeType::Ask if *id == t.ask_id => t.price - limit,
+				_ => 0.0,
+			}).sum::<f64>()
+		}).sum()
+	}
+
+	#[test]
+	fn test_zip_margins() {
+		let rng = || ExperimentSeed::new(1).stream("zip");
+		let mut seller = ZipAgent::new(String::from("s"), TradeType::Ask, 100.0, rng());
+		let mut buyer = ZipAgent::new(String::from("b"), TradeType::Bid, 100.0, rng());
+		assert!(seller.price() > 100.0 && buyer.price() < 100.0);
+
+		// A trade well above the seller's price raises its margin, and it never prices below cost
+		let before = seller.margin;
+		let mut trade = Trade::new(String::from("x"), String::from("y"), Some(TradeType::Bid), 150.0, 1.0);
+		seller.on_event(&MarketEvent::Trade(trade.clone()));
+		assert!(seller.margin > before);
+		for _ in 0..100 {
+			seller.on_event(&MarketEvent::Shout { trade_type: TradeType::Ask, price: 50.0 });
+		}
+		assert_eq!(seller.margin, 0.0);
+
+		// A buyer whose bid would be below an accepted ask raises its bid toward it
+		let before = buyer.price();
+		trade.aggressor = Some(TradeType::Ask);
+		trade.price = 99.0;
+		buyer.on_event(&MarketEvent::Trade(trade));
+		assert!(buyer.price() > before && buyer.price() <= 100.0);
+	}
+
+	#[test]
+	fn test_zip_quotes() {
+		let rng = || ExperimentSeed::new(1).stream("zip");
+		let mut seller = ZipAgent::new(String::from("s"), TradeType::Ask, 100.0, rng());
+		let mut buyer = ZipAgent::new(String::from("b"), TradeType::Bid, 100.0, rng());
+
+		// A seller priced below the best bid raises toward it, the best ask doesn't move it
+		let before = seller.price();
+		seller.on_event(&MarketEvent::Quote { bid: None, ask: Some(before - 10.0) });
+		assert_eq!(seller.price(), before);
+		seller.on_event(&MarketEvent::Quote { bid: Some(before + 20.0), ask: None });
+		assert!(seller.price() > before);
+
+		// The same quote again isn't news
+		let raised = seller.price();
+		seller.on_event(&MarketEvent::Quote { bid: Some(before + 20.0), ask: None });
+		assert_eq!(seller.price(), raised);
+
+		// A bid below the seller's price leaves it alone
+		let before = seller.price();
+		seller.on_event(&MarketEvent::Quote { bid: Some(before - 1.0), ask: None });
+		assert_eq!(seller.price(), before);
+
+		// A buyer priced above the best ask lowers toward it
+		let before = buyer.price();
+		buyer.on_event(&MarketEvent::Quote { bid: None, ask: Some(before - 20.0) });
+		assert!(buyer.price() < before && buyer.price() >= 0.0);
+	}
+
+	#[test]
+	fn test_gd_belief() {
+		let mut seller = GdAgent::new(String::from("s"), TradeType::Ask, 50.0, (1.0, 200.0), 20);
+		assert_eq!(seller.price(), 200.0);
+		seller.on_event(&MarketEvent::Shout { trade_type: TradeType::Ask, price: 120.0 });
+		seller.on_event(&MarketEvent::Trade(Trade::new(String::from("x"), String::from("y"), None, 100.0, 1.0)));
+		assert_eq!(seller.belief(90.0), 1.0);
+		// Nothing was seen between the trade and the rejected ask
+		assert_eq!(seller.belief(110.0), 0.5);
+		assert_eq!(seller.belief(130.0), 0.0);
+		// Asking at the last trade price maximizes the expected profit
+		assert_eq!(seller.price(), 100.0);
+
+		// A bid resting at 105 counts as an unaccepted bid, so asks up to it now look likely
+		seller.on_event(&MarketEvent::Quote { bid: Some(105.0), ask: None });
+		assert_eq!(seller.belief(105.0), 1.0);
+		assert_eq!(seller.price(), 105.0);
+
+		// An ask resting at 102 counts as an unaccepted ask and replaces the earlier quote,
+		// so the seller is back to asking at the last trade price
+		seller.on_event(&MarketEvent::Quote { bid: None, ask: Some(102.0) });
+		assert_eq!(seller.belief(102.0), 0.0);
+		assert_eq!(seller.price(), 100.0);
+	}
+
+	#[test]
+	fn test_adaptive_agents_efficiency() {
+		let seed = ExperimentSeed::new(9);
+		let market = SupplyDemand::linear(20, (60.0, 140.0), (60.0, 140.0));
+		let eq = market.equilibrium().unwrap();
+		let limits = market.agents(|id, side, limit| (id, side, limit));
+
+		let mut zip = market.agents(|id, side, limit| {
+			let rng = seed.stream(&id);
+			ZipAgent::new(id, side, limit, rng)
+		});
+		let mut gd = market.agents(|id, side, limit| GdAgent::new(id, side, limit, (1.0, 200.0), 30));
+
+		for (name, trades) in [("zip", run(&mut zip, seed)), ("gd", run(&mut gd, seed))] {
+			assert!(!trades.is_empty());
+			// Trades at a loss would make the extracted surplus exceed the equilibrium's
+			let extracted = surplus(&trades, &limits);
+			assert!(extracted <= eq.surplus + 0.000_001);
+			assert!(extracted > 0.8 * eq.surplus, "{} efficiency {}", name, extracted / eq.surplus);
+		}
+	}
+
+	fn run<S: Strategy>(agents: &mut [S], seed: ExperimentSeed) -> Vec<Trade> {
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		StrategyRunner::in_process(&mut harness, agents, 1, 1_000, seed);
+		harness.trade_log.since(0)
+	}
+
+}
@@ -0,0 +1,213 @@
+use rand::Rng;
+use rand::distributions::Exp;
+use rand::rngs::StdRng;
+use std::time::Duration;
+
+/// A stochastic process of order arrival times, rates in arrivals per second.
+/// Poisson: arrivals at a constant rate with exponential gaps between them
+/// Piecewise: a Poisson process whose rate changes over the day, each (start_ms, rate)
+/// holding from start_ms until the next, e.g. busy opens and closes
+/// Hawkes: a self-exciting process. Every arrival raises the intensity by alpha, which
+/// decays back to base at rate beta per second, so arrivals cluster. alpha < beta keeps
+/// it stationary with a mean rate of base / (1 - alpha / beta).
+/// In experiment configs the process is tagged by type, e.g. {"type": "poisson", "rate": 100}.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ArrivalProcess {
+	Poisson { rate: f64 },
+	Piecewise { rates: Vec<(u64, f64)> },
+	Hawkes { base: f64, alpha: f64, beta: f64 },
+}
+
+impl ArrivalProcess {
+	/// The process's arrival times drawn from rng. Panics if the process is invalid, see validate.
+	pub fn arrivals(&self, rng: StdRng) -> Arrivals {
+		if let Err(e) = self.validate() {
+			panic!("invalid arrival process {:?}: {}", self, e);
+		}
+		Arrivals {
+			process: self.clone(),
+			rng,
+			time: 0.0,
+			excitation: 0.0,
+		}
+	}
+
+	/// Checks the parameters can be simulated. Rates must be finite and non-negative, a
+	/// Piecewise schedule's starts strictly ascending and a Hawkes process needs base > 0
+	/// and 0 <= alpha < beta to be stationary.
+	pub fn validate(&self) -> Result<(), &'static str> {
+		let rate_ok = |rate: f64| rate.is_finite() && rate >= 0.0;
+		match self {
+			ArrivalProcess::Poisson { rate } => {
+				if !rate_ok(*rate) {
+					return Err("poisson rate must be finite and non-negative");
+				}
+			},
+			ArrivalProcess::Piecewise { rates } => {
+				if !rates.iter().all(|(_, rate)| rate_ok(*rate)) {
+					return Err("piecewise rates must be finite and non-negative");
+				}
+				if !rates.windows(2).all(|w| w[0].0 < w[1].0) {
+					return Err("piecewise starts must be strictly ascending");
+				}
+			},
+			ArrivalProcess::Hawkes { base, alpha, beta } => {
+				if !(base.is_finite() && *base > 0.0) {
+					return Err("hawkes base must be finite and greater than zero");
+				}
+				if !(beta.is_finite() && *alpha >= 0.0 && alpha < beta) {
+					return Err("hawkes needs 0 <= alpha < beta");
+				}
+			},
+		}
+		Ok(())
+	}
+
+	// The intensity of a Piecewise process at t seconds
+	fn piecewise_rate(rates: &[(u64, f64)], t: f64) -> f64 {
+		rates.iter().rev().find(|(start, _)| (*start as f64) / 1_000.0 <= t).map_or(0.0, |(_, rate)| *rate)
+	}
+}
+
+/// The arrival times of an ArrivalProcess in order, starting from time 0. Time-varying
+/// and self-exciting processes are simulated by thinning: candidates are drawn at an
+/// upper bound of the intensity and each is kept with probability intensity / bound.
+pub struct Arrivals {
+	process: ArrivalProcess,
+	rng: StdRng,
+	// Seconds since the start of the last arrival
+	time: f64,
+	// The Hawkes intensity above base at time
+	excitation: f64,
+}
+
+impl Arrivals {
+	/// The time of the next arrival, None if no more arrivals can happen
+	pub fn next_arrival(&mut self) -> Option<Duration> {
+		match self.process.clone() {
+			ArrivalProcess::Poisson { rate } => {
+				if rate <= 0.0 {
+					return None;
+				}
+				self.time += self.rng.sample(Exp::new(rate));
+			},
+			ArrivalProcess::Piecewise { rates } => {
+				let bound = rates.iter().map(|(_, r)| *r).fold(0.0, f64::max);
+				if bound <= 0.0 {
+					return None;
+				}
+				loop {
+					self.time += self.rng.sample(Exp::new(bound));
+					let rate = ArrivalProcess::piecewise_rate(&rates, self.time);
+					// Past the last positive rate nothing arrives again
+					if rate <= 0.0 && rates.iter().all(|(start, r)| *r <= 0.0 || (*start as f64) / 1_000.0 <= self.time) {
+						return None;
+					}
+					if self.rng.gen::<f64>() * bound < rate {
+						break;
+					}
+				}
+			},
+			ArrivalProcess::Hawkes { base, alpha, beta } => {
+				if base <= 0.0 && self.excitation <= 0.0 {
+					return None;
+				}
+				loop {
+					// The intensity only decays until the next arrival so its current value bounds it
+					let bound = base + self.excitation;
+					let wait = self.rng.sample(Exp::new(bound));
+					self.time += wait;
+					self.excitation *= (-beta * wait).exp();
+					if self.rng.gen::<f64>() * bound < base + self.excitation {
+						self.excitation += alpha;
+						break;
+					}
+				}
+			},
+		}
+		Some(Duration::from_micros((self.time * 1_000_000.0) as u64))
+	}
+
+	/// Every arrival up to until
+	pub fn until(&mut self, until: Duration) -> Vec<Duration> {
+		let mut times = Vec::new();
+		while let Some(time) = self.next_arrival() {
+			if time > until {
+				break;
+			}
+			times.push(time);
+		}
+		times
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::seed::ExperimentSeed;
+
+	fn times(process: ArrivalProcess, secs: u64) -> Vec<Duration> {
+		process.arrivals(ExperimentSeed::new(3).stream("arrivals")).until(Duration::from_secs(secs))
+	}
+
+	// Variance over mean of the arrival counts in one second windows
+	fn dispersion(times: &[Duration], secs: u64) -> f64 {
+		let mut counts = vec![0.0; secs as usize];
+		for t in times {
+			counts[t.as_secs() as usize] += 1.0;
+		}
+		let mean = counts.iter().sum::<f64>() / secs as f64;
+		counts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / secs as f64 / mean
+	}
+
+	#[test]
+	fn test_poisson_and_piecewise() {
+		let poisson = times(ArrivalProcess::Poisson { rate: 100.0 }, 100);
+		assert!((poisson.len() as f64 - 10_000.0).abs() < 400.0, "{} arrivals", poisson.len());
+		assert!(poisson.windows(2).all(|w| w[0] <= w[1]));
+		// Poisson counts have variance equal to their mean
+		assert!((dispersion(&poisson, 100) - 1.0).abs() < 0.4);
+
+		// A busy first second, a quiet second and nothing after the third
+		let piecewise = times(ArrivalProcess::Piecewise { rates: vec![(0, 1_000.0), (1_000, 100.0), (2_000, 0.0)] }, 10);
+		let first = piecewise.iter().filter(|t| t.as_secs() == 0).count();
+		let second = piecewise.iter().filter(|t| t.as_secs() == 1).count();
+		assert!(first > 900 && first < 1_100, "{} arrivals", first);
+		assert!(second > 60 && second < 140, "{} arrivals", second);
+		assert_eq!(piecewise.len(), first + second);
+	}
+
+	#[test]
+	fn test_hawkes_clusters() {
+		let hawkes = times(ArrivalProcess::Hawkes { base: 50.0, alpha: 5.0, beta: 10.0 }, 200);
+		// The mean rate is 50 / (1 - 5 / 10) = 100
+		let rate = hawkes.len() as f64 / 200.0;
+		assert!(rate > 85.0 && rate < 115.0, "{} per second", rate);
+		// Self-excitation makes the counts over-dispersed compared to Poisson
+		assert!(dispersion(&hawkes, 200) > 2.0);
+	}
+
+	#[test]
+	fn test_validate() {
+		assert!(ArrivalProcess::Poisson { rate: 0.0 }.validate().is_ok());
+		assert!(ArrivalProcess::Poisson { rate: -1.0 }.validate().is_err());
+		assert!(ArrivalProcess::Poisson { rate: f64::NAN }.validate().is_err());
+		assert!(ArrivalProcess::Piecewise { rates: vec![(1_000, 10.0), (0, 10.0)] }.validate().is_err());
+		assert!(ArrivalProcess::Piecewise { rates: vec![(0, 10.0), (0, 5.0)] }.validate().is_err());
+		assert!(ArrivalProcess::Piecewise { rates: vec![(0, 10.0), (1_000, -5.0)] }.validate().is_err());
+		// An explosive, decay-free or negatively excited Hawkes process is rejected
+		assert!(ArrivalProcess::Hawkes { base: 10.0, alpha: 10.0, beta: 10.0 }.validate().is_err());
+		assert!(ArrivalProcess::Hawkes { base: 10.0, alpha: 0.0, beta: 0.0 }.validate().is_err());
+		assert!(ArrivalProcess::Hawkes { base: 10.0, alpha: -1.0, beta: 10.0 }.validate().is_err());
+		assert!(ArrivalProcess::Hawkes { base: 0.0, alpha: 1.0, beta: 10.0 }.validate().is_err());
+		assert!(ArrivalProcess::Hawkes { base: 10.0, alpha: 0.0, beta: 10.0 }.validate().is_ok());
+	}
+
+	#[test]
+	#[should_panic(expected = "hawkes needs 0 <= alpha < beta")]
+	fn test_invalid_arrivals_panic() {
+		times(ArrivalProcess::Hawkes { base: 10.0, alpha: 20.0, beta: 10.0 }, 1);
+	}
+}
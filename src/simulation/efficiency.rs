@@ -0,0 +1,183 @@
+use crate::order::TradeType;
+use crate::exchange::trade::Trade;
+use crate::simulation::zic_behavior::SupplyDemand;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// A trader's private value for a unit, if a buyer, or cost, if a seller.
+/// trader_id: String -> the trader the value belongs to
+/// trade_type: TradeType -> Bid for a buyer, Ask for a seller
+/// limit: f64 -> the value or cost
+#[derive(Debug, Clone, PartialEq)]
+pub struct Valuation {
+	pub trader_id: String,
+	pub trade_type: TradeType,
+	pub limit: f64,
+}
+
+impl Valuation {
+	pub fn new(trader_id: String, trade_type: TradeType, limit: f64) -> Valuation {
+		Valuation {
+			trader_id,
+			trade_type,
+			limit,
+		}
+	}
+
+	/// The valuations of the buyers and sellers of the schedules, under the trader_ids
+	/// SupplyDemand gives its agents
+	pub fn from_schedules(market: &SupplyDemand) -> Vec<Valuation> {
+		market.agents(Valuation::new)
+	}
+}
+
+/// The welfare of a simulation run.
+/// trades: usize -> number of trades in the run
+/// surplus: f64 -> values of the buyers holding a unit at the end less costs of the
+/// sellers that gave theirs up
+/// max_surplus: f64 -> the competitive equilibrium's surplus, the most that can be realized
+/// efficiency: f64 -> allocative efficiency, surplus over max_surplus
+/// equilibrium_price: f64 -> the middle of the competitive equilibrium's price range
+/// smiths_alpha: f64 -> root mean squared deviation of the trade prices from the
+/// equilibrium price, as a percentage of it
+/// volatility: f64 -> standard deviation of the log returns between consecutive trades
+/// extra_marginal_trades: usize -> trades by a buyer valuing the unit below the
+/// equilibrium price range or a seller whose cost is above it
+#[derive(Debug, Clone, PartialEq)]
+pub struct EfficiencyReport {
+	pub trades: usize,
+	pub surplus: f64,
+	pub max_surplus: f64,
+	pub efficiency: f64,
+	pub equilibrium_price: f64,
+	pub smiths_alpha: f64,
+	pub volatility: f64,
+	pub extra_marginal_trades: usize,
+}
+
+impl EfficiencyReport {
+	/// Measures the trades of a run against the traders' valuations. The surplus comes
+	/// from the final allocation rather than the trade prices, which are only transfers:
+	/// each trader values a single unit, so a buyer counts its value once if it ends up
+	/// long and a seller its cost once if it ends up short. Traders without a valuation,
+	/// e.g. market makers, add no surplus, but units they take from sellers cost the
+	/// sellers' costs. Off-book trades count toward the allocation but not the prices.
+	/// Returns None if the valuations have no equilibrium.
+	pub fn compute(valuations: &[Valuation], trades: &[Trade]) -> Option<EfficiencyReport> {
+		let values: Vec<f64> = valuations.iter().filter(|v| v.trade_type == TradeType::Bid).map(|v| v.limit).collect();
+		let costs: Vec<f64> = valuations.iter().filter(|v| v.trade_type == TradeType::Ask).map(|v| v.limit).collect();
+		let eq = SupplyDemand::new(values, costs).equilibrium()?;
+		let price = eq.price();
+
+		let limits: HashMap<&str, (&TradeType, f64)> = valuations.iter().map(|v| (v.trader_id.as_str(), (&v.trade_type, v.limit))).collect();
+		let buyer = |t: &Trade| limits.get(t.bid_id.as_str()).filter(|(side, _)| **side == TradeType::Bid).map(|(_, l)| *l);
+		let seller = |t: &Trade| limits.get(t.ask_id.as_str()).filter(|(side, _)| **side == TradeType::Ask).map(|(_, l)| *l);
+
+		// Ordered so the surplus sums the same way every run
+		let mut positions = BTreeMap::<&str, f64>::new();
+		let mut extra_marginal_trades = 0;
+		for trade in trades {
+			*positions.entry(trade.bid_id.as_str()).or_insert(0.0) += trade.quantity;
+			*positions.entry(trade.ask_id.as_str()).or_insert(0.0) -= trade.quantity;
+			if buyer(trade).is_some_and(|v| v < eq.price_low) || seller(trade).is_some_and(|c| c > eq.price_high) {
+				extra_marginal_trades += 1;
+			}
+		}
+
+		let surplus: f64 = positions.iter().map(|(trader_id, position)| {
+			match limits.get(trader_id) {
+				Some((TradeType::Bid, value)) => value * position.clamp(0.0, 1.0),
+				Some((TradeType::Ask, cost)) => -cost * (-position).clamp(0.0, 1.0),
+				None => 0.0,
+			}
+		}).sum();
+
+		let prices: Vec<f64> = trades.iter().filter(|t| !t.off_book).map(|t| t.price).collect();
+		let smiths_alpha = if prices.is_empty() {
+			0.0
+		} else {
+			100.0 * (prices.iter().map(|p| (p - price).powi(2)).sum::<f64>() / prices.len() as f64).sqrt() / price
+		};
+		let returns: Vec<f64> = prices.windows(2).map(|w| (w[1] / w[0]).ln()).collect();
+		let volatility = if returns.is_empty() {
+			0.0
+		} else {
+			let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+			(returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64).sqrt()
+		};
+
+		Some(EfficiencyReport {
+			trades: trades.len(),
+			surplus,
+			max_surplus: eq.surplus,
+			efficiency: if eq.surplus > 0.0 { surplus / eq.surplus } else { 1.0 },
+			equilibrium_price: price,
+			smiths_alpha,
+			volatility,
+			extra_marginal_trades,
+		})
+	}
+
+	pub fn to_json(&self) -> serde_json::Value {
+		json!({
+			"trades": self.trades,
+			"surplus": self.surplus,
+			"max_surplus": self.max_surplus,
+			"efficiency": self.efficiency,
+			"equilibrium_price": self.equilibrium_price,
+			"smiths_alpha": self.smiths_alpha,
+			"volatility": self.volatility,
+			"extra_marginal_trades": self.extra_marginal_trades,
+		})
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn trade(bid_id: &str, ask_id: &str, price: f64) -> Trade {
+		Trade::new(String::from(bid_id), String::from(ask_id), None, price, 1.0)
+	}
+
+	#[test]
+	fn test_efficiency_report() {
+		// Two units trade at 60 to 70 for a surplus of 100
+		let market = SupplyDemand::new(vec![100.0, 80.0, 60.0, 40.0], vec![30.0, 50.0, 70.0, 90.0]);
+		let valuations = Valuation::from_schedules(&market);
+
+		let efficient = vec![trade("buyer_0", "seller_0", 65.0), trade("buyer_1", "seller_1", 65.0)];
+		let report = EfficiencyReport::compute(&valuations, &efficient).unwrap();
+		assert_eq!((report.surplus, report.max_surplus, report.efficiency), (100.0, 100.0, 1.0));
+		assert_eq!((report.smiths_alpha, report.volatility, report.extra_marginal_trades), (0.0, 0.0, 0));
+
+		// The buyer valuing the unit at 40 displaces the one valuing it at 80, and a second
+		// unit is worth nothing to a buyer that already holds one
+		let displaced = vec![trade("buyer_0", "seller_0", 60.0), trade("buyer_3", "seller_1", 40.0), trade("buyer_0", "mm", 50.0)];
+		let report = EfficiencyReport::compute(&valuations, &displaced).unwrap();
+		assert_eq!((report.surplus, report.efficiency, report.extra_marginal_trades), (60.0, 0.6, 1));
+		assert!(report.smiths_alpha > 0.0 && report.volatility > 0.0);
+		assert_eq!(report.to_json()["extra_marginal_trades"], 1);
+
+		// A unit the seller gives up to a trader without a valuation only costs the seller's cost
+		let report = EfficiencyReport::compute(&valuations, &[trade("mm", "seller_0", 65.0)]).unwrap();
+		assert_eq!(report.surplus, -30.0);
+	}
+
+	#[test]
+	fn test_surplus_through_intermediary() {
+		let market = SupplyDemand::new(vec![100.0, 80.0, 60.0, 40.0], vec![30.0, 50.0, 70.0, 90.0]);
+		let valuations = Valuation::from_schedules(&market);
+
+		// The market maker buying at 100 and selling at 40 moves only one unit from a cost of
+		// 30 to a value of 100, whatever it loses on the prices
+		let trades = vec![trade("mm", "seller_0", 100.0), trade("buyer_0", "mm", 40.0)];
+		let report = EfficiencyReport::compute(&valuations, &trades).unwrap();
+		assert_eq!((report.surplus, report.efficiency), (70.0, 0.7));
+
+		// A round trip leaves the allocation, and the surplus, where it started
+		let trades = vec![trade("buyer_1", "seller_1", 60.0), trade("seller_1", "buyer_1", 65.0)];
+		assert_eq!(EfficiencyReport::compute(&valuations, &trades).unwrap().surplus, 0.0);
+	}
+}
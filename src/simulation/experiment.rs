@@ -0,0 +1,457 @@
+use crate::simulation::adaptive_behavior::{GdAgent, ZipAgent};
+use crate::simulation::arrivals::ArrivalProcess;
+use crate::simulation::efficiency::{EfficiencyReport, Valuation};
+use crate::simulation::harness::Harness;
+use crate::simulation::liquidity::LiquidityMonitor;
+use crate::simulation::random_behavior::{RandBehavior, RandomFlow};
+use crate::simulation::seed::ExperimentSeed;
+use crate::simulation::strategy::{Strategy, StrategyRunner};
+use crate::simulation::trader::Traders;
+use crate::simulation::trader_behavior::OrderRange;
+use crate::simulation::zic_behavior::SupplyDemand;
+
+use rayon::prelude::*;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+// How many prices a GD agent remembers
+const GD_MEMORY: usize = 50;
+
+/// How the exchange matches orders.
+/// Continuous: the continuous double auction, every arrival matched as it comes in
+/// Batch: frequent batch auctions uncrossing the Books every interval_ms
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MarketDesign {
+	Continuous,
+	Batch { interval_ms: u64 },
+}
+
+/// The kind of trader in a double auction population
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraderKind {
+	Zic,
+	Zip,
+	Gd,
+}
+
+/// The agents trading in a run.
+/// Random: random order flow in batches on fixed intervals
+/// RandomArrivals: random orders arriving one at a time at the times of an arrival
+/// process each for enters, updates and cancels
+/// DoubleAuction: traders buying or selling one unit each, values and costs drawn
+/// uniformly from their ranges and shouts bounded by bounds, one chosen to act every
+/// interval milliseconds. Runs of them are measured against their valuations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentConfig {
+	Random {
+		#[serde(default)]
+		flow: RandomFlow,
+	},
+	RandomArrivals {
+		enter: ArrivalProcess,
+		update: ArrivalProcess,
+		cancel: ArrivalProcess,
+		#[serde(default)]
+		range: OrderRange,
+	},
+	DoubleAuction {
+		kind: TraderKind,
+		traders: usize,
+		values: (f64, f64),
+		costs: (f64, f64),
+		bounds: (f64, f64),
+		interval: u64,
+	},
+}
+
+/// How a run's liquidity is measured, see LiquidityMonitor.
+/// tick: f64 -> the price increment depth is measured in
+/// depth_ticks: u32 -> depth and imbalance count the quantity within this many ticks of the best price
+/// horizons_ms: Vec<u64> -> horizons of the realized spreads and price impacts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+	pub tick: f64,
+	pub depth_ticks: u32,
+	pub horizons_ms: Vec<u64>,
+}
+
+impl Default for MetricsConfig {
+	fn default() -> Self {
+		MetricsConfig {
+			tick: 0.01,
+			depth_ticks: 10,
+			horizons_ms: vec![100, 1000],
+		}
+	}
+}
+
+/// Everything a single run needs besides its seed.
+/// market: MarketDesign -> continuous or batch matching
+/// agents: AgentConfig -> who trades
+/// until: u64 -> virtual milliseconds to run for
+/// metrics: MetricsConfig -> how liquidity is measured
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunConfig {
+	pub market: MarketDesign,
+	pub agents: AgentConfig,
+	pub until: u64,
+	#[serde(default)]
+	pub metrics: MetricsConfig,
+}
+
+impl RunConfig {
+	/// Checks the values the run would otherwise panic or never finish on: zero intervals
+	/// and ranges with nothing to draw from
+	pub fn validate(&self) -> Result<(), &'static str> {
+		if self.market == (MarketDesign::Batch { interval_ms: 0 }) {
+			return Err("batch interval must be greater than zero");
+		}
+		match &self.agents {
+			AgentConfig::Random { flow } => {
+				let (arrival, update, cancel) = flow.intervals;
+				if arrival == 0 || update == 0 || cancel == 0 {
+					return Err("random flow intervals must be greater than zero");
+				}
+				if flow.max_enters == 0 || flow.upper == 0 {
+					return Err("random flow max_enters and upper must be greater than zero");
+				}
+				if !flow.range.is_valid() {
+					return Err("order price or quantity range is empty");
+				}
+			},
+			AgentConfig::RandomArrivals { enter, update, cancel, range } => {
+				enter.validate()?;
+				update.validate()?;
+				cancel.validate()?;
+				if !range.is_valid() {
+					return Err("order price or quantity range is empty");
+				}
+			},
+			AgentConfig::DoubleAuction { values, costs, interval, .. } => {
+				if values.0 >= values.1 || costs.0 >= costs.1 {
+					return Err("value or cost range is empty");
+				}
+				if *interval == 0 {
+					return Err("double auction interval must be greater than zero");
+				}
+			},
+		}
+		Ok(())
+	}
+}
+
+/// A declarative experiment, read from JSON. The run's fields sit at the top level next
+/// to the seeds and the grid, e.g.
+/// {"name": "fba", "market": {"type": "batch", "interval_ms": 100}, "until": 10000,
+///  "agents": {"type": "double_auction", "kind": "zic", ...},
+///  "seeds": [1, 2, 3], "grid": {"market.interval_ms": [10, 100, 1000]}}
+/// name: String -> what the experiment is called
+/// base: RunConfig -> the run every point of the grid starts from
+/// seeds: Vec<u64> -> every point of the grid is run once with each seed, so the points
+/// see the same random draws
+/// grid: BTreeMap<String, Vec<Value>> -> values to sweep, keyed by the dotted path of the
+/// field they replace. The runs cover every combination.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentConfig {
+	pub name: String,
+	#[serde(flatten)]
+	pub base: RunConfig,
+	pub seeds: Vec<u64>,
+	#[serde(default)]
+	pub grid: BTreeMap<String, Vec<Value>>,
+}
+
+impl ExperimentConfig {
+	pub fn from_json(json: &str) -> serde_json::Result<ExperimentConfig> {
+		serde_json::from_str(json)
+	}
+
+	/// Expands the grid into its runs, each combination once per seed in the order of
+	/// the seeds. Fails if a combination doesn't make a valid RunConfig.
+	pub fn runs(&self) -> Result<Vec<Run>, &'static str> {
+		let base = serde_json::to_value(&self.base).expect("config serializes");
+		let mut points = vec![(BTreeMap::<String, Value>::new(), base)];
+		for (path, values) in self.grid.iter() {
+			if values.is_empty() {
+				return Err("grid parameter has no values");
+			}
+			let mut next = Vec::with_capacity(points.len() * values.len());
+			for (params, config) in points.iter() {
+				for value in values {
+					let mut config = config.clone();
+					*ExperimentConfig::field(&mut config, path)? = value.clone();
+					let mut params = params.clone();
+					params.insert(path.clone(), value.clone());
+					next.push((params, config));
+				}
+			}
+			points = next;
+		}
+
+		let mut runs = Vec::with_capacity(points.len() * self.seeds.len());
+		for (params, config) in points {
+			let config: RunConfig = serde_json::from_value(config).map_err(|_| "grid value doesn't fit the config")?;
+			config.validate()?;
+			runs.extend(self.seeds.iter().map(|seed| Run { params: params.clone(), seed: *seed, config: config.clone() }));
+		}
+		Ok(runs)
+	}
+
+	// The field at the dotted path, which must already be in the config
+	fn field<'a>(config: &'a mut Value, path: &str) -> Result<&'a mut Value, &'static str> {
+		path.split('.').try_fold(config, |value, key| value.get_mut(key).ok_or("grid parameter isn't in the config"))
+	}
+}
+
+/// One run of an experiment.
+/// params: BTreeMap<String, Value> -> the grid values the run was made with
+/// seed: u64 -> the run's seed
+/// config: RunConfig -> the base config with the grid values in place
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+	pub params: BTreeMap<String, Value>,
+	pub seed: u64,
+	pub config: RunConfig,
+}
+
+impl Run {
+	/// Runs the config on its own in-process harness and measures it
+	pub fn execute(&self) -> RunSummary {
+		let config = &self.config;
+		let seed = ExperimentSeed::new(self.seed);
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		if let MarketDesign::Batch { interval_ms } = config.market {
+			harness.set_batch_interval(Some(Duration::from_millis(interval_ms)));
+		}
+		let horizons = config.metrics.horizons_ms.iter().map(|h| Duration::from_millis(*h)).collect();
+		harness.set_liquidity_monitor(Some(LiquidityMonitor::new(config.metrics.tick, config.metrics.depth_ticks, horizons)));
+
+		let mut valuations = None;
+		let orders = match &config.agents {
+			AgentConfig::Random { flow } => RandBehavior::in_process(&mut harness, Arc::new(Traders::new()), flow, config.until, seed),
+			AgentConfig::RandomArrivals { enter, update, cancel, range } => {
+				let processes = (enter.clone(), update.clone(), cancel.clone());
+				RandBehavior::with_arrivals(&mut harness, Arc::new(Traders::new()), processes, range, config.until, seed)
+			},
+			AgentConfig::DoubleAuction { kind, traders, values, costs, bounds, interval } => {
+				let market = SupplyDemand::uniform(*traders, *values, *costs, &mut seed.stream("schedules"));
+				let mut agents: Vec<Box<dyn Strategy>> = match kind {
+					TraderKind::Zic => market.zic_agents(*bounds, seed).into_iter().map(|a| Box::new(a) as Box<dyn Strategy>).collect(),
+					TraderKind::Zip => market.agents(|id, side, limit| {
+						let rng = seed.stream(&id);
+						Box::new(ZipAgent::new(id, side, limit, rng)) as Box<dyn Strategy>
+					}),
+					TraderKind::Gd => market.agents(|id, side, limit| Box::new(GdAgent::new(id, side, limit, *bounds, GD_MEMORY)) as Box<dyn Strategy>),
+				};
+				valuations = Some(Valuation::from_schedules(&market));
+				StrategyRunner::in_process(&mut harness, &mut agents, *interval, config.until, seed)
+			},
+		};
+
+		let trades = harness.trade_log.since(0);
+		let liquidity = harness.liquidity_monitor().expect("monitor attached").report(harness.now());
+		RunSummary {
+			params: self.params.clone(),
+			seed: self.seed,
+			orders,
+			trades: trades.len(),
+			efficiency: valuations.and_then(|v| EfficiencyReport::compute(&v, &trades)),
+			liquidity: liquidity.summary_json(),
+		}
+	}
+}
+
+/// The results of one run.
+/// params: BTreeMap<String, Value> -> the grid values the run was made with
+/// seed: u64 -> the run's seed
+/// orders: usize -> orders the agents sent
+/// trades: usize -> trades made
+/// efficiency: Option<EfficiencyReport> -> welfare of the run, for agents with valuations
+/// liquidity: Value -> LiquidityReport::summary_json of the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunSummary {
+	pub params: BTreeMap<String, Value>,
+	pub seed: u64,
+	pub orders: usize,
+	pub trades: usize,
+	pub efficiency: Option<EfficiencyReport>,
+	pub liquidity: Value,
+}
+
+impl RunSummary {
+	/// The grid values, seed and every metric as one flat object
+	pub fn to_json(&self) -> Value {
+		let mut row = serde_json::Map::new();
+		for (path, value) in self.params.iter() {
+			row.insert(path.clone(), value.clone());
+		}
+		row.insert(String::from("seed"), json!(self.seed));
+		row.insert(String::from("orders"), json!(self.orders));
+		row.insert(String::from("trades"), json!(self.trades));
+		let metrics = self.efficiency.iter().map(|e| e.to_json()).chain(Some(self.liquidity.clone()));
+		for metric in metrics {
+			if let Value::Object(fields) = metric {
+				// The efficiency report's trade count is already in the row
+				row.extend(fields.into_iter().filter(|(k, _)| k != "trades"));
+			}
+		}
+		Value::Object(row)
+	}
+}
+
+/// The summaries of an experiment's runs as one table, a row per run.
+/// columns: Vec<String> -> the grid parameters, then the seed, then the metrics
+/// rows: Vec<Vec<Value>> -> each run's values in column order, null where it has none
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultTable {
+	pub columns: Vec<String>,
+	pub rows: Vec<Vec<Value>>,
+}
+
+impl ResultTable {
+	pub fn new(summaries: &[RunSummary]) -> ResultTable {
+		let objects: Vec<Value> = summaries.iter().map(|s| s.to_json()).collect();
+		let mut columns = Vec::<String>::new();
+		for object in objects.iter() {
+			for key in object.as_object().expect("summary is an object").keys() {
+				if !columns.contains(key) {
+					columns.push(key.clone());
+				}
+			}
+		}
+		// serde_json sorts the keys, so put the parameters and seed back in front
+		let (front, metrics): (Vec<String>, Vec<String>) = columns.into_iter()
+			.partition(|c| c == "seed" || summaries.iter().any(|s| s.params.contains_key(c)));
+		let (seed, params): (Vec<String>, Vec<String>) = front.into_iter().partition(|c| c == "seed");
+		let columns: Vec<String> = params.into_iter().chain(seed).chain(metrics).collect();
+		let rows = objects.iter().map(|o| columns.iter().map(|c| o.get(c).cloned().unwrap_or(Value::Null)).collect()).collect();
+		ResultTable { columns, rows }
+	}
+
+	/// The table as comma separated values with a header line. Strings are written
+	/// without their JSON quotes, nulls as empty cells and cells holding commas or quotes,
+	/// e.g. objects swept in the grid, are quoted.
+	pub fn to_csv(&self) -> String {
+		let quote = |text: String| {
+			if text.contains([',', '"', '\n']) { format!("\"{}\"", text.replace('"', "\"\"")) } else { text }
+		};
+		let cell = |value: &Value| quote(match value {
+			Value::Null => String::new(),
+			Value::String(s) => s.clone(),
+			other => other.to_string(),
+		});
+		let mut csv = self.columns.iter().cloned().map(quote).collect::<Vec<String>>().join(",");
+		csv.push('\n');
+		for row in self.rows.iter() {
+			csv.push_str(&row.iter().map(cell).collect::<Vec<String>>().join(","));
+			csv.push('\n');
+		}
+		csv
+	}
+
+	/// The table as an array of objects, one per run
+	pub fn to_json(&self) -> Value {
+		Value::Array(self.rows.iter().map(|row| {
+			Value::Object(self.columns.iter().cloned().zip(row.iter().cloned()).collect())
+		}).collect())
+	}
+}
+
+/// Runs experiments in parallel
+pub struct ExperimentRunner {}
+
+impl ExperimentRunner {
+	/// Expands the experiment's grid and seeds into runs and executes them on the rayon
+	/// thread pool, each on its own harness. The table's rows are in the order of the runs.
+	pub fn run(config: &ExperimentConfig) -> Result<ResultTable, &'static str> {
+		let runs = config.runs()?;
+		let summaries: Vec<RunSummary> = runs.par_iter().map(|run| run.execute()).collect();
+		Ok(ResultTable::new(&summaries))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config() -> ExperimentConfig {
+		ExperimentConfig::from_json(r#"{
+			"name": "zic_batches",
+			"market": {"type": "batch", "interval_ms": 10},
+			"agents": {"type": "double_auction", "kind": "zic", "traders": 10,
+					   "values": [80, 120], "costs": [80, 120], "bounds": [1, 200], "interval": 1},
+			"until": 500,
+			"seeds": [1, 2],
+			"grid": {"market.interval_ms": [5, 50], "agents.kind": ["zic", "zip"]}
+		}"#).unwrap()
+	}
+
+	#[test]
+	fn test_grid_expansion() {
+		let runs = config().runs().unwrap();
+		assert_eq!(runs.len(), 8);
+		assert_eq!(runs[0].params["agents.kind"], "zic");
+		assert_eq!(runs[0].config.market, MarketDesign::Batch { interval_ms: 5 });
+		assert_eq!((runs[0].seed, runs[1].seed), (1, 2));
+		assert!(matches!(runs[7].config.agents, AgentConfig::DoubleAuction { kind: TraderKind::Zip, .. }));
+		assert_eq!(runs[7].config.market, MarketDesign::Batch { interval_ms: 50 });
+
+		let mut bad = config();
+		bad.grid.insert(String::from("market.interval"), vec![json!(1)]);
+		assert_eq!(bad.runs().unwrap_err(), "grid parameter isn't in the config");
+		let mut bad = config();
+		bad.grid.insert(String::from("agents.kind"), vec![json!("random")]);
+		assert_eq!(bad.runs().unwrap_err(), "grid value doesn't fit the config");
+		let mut bad = config();
+		bad.grid.insert(String::from("market.interval_ms"), vec![json!(10), json!(0)]);
+		assert_eq!(bad.runs().unwrap_err(), "batch interval must be greater than zero");
+		let mut bad = config();
+		bad.grid.insert(String::from("agents.values"), vec![json!([120, 80])]);
+		assert_eq!(bad.runs().unwrap_err(), "value or cost range is empty");
+
+		// Missing fields of the random flow take their defaults
+		let random = ExperimentConfig::from_json(r#"{"name": "random", "market": {"type": "continuous"},
+			"agents": {"type": "random", "flow": {"upper": 5}}, "until": 100, "seeds": [0]}"#).unwrap();
+		let expected = RandomFlow { upper: 5, ..RandomFlow::default() };
+		assert_eq!(random.runs().unwrap()[0].config.agents, AgentConfig::Random { flow: expected });
+
+		// A flow whose prices can't be drawn is caught before it panics in a worker
+		let empty = ExperimentConfig::from_json(r#"{"name": "random", "market": {"type": "continuous"},
+			"agents": {"type": "random", "flow": {"range": {"price": [100, 100], "quantity": [1, 5]}}},
+			"until": 100, "seeds": [0]}"#).unwrap();
+		assert_eq!(empty.runs().unwrap_err(), "order price or quantity range is empty");
+
+		// And so is an explosive arrival process
+		let explosive = ExperimentConfig::from_json(r#"{"name": "arrivals", "market": {"type": "continuous"},
+			"agents": {"type": "random_arrivals", "enter": {"type": "poisson", "rate": 100},
+			"update": {"type": "hawkes", "base": 10, "alpha": 20, "beta": 10},
+			"cancel": {"type": "poisson", "rate": 10}}, "until": 100, "seeds": [0]}"#).unwrap();
+		assert_eq!(explosive.runs().unwrap_err(), "hawkes needs 0 <= alpha < beta");
+	}
+
+	#[test]
+	fn test_parallel_sweep() {
+		let config = config();
+		let table = ExperimentRunner::run(&config).unwrap();
+		assert_eq!(table.rows.len(), 8);
+		assert_eq!(&table.columns[..3], &["agents.kind", "market.interval_ms", "seed"]);
+		let efficiency = table.columns.iter().position(|c| c == "efficiency").unwrap();
+		assert!(table.rows.iter().all(|r| r[efficiency].as_f64().is_some_and(|e| e > 0.0)));
+
+		// Running in parallel gives the same results as running alone
+		let runs = config.runs().unwrap();
+		assert_eq!(table.rows[3], ResultTable::new(&[runs[3].execute()]).rows[0]);
+		assert_eq!(table.to_csv().lines().count(), 9);
+		let market = ResultTable { columns: vec![String::from("market")], rows: vec![vec![json!({"type": "batch", "interval_ms": 5})]] };
+		assert_eq!(market.to_csv(), "market\n\"{\"\"interval_ms\"\":5,\"\"type\"\":\"\"batch\"\"}\"\n");
+		assert_eq!(table.to_json()[0]["seed"], 1);
+	}
+}
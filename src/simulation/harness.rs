@@ -0,0 +1,317 @@
+use crate::order::Order;
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::queue_processing::QueueProcessor;
+use crate::exchange::auction::Auction;
+use crate::exchange::peg_processing::PegProcessor;
+use crate::exchange::trade::{Trade, TradeLog};
+use crate::simulation::liquidity::LiquidityMonitor;
+use crate::utility::{set_virtual_time, set_logging};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An in-process exchange that agents submit orders to directly instead of over TCP or
+/// WebSocket. Time is virtual: it only moves when the harness is advanced, so runs are
+/// as fast as the matching engine and don't depend on the wall clock. Each order is
+/// delivered to the Queue and processed sequentially at its arrival time, as the
+/// continuous market would. While the harness exists get_time returns its virtual
+/// time on the thread that created it, so it must be run on that thread. Large runs
+/// should switch the exchange's logging off with set_logging. With a batch interval set
+/// the harness runs frequent batch auctions instead: orders accumulate in the Books
+/// without matching and the Books are uncrossed at every multiple of the interval.
+/// A LiquidityMonitor can be attached to record the Books and trades as the run goes.
+/// queue, bids, asks: the exchange the harness drives
+/// trade_log: Arc<TradeLog> -> every trade made during the run
+/// now: Duration -> the current virtual time, starting at 0
+/// batch: Option<(Duration, Duration)> -> the batch interval and the time of the next auction
+/// monitor: Option<LiquidityMonitor> -> records the run's market quality when set
+pub struct Harness {
+	pub queue: Arc<Queue>,
+	pub bids: Arc<Book>,
+	pub asks: Arc<Book>,
+	pub trade_log: Arc<TradeLog>,
+	now: Duration,
+	in_flight: BTreeMap<Duration, VecDeque<Order>>,
+	batch: Option<(Duration, Duration)>,
+	monitor: Option<LiquidityMonitor>,
+}
+
+impl Default for Harness {
+	fn default() -> Self {
+		Harness::new()
+	}
+}
+
+impl Harness {
+	pub fn new() -> Harness {
+		let (queue, bids, asks, _) = crate::setup_exchange();
+		set_virtual_time(Some(Duration::from_millis(0)));
+		Harness {
+			queue,
+			bids,
+			asks,
+			trade_log: Arc::new(TradeLog::new()),
+			now: Duration::from_millis(0),
+			in_flight: BTreeMap::new(),
+			batch: None,
+			monitor: None,
+		}
+	}
+
+	/// Switches between the continuous market, None, and batch auctions every interval.
+	/// Panics on a zero interval, which would never let virtual time move past the auction.
+	pub fn set_batch_interval(&mut self, interval: Option<Duration>) {
+		assert!(interval != Some(Duration::ZERO), "batch interval must be greater than zero");
+		self.batch = interval.map(|i| (i, self.now + i));
+	}
+
+	/// Attaches a LiquidityMonitor, or detaches it with None. It sees the orders sent and
+	/// the Books from then on.
+	pub fn set_liquidity_monitor(&mut self, monitor: Option<LiquidityMonitor>) {
+		self.monitor = monitor;
+	}
+
+	/// The attached LiquidityMonitor
+	pub fn liquidity_monitor(&self) -> Option<&LiquidityMonitor> {
+		self.monitor.as_ref()
+	}
+
+	/// Turns the exchange's per-order messages on or off for the run
+	pub fn set_logging(&self, on: bool) {
+		set_logging(on);
+	}
+
+	/// The current virtual time
+	pub fn now(&self) -> Duration {
+		self.now
+	}
+
+	/// Sends the order to arrive at the current time, when the harness is next advanced
+	pub fn submit(&mut self, order: Order) {
+		self.submit_after(order, Duration::from_millis(0));
+	}
+
+	/// Sends the order to arrive after latency, e.g. the sending agent's delay between
+	/// deciding and reaching the exchange
+	pub fn submit_after(&mut self, order: Order, latency: Duration) {
+		if let Some(monitor) = self.monitor.as_mut() {
+			monitor.on_order(&order);
+		}
+		// Orders arriving at the same time wait in the order they were sent
+		self.in_flight.entry(self.now + latency).or_default().push_back(order);
+	}
+
+	/// Number of orders sent that haven't arrived yet
+	pub fn in_flight(&self) -> usize {
+		self.in_flight.values().map(|orders| orders.len()).sum()
+	}
+
+	/// Moves the clock to time, processing every order arriving up to then at its arrival
+	/// time and running the batch auctions due by then. Orders arriving at the time of an
+	/// auction are in its batch. Returns the trades, which are also appended to the trade log.
+	pub fn advance_to(&mut self, time: Duration) -> Vec<Trade> {
+		let mut trades = Vec::<Trade>::new();
+		loop {
+			let arrival = self.in_flight.keys().next().copied().filter(|t| *t <= time);
+			let auction = self.batch.map(|(_, next)| next).filter(|t| *t <= time);
+			let step = match (arrival, auction) {
+				(Some(a), Some(b)) if b < a => self.run_auction(),
+				(Some(_), _) => {
+					let mut entry = self.in_flight.first_entry().expect("peeked arrival");
+					let arrival = *entry.key();
+					let order = entry.get_mut().pop_front().expect("arrivals are never empty");
+					if entry.get().is_empty() {
+						entry.remove();
+					}
+					self.set_now(arrival.max(self.now));
+					self.queue.add(order);
+					if self.batch.is_some() {
+						QueueProcessor::accumulate_order_queue(Arc::clone(&self.queue), Arc::clone(&self.bids), Arc::clone(&self.asks));
+						Vec::new()
+					} else {
+						QueueProcessor::seq_process_order_queue(Arc::clone(&self.queue),
+																Arc::clone(&self.bids),
+																Arc::clone(&self.asks))
+					}
+				},
+				(None, Some(_)) => self.run_auction(),
+				(None, None) => break,
+			};
+			if let Some(monitor) = self.monitor.as_mut() {
+				monitor.observe(self.now, &self.bids, &self.asks, &step);
+			}
+			trades.extend(step);
+		}
+		self.set_now(time.max(self.now));
+		self.trade_log.add(trades.clone());
+		trades
+	}
+
+	/// Moves the clock forward by dt, see advance_to
+	pub fn advance(&mut self, dt: Duration) -> Vec<Trade> {
+		self.advance_to(self.now + dt)
+	}
+
+	// Uncrosses the Books at the next batch time and schedules the following auction.
+	// As in continuous trading pegs then follow the touch, and the stops the trades set
+	// off join the next batch.
+	fn run_auction(&mut self) -> Vec<Trade> {
+		let (interval, next) = self.batch.expect("batch auctions are on");
+		self.set_now(next.max(self.now));
+		self.batch = Some((interval, next + interval));
+		let mut trades = Auction::uncross(Arc::clone(&self.bids), Arc::clone(&self.asks)).map_or(Vec::new(), |(_, trades)| trades);
+		QueueProcessor::cancel_immediate(&self.bids, &self.asks);
+		trades.extend(PegProcessor::reprice_pegs(Arc::clone(&self.bids), Arc::clone(&self.asks)));
+		QueueProcessor::release_stops(&self.bids, &self.asks, &self.queue, &trades);
+		QueueProcessor::accumulate_order_queue(Arc::clone(&self.queue), Arc::clone(&self.bids), Arc::clone(&self.asks));
+		trades
+	}
+
+	fn set_now(&mut self, time: Duration) {
+		self.now = time;
+		set_virtual_time(Some(time));
+	}
+}
+
+impl Drop for Harness {
+	// Hands get_time back to the wall clock and turns logging back on
+	fn drop(&mut self) {
+		set_virtual_time(None);
+		set_logging(true);
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::order::{TradeType, ExecType, Peg, PegType};
+	use crate::utility::get_time;
+	use crate::test_utils::{order, OrderSetup};
+
+	// Arrivals every 1ms, updates every 2ms and cancels every 4ms
+	fn flow() -> crate::simulation::random_behavior::RandomFlow {
+		crate::simulation::random_behavior::RandomFlow { intervals: (1, 2, 4), ..Default::default() }
+	}
+
+	#[test]
+	fn test_arrivals_on_virtual_clock() {
+		let mut harness = Harness::new();
+		harness.submit_after(order("slow", TradeType::Bid, 101.0, 1.0), Duration::from_millis(5));
+		harness.submit_after(order("fast", TradeType::Bid, 100.0, 1.0), Duration::from_millis(1));
+		harness.submit(order("seller", TradeType::Ask, 99.0, 1.0));
+		assert_eq!(harness.in_flight(), 3);
+
+		// The ask arrives first, then the faster bid takes it at 1ms
+		let trades = harness.advance(Duration::from_millis(2));
+		assert_eq!(trades.len(), 1);
+		assert_eq!(trades[0].bid_id, "fast");
+		assert_eq!(trades[0].time, Duration::from_millis(1));
+		assert_eq!(get_time(), Duration::from_millis(2));
+
+		assert!(harness.advance(Duration::from_millis(10)).is_empty());
+		assert_eq!(harness.bids.peek_best_price(), Some(101.0));
+		assert_eq!(harness.trade_log.len(), 1);
+
+		drop(harness);
+		assert!(get_time() > Duration::from_secs(1));
+	}
+
+	#[test]
+	#[should_panic(expected = "batch interval must be greater than zero")]
+	fn test_zero_batch_interval() {
+		Harness::new().set_batch_interval(Some(Duration::ZERO));
+	}
+
+	#[test]
+	fn test_batch_auctions() {
+		let mut harness = Harness::new();
+		harness.set_batch_interval(Some(Duration::from_millis(10)));
+		harness.submit_after(order("early", TradeType::Bid, 101.0, 1.0), Duration::from_millis(3));
+		harness.submit_after(order("seller", TradeType::Ask, 99.0, 1.0), Duration::from_millis(10));
+		harness.submit_after(order("late", TradeType::Bid, 102.0, 1.0), Duration::from_millis(11));
+
+		// Nothing matches between auctions, and an order arriving at the auction is in its batch
+		assert!(harness.advance(Duration::from_millis(9)).is_empty());
+		assert_eq!((harness.bids.len(), harness.asks.len()), (1, 0));
+		let trades = harness.advance(Duration::from_millis(5));
+		assert_eq!(trades.len(), 1);
+		assert_eq!((trades[0].bid_id.as_str(), trades[0].time), ("early", Duration::from_millis(10)));
+		assert_eq!(trades[0].aggressor, None);
+		assert_eq!(harness.bids.peek_best_price(), Some(102.0));
+	}
+
+	#[test]
+	fn test_batch_auctions_release_stops_and_reprice_pegs() {
+		let mut harness = Harness::new();
+		harness.set_batch_interval(Some(Duration::from_millis(10)));
+		harness.submit(order("b", TradeType::Bid, 99.0, 1.0));
+		harness.submit(order("b2", TradeType::Bid, 98.0, 5.0));
+		harness.submit(order("peg", TradeType::Bid, 0.0, 5.0).with_exec_type(ExecType::Pegged(Peg::new(PegType::Primary, 1.0, None))));
+		harness.submit(order("stop", TradeType::Ask, 90.0, 12.0).with_exec_type(ExecType::Stop(99.5)));
+		harness.submit(order("s", TradeType::Ask, 99.0, 1.0));
+
+		// The first auction trades at 99, which sets the stop off and moves the best bid to 98
+		let trades = harness.advance(Duration::from_millis(10));
+		assert_eq!(trades.len(), 1);
+		assert_eq!(trades[0].price, 99.0);
+		assert!(harness.asks.stops.is_empty());
+		let i = harness.bids.peek_id_pos(String::from("peg")).unwrap();
+		assert_eq!(harness.bids.orders.lock().unwrap()[i].price, 97.0);
+
+		// The stop joins the next batch as a market order and what it can't fill is cancelled
+		let trades = harness.advance(Duration::from_millis(10));
+		assert!(trades.iter().all(|t| t.ask_id == "stop"));
+		assert_eq!(trades.iter().map(|t| t.quantity).sum::<f64>(), 10.0);
+		assert!(harness.bids.is_empty());
+		assert!(harness.asks.is_empty());
+	}
+
+	#[test]
+	fn test_random_flow_in_process() {
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		let traders = Arc::new(crate::simulation::trader::Traders::new());
+		let seed = crate::simulation::seed::ExperimentSeed::new(3);
+		let submitted = crate::simulation::random_behavior::RandBehavior::in_process(&mut harness, traders, &flow(), 2_000, seed);
+		assert!(submitted > 0);
+		assert_eq!(harness.now(), Duration::from_millis(2_000));
+		// Only cancels decided in the last second are still in flight
+		assert!(harness.in_flight() < submitted);
+	}
+
+	#[test]
+	fn test_arrival_processes_in_process() {
+		use crate::simulation::arrivals::ArrivalProcess;
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		let traders = Arc::new(crate::simulation::trader::Traders::new());
+		let processes = (ArrivalProcess::Poisson { rate: 2_000.0 },
+						 ArrivalProcess::Hawkes { base: 500.0, alpha: 50.0, beta: 100.0 },
+						 ArrivalProcess::Poisson { rate: 500.0 });
+		let seed = crate::simulation::seed::ExperimentSeed::new(8);
+		let submitted = crate::simulation::random_behavior::RandBehavior::with_arrivals(&mut harness, traders, processes, &Default::default(), 1_000, seed);
+		assert!(submitted > 2_500 && submitted < 5_000, "{} orders", submitted);
+		// Orders arrive in continuous time rather than on the millisecond ticks
+		let trades = harness.trade_log.since(0);
+		assert!(trades.iter().any(|t| t.time.subsec_micros() % 1_000 != 0));
+	}
+
+	#[test]
+	fn test_seeded_runs_reproduce() {
+		let run = |seed: u64| -> Vec<Trade> {
+			let mut harness = Harness::new();
+			harness.set_logging(false);
+			let traders = Arc::new(crate::simulation::trader::Traders::new());
+			let seed = crate::simulation::seed::ExperimentSeed::new(seed);
+			crate::simulation::random_behavior::RandBehavior::in_process(&mut harness, traders, &flow(), 500, seed);
+			harness.trade_log.since(0)
+		};
+		let trades = run(11);
+		assert!(!trades.is_empty());
+		assert_eq!(trades, run(11));
+		assert_ne!(trades, run(12));
+	}
+}
@@ -0,0 +1,346 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::exchange::trade::Trade;
+use crate::simulation::harness::Harness;
+use crate::simulation::market_maker_behavior::{InventoryModel, MarketMaker};
+use crate::simulation::seed::ExperimentSeed;
+use crate::simulation::strategy::{MarketEvent, Strategy, StrategyRunner};
+
+use rand::Rng;
+use rand::distributions::Exp;
+use rand::rngs::StdRng;
+use std::time::Duration;
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// A public fair value that jumps up or down by jump with equal probability at the
+/// arrivals of a Poisson process.
+/// value: f64 -> the current fair value
+/// jump: f64 -> size of every jump
+/// rate: f64 -> expected jumps per second
+pub struct JumpProcess {
+	pub value: f64,
+	pub jump: f64,
+	pub rate: f64,
+	next: Duration,
+	rng: StdRng,
+}
+
+impl JumpProcess {
+	pub fn new(value: f64, jump: f64, rate: f64, mut rng: StdRng) -> JumpProcess {
+		let next = JumpProcess::wait(rate, &mut rng);
+		JumpProcess {
+			value,
+			jump,
+			rate,
+			next,
+			rng,
+		}
+	}
+
+	// Time to the next jump, exponentially distributed
+	fn wait(rate: f64, rng: &mut StdRng) -> Duration {
+		let secs: f64 = rng.sample(Exp::new(rate));
+		Duration::from_micros((secs * 1_000_000.0) as u64)
+	}
+
+	/// Applies every jump up to now, returning the new value if it moved
+	pub fn update(&mut self, now: Duration) -> Option<f64> {
+		let before = self.value;
+		while self.next <= now {
+			self.value += if self.rng.gen::<bool>() { self.jump } else { -self.jump };
+			self.next += JumpProcess::wait(self.rate, &mut self.rng);
+		}
+		if (self.value - before).abs() > EPSILON { Some(self.value) } else { None }
+	}
+}
+
+/// A fast trader that picks off stale quotes. When the public fair value moves past a
+/// resting quote by more than threshold, the sniper takes the quote at its stale price.
+/// A snipe left unfilled, because the quote was updated first, is cancelled once it has
+/// had patience to fill. Profit is marked to the fair value.
+/// trader_id: String -> trader_id the sniper's orders are sent under
+/// size: f64 -> most the sniper takes in a single snipe
+/// threshold: f64 -> how far a quote must be from the fair value to be sniped
+/// latency: Duration -> delay between the sniper deciding and its orders arriving
+/// patience: Duration -> how long an unfilled snipe rests after arriving before it's cancelled
+/// inventory, cash: the sniper's position
+pub struct Sniper {
+	pub trader_id: String,
+	pub size: f64,
+	pub threshold: f64,
+	pub latency: Duration,
+	pub patience: Duration,
+	pub inventory: f64,
+	pub cash: f64,
+	fair_value: Option<f64>,
+	quote: (Option<f64>, Option<f64>),
+	// The side, price, unfilled quantity and cancel time of the outstanding snipe
+	pending: Option<(TradeType, f64, f64, Duration)>,
+}
+
+impl Sniper {
+	pub fn new(trader_id: String, size: f64, threshold: f64, latency: Duration, patience: Duration) -> Sniper {
+		Sniper {
+			trader_id,
+			size,
+			threshold,
+			latency,
+			patience,
+			inventory: 0.0,
+			cash: 0.0,
+			fair_value: None,
+			quote: (None, None),
+			pending: None,
+		}
+	}
+
+	/// Profit marked to the last fair value seen
+	pub fn pnl(&self) -> f64 {
+		self.cash + self.inventory * self.fair_value.unwrap_or(0.0)
+	}
+
+	fn on_fill(&mut self, trade: &Trade) {
+		let signed = if trade.bid_id == self.trader_id {
+			trade.quantity
+		} else if trade.ask_id == self.trader_id {
+			-trade.quantity
+		} else {
+			return;
+		};
+		self.inventory += signed;
+		self.cash -= signed * trade.price;
+		if let Some((_, _, remaining, _)) = &mut self.pending {
+			*remaining -= trade.quantity;
+			if *remaining <= EPSILON {
+				self.pending = None;
+			}
+		}
+	}
+}
+
+impl Strategy for Sniper {
+	// The sniper only trades in reaction to the news
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		Vec::new()
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		match event {
+			MarketEvent::Trade(trade) => self.on_fill(trade),
+			MarketEvent::Quote { bid, ask } => self.quote = (*bid, *ask),
+			MarketEvent::FairValue(value) => self.fair_value = Some(*value),
+			_ => {},
+		}
+	}
+
+	fn react(&mut self, now: Duration) -> Vec<Order> {
+		if let Some((trade_type, price, remaining, deadline)) = self.pending.take() {
+			if now < deadline {
+				self.pending = Some((trade_type, price, remaining, deadline));
+				return Vec::new();
+			}
+			return vec![Order::new(self.trader_id.clone(), OrderType::Cancel, trade_type, price, remaining)];
+		}
+		let value = match self.fair_value {
+			Some(value) => value,
+			None => return Vec::new(),
+		};
+		let (trade_type, price) = match self.quote {
+			(_, Some(ask)) if ask < value - self.threshold => (TradeType::Bid, ask),
+			(Some(bid), _) if bid > value + self.threshold => (TradeType::Ask, bid),
+			_ => return Vec::new(),
+		};
+		self.pending = Some((trade_type.clone(), price, self.size, now + self.latency + self.patience));
+		vec![Order::new(self.trader_id.clone(), OrderType::Enter, trade_type, price, self.size)]
+	}
+
+	fn latency(&self) -> Duration {
+		self.latency
+	}
+}
+
+/// The setup of a latency arbitrage run.
+/// makers: usize -> number of market makers quoting around the public fair value
+/// maker_latency: Duration -> the makers' delay in requoting
+/// snipers: usize -> number of snipers
+/// sniper_latency: Duration -> the snipers' delay, lower than the makers' for them to profit
+/// model: InventoryModel -> the makers' quoting model
+/// size: f64 -> quantity the makers quote and the snipers take
+/// threshold: f64 -> how far a quote must be from the fair value for the snipers to take it
+/// jump: f64 -> size of the fair value's jumps
+/// jump_rate: f64 -> expected jumps per second
+/// batch_interval: Option<Duration> -> run frequent batch auctions instead of the continuous market
+/// until: u64 -> length of the run in milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnipingConfig {
+	pub makers: usize,
+	pub maker_latency: Duration,
+	pub snipers: usize,
+	pub sniper_latency: Duration,
+	pub model: InventoryModel,
+	pub size: f64,
+	pub threshold: f64,
+	pub jump: f64,
+	pub jump_rate: f64,
+	pub batch_interval: Option<Duration>,
+	pub until: u64,
+}
+
+impl Default for SnipingConfig {
+	fn default() -> Self {
+		SnipingConfig {
+			makers: 3,
+			maker_latency: Duration::from_millis(5),
+			snipers: 2,
+			sniper_latency: Duration::from_millis(1),
+			model: InventoryModel::new(0.1, 0.5, 1.5, 1.0),
+			size: 1.0,
+			threshold: 0.0,
+			jump: 1.0,
+			jump_rate: 20.0,
+			batch_interval: None,
+			until: 10_000,
+		}
+	}
+}
+
+/// The outcome of a latency arbitrage run.
+/// sniper_profit: f64 -> the snipers' total profit marked to the final fair value
+/// maker_pnl: f64 -> the makers' total profit marked to the final fair value
+/// sniped_volume: f64 -> quantity the snipers traded
+/// mean_spread: f64 -> average quoted spread over the times the Books had both sides uncrossed
+/// jumps: usize -> number of times the fair value moved
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnipingReport {
+	pub sniper_profit: f64,
+	pub maker_pnl: f64,
+	pub sniped_volume: f64,
+	pub mean_spread: f64,
+	pub jumps: usize,
+}
+
+// Records the quoted spread from every Quote event, never acts
+struct SpreadMonitor {
+	spreads: Vec<f64>,
+}
+
+impl Strategy for SpreadMonitor {
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		Vec::new()
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		if let MarketEvent::Quote { bid: Some(bid), ask: Some(ask) } = event {
+			if ask >= bid {
+				self.spreads.push(ask - bid);
+			}
+		}
+	}
+
+	fn active(&self) -> bool {
+		false
+	}
+}
+
+/// The latency arbitrage ("sniping") experiment. Market makers quote around a public fair
+/// value that jumps at random, snipers race their requotes to the stale quotes, and the
+/// run measures what the snipers take from the makers and the spreads quoted. The same
+/// config can run under the continuous market or frequent batch auctions, where a snipe
+/// and a requote in the same batch no longer race.
+pub struct SnipingExperiment {}
+
+impl SnipingExperiment {
+	pub fn run(config: &SnipingConfig, seed: ExperimentSeed) -> SnipingReport {
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		harness.set_batch_interval(config.batch_interval);
+		let mut process = JumpProcess::new(100.0, config.jump, config.jump_rate, seed.stream("fair_value"));
+
+		let mut makers: Vec<MarketMaker> = (0..config.makers).map(|i| {
+			let mut maker = MarketMaker::new(format!("maker_{}", i), config.model.clone(), config.size, 5.0 * config.size, process.value);
+			maker.latency = config.maker_latency;
+			maker.set_fair_value(process.value);
+			maker
+		}).collect();
+		// A snipe is given until the next batch to fill, or the next round in the continuous market
+		let patience = config.batch_interval.unwrap_or(Duration::from_millis(0));
+		let mut snipers: Vec<Sniper> = (0..config.snipers).map(|i| {
+			Sniper::new(format!("sniper_{}", i), config.size, config.threshold, config.sniper_latency, patience)
+		}).collect();
+		let mut monitor = SpreadMonitor { spreads: Vec::new() };
+
+		let mut jumps = 0;
+		let value = process.value;
+		{
+			let mut agents: Vec<&mut dyn Strategy> = makers.iter_mut().map(|m| m as &mut dyn Strategy).collect();
+			agents.extend(snipers.iter_mut().map(|s| s as &mut dyn Strategy));
+			agents.push(&mut monitor);
+			let mut first = true;
+			StrategyRunner::run(&mut harness, &mut agents, 1, config.until, seed, |now| {
+				let mut news = Vec::new();
+				if first {
+					news.push(MarketEvent::FairValue(value));
+					first = false;
+				}
+				if let Some(value) = process.update(now) {
+					jumps += 1;
+					news.push(MarketEvent::FairValue(value));
+				}
+				news
+			});
+		}
+
+		let sniped_volume = harness.trade_log.since(0).iter()
+			.filter(|t| t.bid_id.starts_with("sniper_") || t.ask_id.starts_with("sniper_"))
+			.map(|t| t.quantity)
+			.sum();
+		SnipingReport {
+			sniper_profit: snipers.iter().map(|s| s.pnl()).sum(),
+			maker_pnl: makers.iter().map(|m| m.pnl()).sum(),
+			sniped_volume,
+			mean_spread: monitor.spreads.iter().sum::<f64>() / monitor.spreads.len().max(1) as f64,
+			jumps,
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_jump_process() {
+		let mut process = JumpProcess::new(100.0, 1.0, 50.0, ExperimentSeed::new(1).stream("fair_value"));
+		let mut moves = 0;
+		for ms in 1..=1_000 {
+			if let Some(value) = process.update(Duration::from_millis(ms)) {
+				assert_eq!(value.fract(), 0.0);
+				moves += 1;
+			}
+		}
+		// Around 50 jumps a second, some of which cancel out within a millisecond
+		assert!(moves > 25 && moves < 75, "{} moves", moves);
+	}
+
+	#[test]
+	fn test_sniping_cda_vs_batch() {
+		let seed = ExperimentSeed::new(6);
+		let config = SnipingConfig { until: 3_000, ..SnipingConfig::default() };
+		let cda = SnipingExperiment::run(&config, seed);
+		assert!(cda.jumps > 0);
+		assert!(cda.sniped_volume > 0.0);
+		// The snipers' profit is the makers' loss
+		assert!(cda.sniper_profit > 0.0);
+		assert!((cda.sniper_profit + cda.maker_pnl).abs() < 0.000_001);
+		assert!(cda.mean_spread > 0.0);
+
+		// Batching every 100ms lets most requotes arrive in the same batch as the snipes
+		let batch = SnipingExperiment::run(&SnipingConfig { batch_interval: Some(Duration::from_millis(100)), ..config.clone() }, seed);
+		assert!(batch.sniper_profit < cda.sniper_profit / 2.0, "{:?} {:?}", cda, batch);
+
+		// No quote strays as far as a threshold of twice the jump, so nothing is sniped
+		let wary = SnipingExperiment::run(&SnipingConfig { threshold: 2.0 * config.jump, ..config }, seed);
+		assert_eq!(wary.sniped_volume, 0.0);
+	}
+}
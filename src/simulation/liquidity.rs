@@ -0,0 +1,374 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::exchange::order_book::Book;
+use crate::exchange::trade::Trade;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// The top of both Books at a point in time, aggregated into price levels.
+/// time: Duration -> virtual time the snapshot was taken
+/// bids, asks: Vec<(f64, f64)> -> (price, quantity) levels best first, only those within
+/// the monitor's depth of the best price
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookSnapshot {
+	pub time: Duration,
+	pub bids: Vec<(f64, f64)>,
+	pub asks: Vec<(f64, f64)>,
+}
+
+impl BookSnapshot {
+	/// Snapshots the levels of both Books within distance of their best prices
+	pub fn take(time: Duration, bids: &Book, asks: &Book, distance: f64) -> BookSnapshot {
+		BookSnapshot {
+			time,
+			bids: BookSnapshot::levels(bids, distance),
+			asks: BookSnapshot::levels(asks, distance),
+		}
+	}
+
+	// The Book is sorted with the best order last, so levels are read from the end
+	fn levels(book: &Book, distance: f64) -> Vec<(f64, f64)> {
+		let orders = book.orders.lock().unwrap();
+		let best = match orders.last() {
+			Some(order) => order.price,
+			None => return Vec::new(),
+		};
+		let mut levels = Vec::<(f64, f64)>::new();
+		for order in orders.iter().rev().take_while(|o| (o.price - best).abs() <= distance + EPSILON) {
+			match levels.last_mut() {
+				Some((price, quantity)) if (*price - order.price).abs() < EPSILON => *quantity += order.quantity,
+				_ => levels.push((order.price, order.quantity)),
+			}
+		}
+		levels
+	}
+
+	pub fn best_bid(&self) -> Option<f64> {
+		self.bids.first().map(|l| l.0)
+	}
+
+	pub fn best_ask(&self) -> Option<f64> {
+		self.asks.first().map(|l| l.0)
+	}
+
+	/// The quoted spread, None unless both sides are quoted and uncrossed
+	pub fn spread(&self) -> Option<f64> {
+		match (self.best_bid(), self.best_ask()) {
+			(Some(bid), Some(ask)) if ask >= bid => Some(ask - bid),
+			_ => None,
+		}
+	}
+
+	/// The midpoint of the best bid and ask, None unless both sides are quoted and uncrossed
+	pub fn midpoint(&self) -> Option<f64> {
+		self.spread().map(|s| self.best_ask().unwrap() - s / 2.0)
+	}
+
+	/// The bid and ask quantity within distance of each side's best price
+	pub fn depth(&self, distance: f64) -> (f64, f64) {
+		let within = |levels: &[(f64, f64)]| -> f64 {
+			levels.first().map_or(0.0, |best| {
+				levels.iter().filter(|l| (l.0 - best.0).abs() <= distance + EPSILON).map(|l| l.1).sum()
+			})
+		};
+		(within(&self.bids), within(&self.asks))
+	}
+
+	/// Order book imbalance within distance of the best prices, (bid - ask) / (bid + ask)
+	/// in -1 to 1, None if both sides are empty
+	pub fn imbalance(&self, distance: f64) -> Option<f64> {
+		let (bid, ask) = self.depth(distance);
+		if bid + ask > 0.0 { Some((bid - ask) / (bid + ask)) } else { None }
+	}
+}
+
+/// Market quality at one Book snapshot.
+/// time: Duration -> virtual time of the snapshot
+/// spread: Option<f64> -> quoted spread, None if a side is empty
+/// bid_depth, ask_depth: f64 -> quantity within the monitor's depth of each best price
+/// imbalance: Option<f64> -> (bid_depth - ask_depth) / (bid_depth + ask_depth)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookPoint {
+	pub time: Duration,
+	pub spread: Option<f64>,
+	pub bid_depth: f64,
+	pub ask_depth: f64,
+	pub imbalance: Option<f64>,
+}
+
+/// The cost of one trade to its aggressor. With d = 1 for a buy and -1 for a sell, m the
+/// midpoint before the trade and m_h the midpoint a horizon after it, the effective
+/// spread is 2d(p - m), the realized spread 2d(p - m_h) and the price impact 2d(m_h - m),
+/// so effective = realized + impact. None where the aggressor or a midpoint is unknown,
+/// e.g. for batch auction fills or horizons past the end of the run.
+/// time: Duration -> virtual time of the trade
+/// price, quantity: f64 -> the trade's price and quantity
+/// effective_spread: Option<f64> -> what the aggressor paid over the midpoint, doubled
+/// realized_spread: Vec<Option<f64>> -> what the passive side kept, per horizon
+/// price_impact: Vec<Option<f64>> -> how far the midpoint moved against the passive side, per horizon
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradePoint {
+	pub time: Duration,
+	pub price: f64,
+	pub quantity: f64,
+	pub effective_spread: Option<f64>,
+	pub realized_spread: Vec<Option<f64>>,
+	pub price_impact: Vec<Option<f64>>,
+}
+
+/// Liquidity and market quality over a run, as time series and their averages.
+/// horizons: Vec<Duration> -> horizons the realized spreads and price impacts are measured at
+/// book: Vec<BookPoint> -> the Book after every change
+/// trades: Vec<TradePoint> -> every trade
+/// time_weighted_spread: Option<f64> -> quoted spread averaged over the time both sides were quoted
+/// effective_spread: Option<f64> -> volume-weighted mean effective spread
+/// realized_spread, price_impact: Vec<Option<f64>> -> volume-weighted means per horizon
+/// mean_bid_depth, mean_ask_depth: f64 -> time-weighted mean depths
+/// mean_imbalance: Option<f64> -> time-weighted mean order book imbalance
+/// fill_ratios: BTreeMap<String, f64> -> each trader's quantity filled over quantity shouted
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiquidityReport {
+	pub horizons: Vec<Duration>,
+	pub book: Vec<BookPoint>,
+	pub trades: Vec<TradePoint>,
+	pub time_weighted_spread: Option<f64>,
+	pub effective_spread: Option<f64>,
+	pub realized_spread: Vec<Option<f64>>,
+	pub price_impact: Vec<Option<f64>>,
+	pub mean_bid_depth: f64,
+	pub mean_ask_depth: f64,
+	pub mean_imbalance: Option<f64>,
+	pub fill_ratios: BTreeMap<String, f64>,
+}
+
+impl LiquidityReport {
+	/// The averages without the time series, flattened to one value per key with the
+	/// horizon in the keys of the realized spreads and price impacts
+	pub fn summary_json(&self) -> serde_json::Value {
+		let mut summary = json!({
+			"time_weighted_spread": self.time_weighted_spread,
+			"effective_spread": self.effective_spread,
+			"mean_bid_depth": self.mean_bid_depth,
+			"mean_ask_depth": self.mean_ask_depth,
+			"mean_imbalance": self.mean_imbalance,
+		});
+		for (i, horizon) in self.horizons.iter().enumerate() {
+			summary[format!("realized_spread_{}ms", horizon.as_millis())] = json!(self.realized_spread[i]);
+			summary[format!("price_impact_{}ms", horizon.as_millis())] = json!(self.price_impact[i]);
+		}
+		summary
+	}
+
+	pub fn to_json(&self) -> serde_json::Value {
+		let millis = |d: &Duration| d.as_secs_f64() * 1_000.0;
+		json!({
+			"horizons_ms": self.horizons.iter().map(millis).collect::<Vec<f64>>(),
+			"time_weighted_spread": self.time_weighted_spread,
+			"effective_spread": self.effective_spread,
+			"realized_spread": self.realized_spread,
+			"price_impact": self.price_impact,
+			"mean_bid_depth": self.mean_bid_depth,
+			"mean_ask_depth": self.mean_ask_depth,
+			"mean_imbalance": self.mean_imbalance,
+			"fill_ratios": self.fill_ratios,
+			"book": self.book.iter().map(|p| json!({
+				"time_ms": millis(&p.time),
+				"spread": p.spread,
+				"bid_depth": p.bid_depth,
+				"ask_depth": p.ask_depth,
+				"imbalance": p.imbalance,
+			})).collect::<Vec<serde_json::Value>>(),
+			"trades": self.trades.iter().map(|t| json!({
+				"time_ms": millis(&t.time),
+				"price": t.price,
+				"quantity": t.quantity,
+				"effective_spread": t.effective_spread,
+				"realized_spread": t.realized_spread,
+				"price_impact": t.price_impact,
+			})).collect::<Vec<serde_json::Value>>(),
+		})
+	}
+}
+
+/// Records the Books and trades of a Harness run to measure its liquidity. The harness
+/// passes the monitor every order it is sent and a snapshot of the Books with the trades
+/// after every arrival and batch auction, so the series change exactly when the market does.
+/// tick: f64 -> the price increment depth is measured in
+/// depth_ticks: u32 -> depth and imbalance count the quantity within this many ticks of the best price
+/// horizons: Vec<Duration> -> horizons to measure realized spreads and price impacts at
+/// snapshots: Vec<BookSnapshot> -> the Books after every change, in time order
+/// trades: Vec<(Trade, Option<f64>)> -> every trade with the midpoint before it
+/// shouted: BTreeMap<String, f64> -> quantity each trader has entered or updated orders for
+pub struct LiquidityMonitor {
+	pub tick: f64,
+	pub depth_ticks: u32,
+	pub horizons: Vec<Duration>,
+	pub snapshots: Vec<BookSnapshot>,
+	pub trades: Vec<(Trade, Option<f64>)>,
+	pub shouted: BTreeMap<String, f64>,
+}
+
+impl LiquidityMonitor {
+	pub fn new(tick: f64, depth_ticks: u32, horizons: Vec<Duration>) -> LiquidityMonitor {
+		LiquidityMonitor {
+			tick,
+			depth_ticks,
+			horizons,
+			snapshots: Vec::new(),
+			trades: Vec::new(),
+			shouted: BTreeMap::new(),
+		}
+	}
+
+	fn distance(&self) -> f64 {
+		self.tick * self.depth_ticks as f64
+	}
+
+	/// Records an order sent to the exchange
+	pub fn on_order(&mut self, order: &Order) {
+		if order.order_type != OrderType::Cancel {
+			*self.shouted.entry(order.trader_id.clone()).or_insert(0.0) += order.quantity;
+		}
+	}
+
+	/// Records the trades the market made at time, against the midpoint before them, and
+	/// the state of the Books they left
+	pub fn observe(&mut self, time: Duration, bids: &Book, asks: &Book, trades: &[Trade]) {
+		let midpoint = self.snapshots.last().and_then(|s| s.midpoint());
+		self.trades.extend(trades.iter().map(|t| (t.clone(), midpoint)));
+		let snapshot = BookSnapshot::take(time, bids, asks, self.distance());
+		// Several changes at the same time leave only the last state
+		if self.snapshots.last().is_some_and(|s| s.time == time) {
+			self.snapshots.pop();
+		}
+		self.snapshots.push(snapshot);
+	}
+
+	// The midpoint in force at time, None past the end of the run
+	fn midpoint_at(&self, time: Duration, end: Duration) -> Option<f64> {
+		if time > end {
+			return None;
+		}
+		let i = self.snapshots.partition_point(|s| s.time <= time);
+		if i == 0 { None } else { self.snapshots[i - 1].midpoint() }
+	}
+
+	/// The liquidity of the run from its first snapshot until end
+	pub fn report(&self, end: Duration) -> LiquidityReport {
+		let distance = self.distance();
+		let book: Vec<BookPoint> = self.snapshots.iter().map(|s| {
+			let (bid_depth, ask_depth) = s.depth(distance);
+			BookPoint { time: s.time, spread: s.spread(), bid_depth, ask_depth, imbalance: s.imbalance(distance) }
+		}).collect();
+
+		// Each snapshot holds until the next one or the end
+		let held: Vec<f64> = self.snapshots.iter().enumerate().map(|(i, s)| {
+			let until = self.snapshots.get(i + 1).map_or(end, |n| n.time);
+			until.checked_sub(s.time).map_or(0.0, |d| d.as_secs_f64())
+		}).collect();
+		let time_weighted = |value: &dyn Fn(&BookPoint) -> Option<f64>| -> Option<f64> {
+			let (sum, weight) = book.iter().zip(held.iter())
+				.filter_map(|(p, w)| value(p).map(|v| (v * w, *w)))
+				.fold((0.0, 0.0), |(s, t), (v, w)| (s + v, t + w));
+			if weight > 0.0 { Some(sum / weight) } else { None }
+		};
+
+		let trades: Vec<TradePoint> = self.trades.iter().map(|(trade, midpoint)| {
+			let direction = match trade.aggressor {
+				Some(TradeType::Bid) => Some(1.0),
+				Some(TradeType::Ask) => Some(-1.0),
+				None => None,
+			};
+			let before = direction.and(*midpoint);
+			let after: Vec<Option<f64>> = self.horizons.iter().map(|h| {
+				before.and_then(|_| self.midpoint_at(trade.time + *h, end))
+			}).collect();
+			let signed = |x: f64| 2.0 * direction.unwrap() * x;
+			TradePoint {
+				time: trade.time,
+				price: trade.price,
+				quantity: trade.quantity,
+				effective_spread: before.map(|m| signed(trade.price - m)),
+				realized_spread: after.iter().map(|a| a.map(|m| signed(trade.price - m))).collect(),
+				price_impact: after.iter().map(|a| a.map(|m| signed(m - before.unwrap()))).collect(),
+			}
+		}).collect();
+		let volume_weighted = |value: &dyn Fn(&TradePoint) -> Option<f64>| -> Option<f64> {
+			let (sum, volume) = trades.iter()
+				.filter_map(|t| value(t).map(|v| (v * t.quantity, t.quantity)))
+				.fold((0.0, 0.0), |(s, q), (v, w)| (s + v, q + w));
+			if volume > 0.0 { Some(sum / volume) } else { None }
+		};
+
+		let mut filled = BTreeMap::<&str, f64>::new();
+		for (trade, _) in self.trades.iter() {
+			*filled.entry(trade.bid_id.as_str()).or_insert(0.0) += trade.quantity;
+			*filled.entry(trade.ask_id.as_str()).or_insert(0.0) += trade.quantity;
+		}
+		let fill_ratios = self.shouted.iter()
+			.filter(|(_, shouted)| **shouted > 0.0)
+			.map(|(id, shouted)| (id.clone(), filled.get(id.as_str()).unwrap_or(&0.0) / shouted))
+			.collect();
+
+		LiquidityReport {
+			horizons: self.horizons.clone(),
+			time_weighted_spread: time_weighted(&|p| p.spread),
+			effective_spread: volume_weighted(&|t| t.effective_spread),
+			realized_spread: (0..self.horizons.len()).map(|i| volume_weighted(&|t| t.realized_spread[i])).collect(),
+			price_impact: (0..self.horizons.len()).map(|i| volume_weighted(&|t| t.price_impact[i])).collect(),
+			mean_bid_depth: time_weighted(&|p| Some(p.bid_depth)).unwrap_or(0.0),
+			mean_ask_depth: time_weighted(&|p| Some(p.ask_depth)).unwrap_or(0.0),
+			mean_imbalance: time_weighted(&|p| p.imbalance),
+			book,
+			trades,
+			fill_ratios,
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::harness::Harness;
+	use crate::test_utils::order;
+
+	#[test]
+	fn test_liquidity_report() {
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		harness.set_liquidity_monitor(Some(LiquidityMonitor::new(1.0, 1, vec![Duration::from_millis(10)])));
+		harness.submit(order("mm", TradeType::Bid, 99.0, 2.0));
+		harness.submit(order("mm", TradeType::Bid, 98.0, 1.0));
+		harness.submit(order("mm", TradeType::Ask, 101.0, 2.0));
+		harness.submit(order("deep", TradeType::Ask, 105.0, 5.0));
+		harness.advance_to(Duration::from_millis(10));
+
+		// A buyer lifts the ask at 101 against a midpoint of 100, then the quotes move up
+		harness.submit(order("taker", TradeType::Bid, 101.0, 1.0));
+		harness.advance_to(Duration::from_millis(20));
+		harness.submit(order("mm", TradeType::Bid, 100.0, 1.0));
+		harness.advance_to(Duration::from_millis(40));
+
+		let report = harness.liquidity_monitor().unwrap().report(harness.now());
+		let snapshot = &harness.liquidity_monitor().unwrap().snapshots[0];
+		assert_eq!((snapshot.bids.clone(), snapshot.asks.clone()), (vec![(99.0, 2.0), (98.0, 1.0)], vec![(101.0, 2.0)]));
+		assert_eq!(report.book[0].imbalance, Some(1.0 / 5.0));
+
+		// Spread 2 for 20ms, then 1 for 20ms after the bid moves to 100
+		assert_eq!(report.time_weighted_spread, Some(1.5));
+		let trade = &report.trades[0];
+		assert_eq!(trade.effective_spread, Some(2.0));
+		// By 20ms the midpoint is 100.5, half the spread was lost to the price moving
+		assert_eq!((trade.realized_spread[0], trade.price_impact[0]), (Some(1.0), Some(1.0)));
+		assert_eq!(report.realized_spread[0].unwrap() + report.price_impact[0].unwrap(), report.effective_spread.unwrap());
+
+		assert_eq!(report.fill_ratios["taker"], 1.0);
+		assert_eq!(report.fill_ratios["mm"], 1.0 / 6.0);
+		assert_eq!(report.fill_ratios["deep"], 0.0);
+		assert_eq!(report.to_json()["trades"][0]["effective_spread"], 2.0);
+		assert_eq!(report.summary_json()["realized_spread_10ms"], 1.0);
+	}
+}
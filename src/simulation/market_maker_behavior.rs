@@ -0,0 +1,281 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::exchange::trade::Trade;
+use crate::simulation::strategy::{MarketEvent, Strategy};
+
+use std::time::Duration;
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// The Avellaneda-Stoikov parameters of a MarketMaker.
+/// gamma: f64 -> risk aversion, how strongly inventory skews the quotes
+/// sigma: f64 -> volatility of the fair value per square root second
+/// k: f64 -> decay of the fill intensity with distance from the fair value
+/// horizon: f64 -> seconds the maker expects to hold inventory, T - t in the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryModel {
+	pub gamma: f64,
+	pub sigma: f64,
+	pub k: f64,
+	pub horizon: f64,
+}
+
+impl InventoryModel {
+	pub fn new(gamma: f64, sigma: f64, k: f64, horizon: f64) -> InventoryModel {
+		InventoryModel {
+			gamma,
+			sigma,
+			k,
+			horizon,
+		}
+	}
+
+	/// The fair value shifted against the inventory, s - q * gamma * sigma^2 * horizon
+	pub fn reservation_price(&self, fair_value: f64, inventory: f64) -> f64 {
+		fair_value - inventory * self.gamma * self.sigma.powi(2) * self.horizon
+	}
+
+	/// The optimal distance between the bid and the ask,
+	/// gamma * sigma^2 * horizon + (2 / gamma) * ln(1 + gamma / k)
+	pub fn spread(&self) -> f64 {
+		self.gamma * self.sigma.powi(2) * self.horizon + (2.0 / self.gamma) * (1.0 + self.gamma / self.k).ln()
+	}
+
+	/// The bid and ask centred on the reservation price
+	pub fn quotes(&self, fair_value: f64, inventory: f64) -> (f64, f64) {
+		let r = self.reservation_price(fair_value, inventory);
+		let half = self.spread() / 2.0;
+		(r - half, r + half)
+	}
+}
+
+/// A market maker that continuously quotes both sides around its estimate of the fair
+/// value, skewing the quotes against its inventory as in Avellaneda-Stoikov. The fair
+/// value follows the last lit trade unless it is set from outside, e.g. by a public
+/// fair value process. After every round of events the maker updates a quote that has
+/// moved more than tolerance from its target, enters one that has been filled and
+/// cancels the side that would take its inventory past max_inventory. Its orders reach
+/// the exchange after latency, leaving stale quotes in the Book until they arrive.
+/// trader_id: String -> trader_id both quotes are sent under
+/// model: InventoryModel -> how the quotes are set from the fair value and inventory
+/// size: f64 -> quantity quoted on each side
+/// max_inventory: f64 -> the maker stops quoting the side that would grow a larger position
+/// tolerance: f64 -> how far a quote may drift from its target before it is updated
+/// latency: Duration -> delay between the maker requoting and the orders arriving
+/// fair_value: f64 -> the maker's estimate of the fair value
+/// inventory: f64 -> shares held, negative when short
+/// cash: f64 -> cash received from selling less cash paid for buying
+/// fills: Vec<Trade> -> every trade the maker was a side of
+pub struct MarketMaker {
+	pub trader_id: String,
+	pub model: InventoryModel,
+	pub size: f64,
+	pub max_inventory: f64,
+	pub tolerance: f64,
+	pub latency: Duration,
+	pub fair_value: f64,
+	pub inventory: f64,
+	pub cash: f64,
+	pub fills: Vec<Trade>,
+	// The resting quotes as (price, remaining quantity)
+	bid: Option<(f64, f64)>,
+	ask: Option<(f64, f64)>,
+	follow_trades: bool,
+}
+
+impl MarketMaker {
+	pub fn new(trader_id: String, model: InventoryModel, size: f64, max_inventory: f64, fair_value: f64) -> MarketMaker {
+		MarketMaker {
+			trader_id,
+			model,
+			size,
+			max_inventory,
+			tolerance: 0.01,
+			latency: Duration::from_millis(0),
+			fair_value,
+			inventory: 0.0,
+			cash: 0.0,
+			fills: Vec::new(),
+			bid: None,
+			ask: None,
+			follow_trades: true,
+		}
+	}
+
+	/// Sets the fair value from outside. The maker stops following the trades from then on.
+	pub fn set_fair_value(&mut self, fair_value: f64) {
+		self.fair_value = fair_value;
+		self.follow_trades = false;
+	}
+
+	/// The resting bid and ask prices
+	pub fn quotes(&self) -> (Option<f64>, Option<f64>) {
+		(self.bid.map(|b| b.0), self.ask.map(|a| a.0))
+	}
+
+	/// Profit marked to the fair value, cash plus inventory at fair value
+	pub fn pnl(&self) -> f64 {
+		self.cash + self.inventory * self.fair_value
+	}
+
+	/// Records a fill of one of the maker's quotes
+	pub fn on_fill(&mut self, trade: &Trade) {
+		let (bought, sold) = (trade.bid_id == self.trader_id, trade.ask_id == self.trader_id);
+		if bought {
+			MarketMaker::reduce(&mut self.bid, trade.quantity);
+			self.inventory += trade.quantity;
+			self.cash -= trade.quantity * trade.price;
+		}
+		if sold {
+			MarketMaker::reduce(&mut self.ask, trade.quantity);
+			self.inventory -= trade.quantity;
+			self.cash += trade.quantity * trade.price;
+		}
+		if bought || sold {
+			self.fills.push(trade.clone());
+		}
+	}
+
+	fn reduce(quote: &mut Option<(f64, f64)>, quantity: f64) {
+		if let Some((_, remaining)) = quote {
+			*remaining -= quantity;
+			if *remaining <= EPSILON {
+				*quote = None;
+			}
+		}
+	}
+
+	// Brings one side's quote to its target, None to pull the quote
+	fn requote(&mut self, trade_type: TradeType, target: Option<f64>) -> Option<Order> {
+		let size = self.size;
+		let tolerance = self.tolerance;
+		let quote = match trade_type {
+			TradeType::Bid => &mut self.bid,
+			TradeType::Ask => &mut self.ask,
+		};
+		let order_type = match (*quote, target) {
+			(None, None) => return None,
+			(Some((price, _)), Some(target)) if (price - target).abs() <= tolerance => return None,
+			(Some(_), None) => OrderType::Cancel,
+			(Some(_), Some(_)) => OrderType::Update,
+			(None, Some(_)) => OrderType::Enter,
+		};
+		let price = target.or(quote.map(|q| q.0)).expect("quote or target");
+		*quote = target.map(|p| (p, size));
+		Some(Order::new(self.trader_id.clone(), order_type, trade_type, price, size))
+	}
+
+	/// Orders bringing both quotes to their targets for the current fair value and inventory
+	pub fn requote_all(&mut self) -> Vec<Order> {
+		let (bid, ask) = self.model.quotes(self.fair_value, self.inventory);
+		let bid = if self.inventory + self.size <= self.max_inventory + EPSILON { Some(bid) } else { None };
+		let ask = if self.inventory - self.size >= -self.max_inventory - EPSILON { Some(ask) } else { None };
+		// When the quotes move up the ask moves first, so the new bid can't reach the old
+		// ask before it has moved, and the other way around when they move down
+		if bid.is_some_and(|b| self.ask.is_some_and(|(a, _)| b >= a)) {
+			let ask = self.requote(TradeType::Ask, ask);
+			ask.into_iter().chain(self.requote(TradeType::Bid, bid)).collect()
+		} else {
+			let bid = self.requote(TradeType::Bid, bid);
+			bid.into_iter().chain(self.requote(TradeType::Ask, ask)).collect()
+		}
+	}
+}
+
+impl Strategy for MarketMaker {
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		self.requote_all()
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		match event {
+			MarketEvent::Trade(trade) => {
+				self.on_fill(trade);
+				if self.follow_trades && !trade.off_book {
+					self.fair_value = trade.price;
+				}
+			},
+			MarketEvent::FairValue(value) => self.set_fair_value(*value),
+			_ => {},
+		}
+	}
+
+	fn react(&mut self, _now: Duration) -> Vec<Order> {
+		self.requote_all()
+	}
+
+	fn latency(&self) -> Duration {
+		self.latency
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::harness::Harness;
+	use crate::simulation::seed::ExperimentSeed;
+	use crate::simulation::strategy::StrategyRunner;
+	use crate::simulation::zic_behavior::SupplyDemand;
+
+	fn maker() -> MarketMaker {
+		MarketMaker::new(String::from("mm"), InventoryModel::new(0.1, 2.0, 1.5, 1.0), 1.0, 3.0, 100.0)
+	}
+
+	#[test]
+	fn test_inventory_skew() {
+		let mut mm = maker();
+		let orders = mm.requote_all();
+		assert_eq!(orders.len(), 2);
+		let (bid, ask) = mm.quotes();
+		let (bid, ask) = (bid.unwrap(), ask.unwrap());
+		assert!((100.0 - bid - (ask - 100.0)).abs() < EPSILON);
+		assert!((ask - bid - mm.model.spread()).abs() < EPSILON);
+		// Nothing changed so nothing is resent
+		assert!(mm.requote_all().is_empty());
+
+		// Buying at the bid shifts both quotes down to shed the inventory
+		mm.on_fill(&Trade::new(String::from("mm"), String::from("x"), Some(TradeType::Ask), bid, 1.0));
+		assert_eq!((mm.inventory, mm.cash), (1.0, -bid));
+		let orders = mm.requote_all();
+		assert_eq!(orders[0].order_type, OrderType::Enter);
+		assert_eq!(orders[1].order_type, OrderType::Update);
+		assert!(mm.quotes().0.unwrap() < bid && mm.quotes().1.unwrap() < ask);
+
+		// A jump past the spread moves the ask up before the bid so the maker doesn't trade
+		// with itself
+		mm.set_fair_value(105.0);
+		let orders = mm.requote_all();
+		assert_eq!((&orders[0].trade_type, &orders[1].trade_type), (&TradeType::Ask, &TradeType::Bid));
+		assert!(orders[1].price > ask);
+
+		// At the inventory limit the bid is pulled
+		mm.inventory = 3.0;
+		let orders = mm.requote_all();
+		assert_eq!((&orders[0].order_type, &orders[0].trade_type), (&OrderType::Cancel, &TradeType::Bid));
+		assert_eq!(mm.quotes().0, None);
+	}
+
+	#[test]
+	fn test_pnl_accounting() {
+		let seed = ExperimentSeed::new(4);
+		let market = SupplyDemand::uniform(30, (80.0, 120.0), (80.0, 120.0), &mut seed.stream("schedules"));
+		let mut traders = market.zic_agents((1.0, 200.0), seed);
+		let mut mm = maker();
+		let mut agents: Vec<&mut dyn Strategy> = traders.iter_mut().map(|a| a as &mut dyn Strategy).collect();
+		agents.push(&mut mm);
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		StrategyRunner::in_process(&mut harness, &mut agents, 1, 500, seed);
+
+		// The maker's books agree with the exchange's trades
+		let fills: Vec<Trade> = harness.trade_log.since(0).into_iter().filter(|t| t.bid_id == "mm" || t.ask_id == "mm").collect();
+		assert!(!fills.is_empty());
+		assert_eq!(mm.fills, fills);
+		let inventory: f64 = fills.iter().map(|t| if t.bid_id == "mm" { t.quantity } else { -t.quantity }).sum();
+		let cash: f64 = fills.iter().map(|t| if t.bid_id == "mm" { -t.quantity * t.price } else { t.quantity * t.price }).sum();
+		assert!((mm.inventory - inventory).abs() < EPSILON);
+		assert!((mm.cash - cash).abs() < 0.000_001);
+		assert!(mm.inventory.abs() <= mm.max_inventory);
+		assert!((mm.pnl() - (cash + inventory * mm.fair_value)).abs() < 0.000_001);
+	}
+}
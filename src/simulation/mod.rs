@@ -1,3 +1,15 @@
 pub mod trader;
 pub mod trader_behavior;
-pub mod random_behavior;
\ No newline at end of file
+pub mod random_behavior;
+pub mod sandwich_behavior;
+pub mod harness;
+pub mod seed;
+pub mod arrivals;
+pub mod strategy;
+pub mod zic_behavior;
+pub mod adaptive_behavior;
+pub mod market_maker_behavior;
+pub mod latency_arbitrage;
+pub mod efficiency;
+pub mod liquidity;
+pub mod experiment;
@@ -1,24 +1,79 @@
 use crate::simulation::trader_behavior;
+use crate::simulation::trader_behavior::OrderRange;
 use crate::order::{Order};
 use crate::simulation::trader::Traders;
+use crate::simulation::harness::Harness;
+use crate::simulation::seed::ExperimentSeed;
+use crate::simulation::strategy::Strategy;
+use crate::simulation::arrivals::ArrivalProcess;
 use crate::exchange::order_processing::JsonOrder;
 use crate::controller::Task;
 use crate::io::tcp_json;
 use crate::io::ws_json;
 use crate::utility::get_time;
 
-use std::sync::Arc;
+use rand::rngs::StdRng;
+use std::sync::{Mutex, Arc};
 use std::thread;
+use std::time::Duration;
 
 
-pub struct RandBehavior {}
+/// The parameters of random order flow. Missing fields take their defaults when read
+/// from an experiment config.
+/// intervals: (u64, u64, u64) -> milliseconds between batches of arrivals, updates and cancels
+/// max_enters: u64 -> each batch of arrivals has up to this many bids and as many asks
+/// upper: u32 -> a trader is updated or cancelled with probability 1 / upper each batch
+/// cancel_delay: u64 -> milliseconds between deciding on a cancel and sending it
+/// range: OrderRange -> prices and quantities of the random orders
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RandomFlow {
+	pub intervals: (u64, u64, u64),
+	pub max_enters: u64,
+	pub upper: u32,
+	pub cancel_delay: u64,
+	pub range: OrderRange,
+}
+
+impl Default for RandomFlow {
+	fn default() -> Self {
+		RandomFlow {
+			intervals: (500, 1000, 2000),
+			max_enters: 10,
+			upper: 10,
+			cancel_delay: 1000,
+			range: OrderRange::default(),
+		}
+	}
+}
+
+/// Random order flow. Each task draws from its own substream of the experiment seed.
+/// As a Strategy it sends a random batch of arrivals, updates and cancels whenever it acts.
+/// traders: Arc<Traders> -> the orders of the random traders it has sent
+/// flow: RandomFlow -> the batch sizes, update and cancel probabilities and order ranges
+pub struct RandBehavior {
+	pub traders: Arc<Traders>,
+	pub flow: RandomFlow,
+	rng: StdRng,
+}
 
 impl RandBehavior {
+	pub fn new(traders: Arc<Traders>, flow: RandomFlow, seed: ExperimentSeed) -> RandBehavior {
+		RandBehavior {
+			traders,
+			flow,
+			rng: seed.stream("rand_behavior"),
+		}
+	}
+
 	// Generates a random number of new traders on a fixed interval over tcp
-	pub fn tcp_arrival_interval(traders: Arc<Traders>, duration: u64, address: String) -> Task {
+	pub fn tcp_arrival_interval(traders: Arc<Traders>, flow: &RandomFlow, address: String, seed: ExperimentSeed) -> Task {
+		let rng = Mutex::new(seed.stream("tcp_arrivals"));
+		let (max_enters, range) = (flow.max_enters, flow.range.clone());
 		Task::rpt_task(move || {
+			let mut rng = rng.lock().unwrap();
 			// Make new random orders
-	            let orders: Vec<Order> = trader_behavior::rand_enters(10);
+	            let orders: Vec<Order> = trader_behavior::rand_enters(max_enters, &range, &mut *rng);
 	            println!("{} new arrivals!", orders.len());
 
 	            // Send them over JSON
@@ -32,29 +87,33 @@ impl RandBehavior {
 	            // Save new traders in the traders HashMap
 	            traders.new_traders(orders);
 	            println!("num_traders: {}", traders.num_traders());
-	        }, duration)
+	        }, flow.intervals.0)
 	}
 
 	// Updates a random number of existing traders on a fixed interval over tcp
-	pub fn tcp_update_interval(traders: Arc<Traders>, duration: u64, address: String) -> Task {
+	pub fn tcp_update_interval(traders: Arc<Traders>, flow: &RandomFlow, address: String, seed: ExperimentSeed) -> Task {
+		let rng = Mutex::new(seed.stream("tcp_updates"));
+		let (upper, range) = (flow.upper, flow.range.clone());
 		Task::rpt_task(move || {
-			let rng_upper = 10;
-            let update_orders = trader_behavior::gen_rand_updates(Arc::clone(&traders), rng_upper);
+			let mut rng = rng.lock().unwrap();
+            let update_orders = trader_behavior::gen_rand_updates(Arc::clone(&traders), upper, &range, &mut *rng);
             println!("updating {} traders", update_orders.len());
             for order in update_orders {
             	let json_order = JsonOrder::params_to_json(order);
                 let json_send_task = tcp_json::tcp_send_json(json_order, address.clone()).task;
                 tokio::spawn(json_send_task);
             }
-		}, duration)
+		}, flow.intervals.1)
 	}
 
 	// Cancels a random number of existing traders on a fixed interval over tcp
-	pub fn tcp_cancel_interval(traders: Arc<Traders>, duration: u64, address: String) -> Task {
+	pub fn tcp_cancel_interval(traders: Arc<Traders>, flow: &RandomFlow, address: String, seed: ExperimentSeed) -> Task {
+		let rng = Mutex::new(seed.stream("tcp_cancels"));
+		let (upper, delay) = (flow.upper, flow.cancel_delay);
 		Task::rpt_task(move || {
+			let mut rng = rng.lock().unwrap();
 			println!("cancel trader!");
-            let rng_upper = 10;
-            let cancel_orders = trader_behavior::gen_rand_cancels(Arc::clone(&traders), rng_upper);
+            let cancel_orders = trader_behavior::gen_rand_cancels(Arc::clone(&traders), upper, &mut *rng);
             println!("cancelling {} traders", cancel_orders.len());
             for order in cancel_orders {
                 println!("time: {:?}, cancelling: {:?} ", get_time(), order.0);
@@ -64,23 +123,26 @@ impl RandBehavior {
                 	let json_order = JsonOrder::params_to_json(order.clone());
                 	let json_send_task = tcp_json::tcp_send_json(json_order, addr.clone()).task;
                     tokio::spawn(json_send_task);
-                }, 1000).task;
+                }, delay).task;
 
                 tokio::spawn(send_cancel);
             }
-		}, duration)
+		}, flow.intervals.2)
 	}
 
 	// Generates a random number of new traders on a fixed interval over tcp
-	pub fn ws_arrival_interval(traders: Arc<Traders>, duration: u64, address: &'static str) -> Task {
+	pub fn ws_arrival_interval(traders: Arc<Traders>, flow: &RandomFlow, address: &'static str, seed: ExperimentSeed) -> Task {
+		let rng = Mutex::new(seed.stream("ws_arrivals"));
+		let (max_enters, range) = (flow.max_enters, flow.range.clone());
 		Task::rpt_task(move || {
+			let mut rng = rng.lock().unwrap();
 			// Make new random orders
-	            let orders: Vec<Order> = trader_behavior::rand_enters(10);
+	            let orders: Vec<Order> = trader_behavior::rand_enters(max_enters, &range, &mut *rng);
 	            println!("{} new arrivals!", orders.len());
 
 	            // Send them over JSON
 	            for order in &orders {
-	            	let addr = address.clone();
+	            	let addr = address;
 	                // Don't want a full clone of the order, just params to make json
 	                let json_order = JsonOrder::order_to_json(order);
 	                // Spawn the task to send json over tcp
@@ -91,17 +153,19 @@ impl RandBehavior {
 	            // Save new traders in the traders HashMap
 	            traders.new_traders(orders);
 	            println!("num_traders: {}", traders.num_traders());
-	        }, duration)
+	        }, flow.intervals.0)
 	}
 
 	// Updates a random number of existing traders on a fixed interval over tcp
-	pub fn ws_update_interval(traders: Arc<Traders>, duration: u64, address: &'static str) -> Task {
+	pub fn ws_update_interval(traders: Arc<Traders>, flow: &RandomFlow, address: &'static str, seed: ExperimentSeed) -> Task {
+		let rng = Mutex::new(seed.stream("ws_updates"));
+		let (upper, range) = (flow.upper, flow.range.clone());
 		Task::rpt_task(move || {
-			let rng_upper = 10;
-            let update_orders = trader_behavior::gen_rand_updates(Arc::clone(&traders), rng_upper);
+			let mut rng = rng.lock().unwrap();
+            let update_orders = trader_behavior::gen_rand_updates(Arc::clone(&traders), upper, &range, &mut *rng);
             println!("updating {} traders", update_orders.len());
             for order in update_orders {
-            	let addr = address.clone();
+            	let addr = address;
 
             	let json_order = JsonOrder::params_to_json(order);
                 
@@ -109,40 +173,132 @@ impl RandBehavior {
                 	ws_json::ws_send_json(json_order, addr);
                 });
             }
-		}, duration)
+		}, flow.intervals.1)
 	}
 
 	// Cancels a random number of existing traders on a fixed interval over tcp
-	pub fn ws_cancel_interval(traders: Arc<Traders>, duration: u64, address: &'static str) -> Task {
+	pub fn ws_cancel_interval(traders: Arc<Traders>, flow: &RandomFlow, address: &'static str, seed: ExperimentSeed) -> Task {
+		let rng = Mutex::new(seed.stream("ws_cancels"));
+		let (upper, delay) = (flow.upper, flow.cancel_delay);
 		Task::rpt_task(move || {
+			let mut rng = rng.lock().unwrap();
 			println!("cancel trader!");
-            let rng_upper = 10;
-            let cancel_orders = trader_behavior::gen_rand_cancels(Arc::clone(&traders), rng_upper);
+            let cancel_orders = trader_behavior::gen_rand_cancels(Arc::clone(&traders), upper, &mut *rng);
             println!("cancelling {} traders", cancel_orders.len());
             for order in cancel_orders {
                 println!("time: {:?}, cancelling: {:?} ", get_time(), order.0);
-                let addr = address.clone();
+                let addr = address;
                 // Send a cancel message after a delay
                 let send_cancel = Task::delay_task(move || {
-                	let addr = addr.clone();
+                	let addr = addr;
                 	let json_order = JsonOrder::params_to_json(order.clone());
                 	let _h = thread::spawn(move || {
                 		ws_json::ws_send_json(json_order, addr);
                 	});
-                }, 1000).task;
+                }, delay).task;
 
                 tokio::spawn(send_cancel);
             }
-		}, duration)
+		}, flow.intervals.2)
 	}
-}
-
-
-
-
-
-
 
+	// Runs the same random arrivals, updates and cancels as the interval tasks against an
+	// in-process harness until the virtual time reaches until, without opening any ports.
+	// until is in milliseconds and cancels still arrive the flow's cancel_delay after they
+	// are decided. Returns the number of orders submitted.
+	pub fn in_process(harness: &mut Harness, traders: Arc<Traders>, flow: &RandomFlow, until: u64, seed: ExperimentSeed) -> usize {
+		let (arrival, update, cancel) = flow.intervals;
+		let mut rngs = (seed.stream("arrivals"), seed.stream("updates"), seed.stream("cancels"));
+		let mut next = (arrival, update, cancel);
+		let mut submitted = 0;
+		loop {
+			let now = next.0.min(next.1).min(next.2);
+			if now > until {
+				break;
+			}
+			harness.advance_to(Duration::from_millis(now));
 
+			if now == next.0 {
+				let orders: Vec<Order> = trader_behavior::rand_enters(flow.max_enters, &flow.range, &mut rngs.0);
+				submitted += orders.len();
+				for order in &orders {
+					harness.submit(Order::new(order.trader_id.clone(), order.order_type.clone(),
+											  order.trade_type.clone(), order.price, order.quantity));
+				}
+				traders.new_traders(orders);
+				next.0 += arrival;
+			}
+			if now == next.1 {
+				for (t_id, ot, tt, p, q) in trader_behavior::gen_rand_updates(Arc::clone(&traders), flow.upper, &flow.range, &mut rngs.1) {
+					harness.submit(Order::new(t_id, ot, tt, p, q));
+					submitted += 1;
+				}
+				next.1 += update;
+			}
+			if now == next.2 {
+				for (t_id, ot, tt, p, q) in trader_behavior::gen_rand_cancels(Arc::clone(&traders), flow.upper, &mut rngs.2) {
+					harness.submit_after(Order::new(t_id, ot, tt, p, q), Duration::from_millis(flow.cancel_delay));
+					submitted += 1;
+				}
+				next.2 += cancel;
+			}
+		}
+		harness.advance_to(Duration::from_millis(until));
+		submitted
+	}
 
+	/// Drives random order flow against the harness with each kind of order arriving one
+	/// at a time at the times of its own process: a new trader's order at each arrival of
+	/// the enters process, an update of a random trader at each arrival of the updates
+	/// process and a cancel of a random trader at each arrival of the cancels process.
+	/// The orders are drawn from range. Runs until the virtual time until in milliseconds
+	/// and returns the number of orders sent.
+	pub fn with_arrivals(harness: &mut Harness, traders: Arc<Traders>, processes: (ArrivalProcess, ArrivalProcess, ArrivalProcess), range: &OrderRange, until: u64, seed: ExperimentSeed) -> usize {
+		let until = Duration::from_millis(until);
+		let mut arrivals = [processes.0.arrivals(seed.stream("enter_times")),
+							processes.1.arrivals(seed.stream("update_times")),
+							processes.2.arrivals(seed.stream("cancel_times"))];
+		let mut rngs = (seed.stream("arrivals"), seed.stream("updates"), seed.stream("cancels"));
+		let mut next: Vec<Option<Duration>> = arrivals.iter_mut().map(|a| a.next_arrival()).collect();
+		let mut submitted = 0;
+		// The earliest arrival among the three processes, ties going to enters first
+		while let Some(kind) = (0..3).filter(|&k| next[k].is_some_and(|t| t <= until)).min_by_key(|&k| next[k]) {
+			harness.advance_to(next[kind].expect("arrival time"));
+			let order = match kind {
+				0 => {
+					let order = trader_behavior::rand_enter(range, &mut rngs.0);
+					let copy = Order::new(order.trader_id.clone(), order.order_type.clone(),
+										  order.trade_type.clone(), order.price, order.quantity);
+					traders.new_traders(vec![order]);
+					Some(copy)
+				},
+				1 => trader_behavior::gen_rand_update(&traders, range, &mut rngs.1).map(|(t_id, ot, tt, p, q)| Order::new(t_id, ot, tt, p, q)),
+				_ => trader_behavior::gen_rand_cancel(&traders, &mut rngs.2).map(|(t_id, ot, tt, p, q)| Order::new(t_id, ot, tt, p, q)),
+			};
+			if let Some(order) = order {
+				harness.submit(order);
+				submitted += 1;
+			}
+			next[kind] = arrivals[kind].next_arrival();
+		}
+		harness.advance_to(until);
+		submitted
+	}
+}
 
+impl Strategy for RandBehavior {
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		let mut to_send: Vec<Order> = trader_behavior::gen_rand_updates(Arc::clone(&self.traders), self.flow.upper, &self.flow.range, &mut self.rng)
+			.into_iter()
+			.chain(trader_behavior::gen_rand_cancels(Arc::clone(&self.traders), self.flow.upper, &mut self.rng))
+			.map(|(t_id, ot, tt, p, q)| Order::new(t_id, ot, tt, p, q))
+			.collect();
+		let orders = trader_behavior::rand_enters(self.flow.max_enters, &self.flow.range, &mut self.rng);
+		for order in &orders {
+			to_send.push(Order::new(order.trader_id.clone(), order.order_type.clone(),
+									order.trade_type.clone(), order.price, order.quantity));
+		}
+		self.traders.new_traders(orders);
+		to_send
+	}
+}
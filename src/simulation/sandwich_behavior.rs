@@ -0,0 +1,285 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::exchange::order_book::Book;
+use crate::exchange::queue::Queue;
+use crate::exchange::queue_processing::QueueProcessor;
+use crate::exchange::auction::{MAX_PRICE, MIN_PRICE};
+use crate::exchange::trade::Trade;
+use crate::controller::Task;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const EPSILON: f64 = 0.000_000_001;
+
+/// An adversarial agent that watches the pending orders in the Queue (or a block being
+/// built from it) and sandwiches the largest marketable order. It buys the liquidity the
+/// victim would have taken below the victim's limit ahead of it, then sells it back to
+/// the victim at the limit from behind it (mirrored for a selling victim).
+/// trader_id: String -> trader_id the agent's orders are sent under
+/// max_qty: f64 -> most the agent will front-run in a single sandwich
+pub struct SandwichAgent {
+	pub trader_id: String,
+	pub max_qty: f64,
+}
+
+impl SandwichAgent {
+	pub fn new(trader_id: String, max_qty: f64) -> SandwichAgent {
+		SandwichAgent {
+			trader_id,
+			max_qty,
+		}
+	}
+
+	/// Plans the front-running and back-running orders around the victim from the current
+	/// Books. Returns None if there is no liquidity priced better than the victim's limit.
+	/// Market orders are skipped since they have no limit to sell back at.
+	pub fn plan(&self, victim: &Order, bids: &Book, asks: &Book) -> Option<(Order, Order)> {
+		if victim.price >= MAX_PRICE || victim.price <= MIN_PRICE {
+			return None;
+		}
+		let (book, front_side, back_side) = match victim.trade_type {
+			TradeType::Bid => (asks, TradeType::Bid, TradeType::Ask),
+			TradeType::Ask => (bids, TradeType::Ask, TradeType::Bid),
+		};
+		let better = |price: f64| match victim.trade_type {
+			TradeType::Bid => price < victim.price,
+			TradeType::Ask => price > victim.price,
+		};
+
+		// Walk the opposite Book from its best price while it is better than the victim's limit
+		let cap = self.max_qty.min(victim.quantity);
+		let mut qty = 0.0;
+		let mut worst = None;
+		for order in book.orders.lock().unwrap().iter().rev() {
+			if qty >= cap - EPSILON || !better(order.price) {
+				break;
+			}
+			qty += order.quantity.min(cap - qty);
+			worst = Some(order.price);
+		}
+
+		let worst = worst?;
+		let front = Order::new(self.trader_id.clone(), OrderType::Enter, front_side, worst, qty);
+		let back = Order::new(self.trader_id.clone(), OrderType::Enter, back_side, victim.price, qty);
+		Some((front, back))
+	}
+
+	/// Finds the largest pending marketable order from another trader and inserts the
+	/// sandwich around it in the Queue. Returns true if a victim was sandwiched.
+	pub fn attack_queue(&self, queue: &Queue, bids: &Book, asks: &Book) -> bool {
+		let (best_bid, best_ask) = (bids.get_max_price(), asks.get_min_price());
+		queue.with_pending(|pending| {
+			let victim = pending.iter().enumerate()
+				.filter(|(_, o)| o.trader_id != self.trader_id && o.order_type == OrderType::Enter)
+				.filter(|(_, o)| match o.trade_type {
+					TradeType::Bid => o.price >= best_ask,
+					TradeType::Ask => o.price <= best_bid,
+				})
+				.max_by(|(_, a), (_, b)| a.quantity.partial_cmp(&b.quantity).unwrap())
+				.map(|(i, _)| i);
+
+			let i = match victim {
+				Some(i) => i,
+				None => return false,
+			};
+			match self.plan(&pending[i], bids, asks) {
+				Some((front, back)) => {
+					pending.insert(i + 1, back);
+					pending.insert(i, front);
+					true
+				},
+				None => false,
+			}
+		})
+	}
+
+	/// The agent's profit from the trades, with any inventory it still holds marked at mark
+	pub fn profit(&self, trades: &[Trade], mark: f64) -> f64 {
+		let (cash, inventory) = trades.iter().fold((0.0, 0.0), |(cash, inventory), t| {
+			if t.bid_id == self.trader_id {
+				(cash - t.price * t.quantity, inventory + t.quantity)
+			} else if t.ask_id == self.trader_id {
+				(cash + t.price * t.quantity, inventory - t.quantity)
+			} else {
+				(cash, inventory)
+			}
+		});
+		cash + inventory * mark
+	}
+}
+
+/// The value an adversary extracted from one run of order flow compared to a baseline
+/// run of the same flow without it.
+/// baseline_surplus: f64 -> surplus the other traders realized without the adversary
+/// adversarial_surplus: f64 -> surplus the other traders realized with the adversary
+/// extracted_value: f64 -> the adversary's profit
+/// sandwiches: usize -> number of victims the adversary sandwiched
+#[derive(Debug, Clone, PartialEq)]
+pub struct MevReport {
+	pub baseline_surplus: f64,
+	pub adversarial_surplus: f64,
+	pub extracted_value: f64,
+	pub sandwiches: usize,
+}
+
+impl MevReport {
+	/// The surplus the other traders lost to the adversary
+	pub fn victim_loss(&self) -> f64 {
+		self.baseline_surplus - self.adversarial_surplus
+	}
+
+	pub fn to_json(&self) -> serde_json::Value {
+		json!({
+			"baseline_surplus": self.baseline_surplus,
+			"adversarial_surplus": self.adversarial_surplus,
+			"extracted_value": self.extracted_value,
+			"victim_loss": self.victim_loss(),
+			"sandwiches": self.sandwiches,
+		})
+	}
+}
+
+pub struct SandwichBehavior {}
+
+// The outcome of processing the order flow once.
+// surplus: f64 -> surplus the other traders realized
+// trades: Vec<Trade> -> every trade in the run
+// marks: Vec<Option<f64>> -> the Books' midpoint before each batch was processed
+// attacked: Vec<usize> -> the batches the agent sandwiched
+struct Run {
+	surplus: f64,
+	trades: Vec<Trade>,
+	marks: Vec<Option<f64>>,
+	attacked: Vec<usize>,
+}
+
+impl SandwichBehavior {
+	/// Runs the order flow twice on fresh exchanges, once without and once with the agent,
+	/// and reports the value it extracted. make_flow must return the same batches on every
+	/// call. Each batch is queued, attacked by the agent in the adversarial run and then
+	/// processed sequentially so the inserted orders keep their place. Inventory the agent
+	/// still holds is marked at the baseline midpoint before its last attack, so its own
+	/// back-running fills don't set the value of what it couldn't sell.
+	pub fn measure<F>(agent: &SandwichAgent, make_flow: F) -> MevReport
+	where F: Fn() -> Vec<Vec<Order>>
+	{
+		let baseline = SandwichBehavior::run(None, make_flow());
+		let adversarial = SandwichBehavior::run(Some(agent), make_flow());
+		let extracted_value = match adversarial.attacked.last() {
+			Some(&i) => {
+				let mark = baseline.marks[i].or(adversarial.marks[i]).unwrap_or(0.0);
+				agent.profit(&adversarial.trades, mark)
+			},
+			None => 0.0,
+		};
+		let report = MevReport {
+			baseline_surplus: baseline.surplus,
+			adversarial_surplus: adversarial.surplus,
+			extracted_value,
+			sandwiches: adversarial.attacked.len(),
+		};
+		log_event!("MEV run: {}", report.to_json());
+		report
+	}
+
+	// Processes the batches, sandwiching them if there is an agent
+	fn run(agent: Option<&SandwichAgent>, batches: Vec<Vec<Order>>) -> Run {
+		let (queue, bids, asks, _) = crate::setup_exchange();
+		let mut limits = HashMap::<String, f64>::new();
+		let mut trades = Vec::<Trade>::new();
+		let mut marks = Vec::new();
+		let mut attacked = Vec::new();
+
+		for (i, batch) in batches.into_iter().enumerate() {
+			marks.push(SandwichBehavior::midpoint(&bids, &asks));
+			for order in batch {
+				limits.insert(order.trader_id.clone(), order.price);
+				queue.add(order);
+			}
+			if let Some(agent) = agent {
+				if agent.attack_queue(&queue, &bids, &asks) {
+					attacked.push(i);
+				}
+			}
+			trades.extend(QueueProcessor::seq_process_order_queue(Arc::clone(&queue), Arc::clone(&bids), Arc::clone(&asks)));
+		}
+
+		let surplus = trades.iter().map(|t| {
+			let bid_surplus = limits.get(&t.bid_id).map_or(0.0, |limit| (limit - t.price) * t.quantity);
+			let ask_surplus = limits.get(&t.ask_id).map_or(0.0, |limit| (t.price - limit) * t.quantity);
+			bid_surplus + ask_surplus
+		}).sum();
+		Run { surplus, trades, marks, attacked }
+	}
+
+	// The midpoint of the best bid and ask, or the best price of the only side quoted.
+	// None if both Books are empty.
+	fn midpoint(bids: &Book, asks: &Book) -> Option<f64> {
+		match (bids.is_empty(), asks.is_empty()) {
+			(false, false) => Some((bids.get_max_price() + asks.get_min_price()) / 2.0),
+			(false, true) => Some(bids.get_max_price()),
+			(true, false) => Some(asks.get_min_price()),
+			(true, true) => None,
+		}
+	}
+
+	/// Sandwiches the largest marketable order waiting in the live Queue on an interval
+	/// determined by the duration parameter in milliseconds. Pair with a sequential
+	/// processor such as the block producer so the sandwich keeps its place.
+	pub fn sandwich_interval(agent: Arc<SandwichAgent>, queue: Arc<Queue>, bids: Arc<Book>, asks: Arc<Book>, duration: u64) -> Task {
+		Task::rpt_task(move || {
+			if agent.attack_queue(&queue, &bids, &asks) {
+				log_event!("{} sandwiched a pending order", agent.trader_id);
+			}
+		}, duration)
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn flow() -> Vec<Vec<Order>> {
+		vec![
+			vec![Order::new(String::from("s1"), OrderType::Enter, TradeType::Ask, 100.0, 5.0),
+				 Order::new(String::from("s2"), OrderType::Enter, TradeType::Ask, 101.0, 5.0)],
+			vec![Order::new(String::from("victim"), OrderType::Enter, TradeType::Bid, 105.0, 8.0)],
+		]
+	}
+
+	#[test]
+	fn test_sandwich_plan() {
+		let agent = SandwichAgent::new(String::from("mev"), 6.0);
+		let (queue, bids, asks, _) = crate::setup_exchange();
+		for order in flow().remove(0) {
+			asks.add_order(order).unwrap();
+		}
+		queue.add(Order::new(String::from("victim"), OrderType::Enter, TradeType::Bid, 105.0, 8.0));
+
+		assert!(agent.attack_queue(&queue, &bids, &asks));
+		let pending = queue.pop_all();
+		let ids: Vec<&str> = pending.iter().map(|o| o.trader_id.as_str()).collect();
+		assert_eq!(ids, ["mev", "victim", "mev"]);
+		assert_eq!((pending[0].price, pending[0].quantity), (101.0, 6.0));
+		assert_eq!((pending[2].price, pending[2].quantity), (105.0, 6.0));
+	}
+
+	#[test]
+	fn test_measure_extracted_value() {
+		let agent = SandwichAgent::new(String::from("mev"), 10.0);
+		let report = SandwichBehavior::measure(&agent, flow);
+
+		// Without the agent the victim buys 5 @100 and 3 @101 for a surplus of 25 + 12
+		assert_eq!(report.baseline_surplus, 37.0);
+		// The agent front-runs the 8 shares the victim wanted, leaving it 2 @101, and sells
+		// 6 back to it at its limit of 105. The 2 shares it still holds cost 101 but are
+		// marked at the baseline's best ask of 100 before the attack, a loss of 2.
+		assert_eq!(report.sandwiches, 1);
+		assert_eq!(report.adversarial_surplus, 8.0);
+		assert_eq!(report.victim_loss(), 29.0);
+		assert_eq!(report.extracted_value, 27.0);
+		assert!(report.extracted_value <= report.victim_loss());
+		assert_eq!(report.to_json()["sandwiches"], 1);
+	}
+}
@@ -0,0 +1,49 @@
+use crate::utility::fnv1a_hash;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+/// The single seed a simulation experiment is run from. Every random component draws
+/// from its own substream, derived from the seed and the component's name (e.g. an
+/// agent's trader_id), so the same seed and configuration reproduce the same order
+/// stream and trades, and adding a component doesn't shift the draws of the others.
+/// seed: u64 -> the experiment's seed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExperimentSeed {
+	pub seed: u64,
+}
+
+impl ExperimentSeed {
+	pub fn new(seed: u64) -> ExperimentSeed {
+		ExperimentSeed {
+			seed,
+		}
+	}
+
+	/// The substream of the named component
+	pub fn stream(&self, name: &str) -> StdRng {
+		StdRng::seed_from_u64(self.seed ^ fnv1a_hash(name.as_bytes()))
+	}
+
+	/// The seed of a run derived from this one, e.g. one repetition of an experiment
+	pub fn derive(&self, run: u64) -> ExperimentSeed {
+		ExperimentSeed::new(fnv1a_hash(&[self.seed.to_le_bytes(), run.to_le_bytes()].concat()))
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::Rng;
+
+	#[test]
+	fn test_substreams() {
+		let seed = ExperimentSeed::new(7);
+		let draw = |mut rng: StdRng| -> Vec<u32> { (0..4).map(|_| rng.gen()).collect() };
+		assert_eq!(draw(seed.stream("agent_1")), draw(seed.stream("agent_1")));
+		assert_ne!(draw(seed.stream("agent_1")), draw(seed.stream("agent_2")));
+		assert_ne!(draw(seed.stream("agent_1")), draw(ExperimentSeed::new(8).stream("agent_1")));
+		assert_ne!(seed.derive(0), seed.derive(1));
+	}
+}
@@ -0,0 +1,263 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::exchange::trade::Trade;
+use crate::simulation::harness::Harness;
+use crate::simulation::seed::ExperimentSeed;
+
+use rand::Rng;
+use std::time::Duration;
+
+/// What an agent observes of the market.
+/// Shout: an order that rested in the Book without trading, a rejected shout
+/// Trade: a fill, the aggressor's shout being accepted
+/// Quote: the best bid and ask once the market has processed the latest arrivals
+/// FairValue: public news of the asset's fair value, seen by every agent at once
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketEvent {
+	Shout { trade_type: TradeType, price: f64 },
+	Trade(Trade),
+	Quote { bid: Option<f64>, ask: Option<f64> },
+	FairValue(f64),
+}
+
+/// A trading agent driven by the simulation. When chosen to act it returns the orders to
+/// send, and every market event is passed to it so it can learn from the order flow.
+pub trait Strategy {
+	/// Orders the agent sends when chosen to act at the virtual time now
+	fn act(&mut self, now: Duration) -> Vec<Order>;
+
+	/// Reacts to an event from the exchange. Events about every trader are passed to
+	/// every agent.
+	fn on_event(&mut self, _event: &MarketEvent) {}
+
+	/// Orders the agent sends in reaction to the latest events, e.g. requoting after the
+	/// book moved. Every agent reacts after each round of events, not only the chosen one.
+	fn react(&mut self, _now: Duration) -> Vec<Order> {
+		Vec::new()
+	}
+
+	/// Whether the agent still has anything to trade. Only active agents are chosen to act.
+	fn active(&self) -> bool {
+		true
+	}
+
+	/// The agent's delay between deciding on an order and the order reaching the exchange
+	fn latency(&self) -> Duration {
+		Duration::from_millis(0)
+	}
+}
+
+impl<S: Strategy + ?Sized> Strategy for Box<S> {
+	fn act(&mut self, now: Duration) -> Vec<Order> {
+		(**self).act(now)
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		(**self).on_event(event)
+	}
+
+	fn react(&mut self, now: Duration) -> Vec<Order> {
+		(**self).react(now)
+	}
+
+	fn active(&self) -> bool {
+		(**self).active()
+	}
+
+	fn latency(&self) -> Duration {
+		(**self).latency()
+	}
+}
+
+impl<S: Strategy + ?Sized> Strategy for &mut S {
+	fn act(&mut self, now: Duration) -> Vec<Order> {
+		(**self).act(now)
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		(**self).on_event(event)
+	}
+
+	fn react(&mut self, now: Duration) -> Vec<Order> {
+		(**self).react(now)
+	}
+
+	fn active(&self) -> bool {
+		(**self).active()
+	}
+
+	fn latency(&self) -> Duration {
+		(**self).latency()
+	}
+}
+
+/// Runs Strategies against an in-process exchange
+pub struct StrategyRunner {}
+
+impl StrategyRunner {
+	/// Every interval milliseconds of virtual time until until, a randomly chosen active
+	/// agent acts and its orders are sent to arrive after its latency. The resulting trades,
+	/// the shouts that arrived and rested without trading and the new quote are then passed
+	/// to every agent, and every agent's reaction is sent the same way. Mixed populations
+	/// can be run as Box<dyn Strategy>, or as &mut dyn Strategy to inspect the agents after
+	/// the run. Stops early once no agent is active. Returns the number of orders sent.
+	pub fn in_process<S: Strategy>(harness: &mut Harness, agents: &mut [S], interval: u64, until: u64, seed: ExperimentSeed) -> usize {
+		StrategyRunner::run(harness, agents, interval, until, seed, |_| Vec::new())
+	}
+
+	/// in_process with public news: at every interval the events news returns for the
+	/// current time are passed to every agent ahead of the market's events
+	pub fn run<S, F>(harness: &mut Harness, agents: &mut [S], interval: u64, until: u64, seed: ExperimentSeed, mut news: F) -> usize
+		where S: Strategy, F: FnMut(Duration) -> Vec<MarketEvent>
+	{
+		let mut rng = seed.stream("selection");
+		let mut submitted = 0;
+		// Shouts sent that haven't been reported on yet
+		let mut shouts = Vec::<Shout>::new();
+		let mut now = interval;
+		while now <= until {
+			let active: Vec<usize> = (0..agents.len()).filter(|&i| agents[i].active()).collect();
+			if active.is_empty() {
+				break;
+			}
+			let time = Duration::from_millis(now);
+			let mut events = news(time);
+			let chosen = active[rng.gen_range(0, active.len())];
+			let orders = agents[chosen].act(time);
+			submitted += StrategyRunner::send(harness, orders, agents[chosen].latency(), &mut shouts);
+
+			let trades = harness.advance_to(time);
+			let (arrived, waiting): (Vec<Shout>, Vec<Shout>) = shouts.drain(..).partition(|s| s.arrival <= time);
+			shouts = waiting;
+			for shout in arrived {
+				// Batch auction fills have no aggressor, either side of them was accepted
+				let accepted = trades.iter().any(|t| match (&t.aggressor, &shout.trade_type) {
+					(Some(TradeType::Bid), TradeType::Bid) | (None, TradeType::Bid) => t.bid_id == shout.trader_id,
+					(Some(TradeType::Ask), TradeType::Ask) | (None, TradeType::Ask) => t.ask_id == shout.trader_id,
+					_ => false,
+				});
+				if !accepted {
+					events.push(MarketEvent::Shout { trade_type: shout.trade_type, price: shout.price });
+				}
+			}
+			events.extend(trades.into_iter().map(MarketEvent::Trade));
+			events.push(MarketEvent::Quote { bid: harness.bids.peek_best_price(), ask: harness.asks.peek_best_price() });
+			for event in events.iter() {
+				for agent in agents.iter_mut() {
+					agent.on_event(event);
+				}
+			}
+			for agent in agents.iter_mut() {
+				let orders = agent.react(time);
+				submitted += StrategyRunner::send(harness, orders, agent.latency(), &mut shouts);
+			}
+			now += interval;
+		}
+		// The orders still in flight arrive, and their fills are passed on
+		for trade in harness.advance_to(Duration::from_millis(until)) {
+			let event = MarketEvent::Trade(trade);
+			for agent in agents.iter_mut() {
+				agent.on_event(&event);
+			}
+		}
+		submitted
+	}
+
+	// Submits the orders to arrive after latency, remembering the shouts among them
+	fn send(harness: &mut Harness, orders: Vec<Order>, latency: Duration, shouts: &mut Vec<Shout>) -> usize {
+		let sent = orders.len();
+		for order in orders {
+			if order.order_type != OrderType::Cancel {
+				shouts.push(Shout {
+					trader_id: order.trader_id.clone(),
+					trade_type: order.trade_type.clone(),
+					price: order.price,
+					arrival: harness.now() + latency,
+				});
+			}
+			harness.submit_after(order, latency);
+		}
+		sent
+	}
+}
+
+// A shout sent by an agent, reported as rejected if it rests once it arrives
+struct Shout {
+	trader_id: String,
+	trade_type: TradeType,
+	price: f64,
+	arrival: Duration,
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Shouts a single order at a fixed price and records what it observes
+	struct Fixed {
+		order: Option<Order>,
+		events: Vec<MarketEvent>,
+	}
+
+	impl Strategy for Fixed {
+		fn act(&mut self, _now: Duration) -> Vec<Order> {
+			self.order.take().into_iter().collect()
+		}
+
+		fn on_event(&mut self, event: &MarketEvent) {
+			self.events.push(event.clone());
+		}
+
+		fn active(&self) -> bool {
+			self.order.is_some()
+		}
+	}
+
+	#[test]
+	fn test_runner_events() {
+		let fixed = |id: &str, trade_type: TradeType, price: f64| Fixed {
+			order: Some(Order::new(String::from(id), OrderType::Enter, trade_type, price, 1.0)),
+			events: Vec::new(),
+		};
+		let mut agents = vec![fixed("buyer", TradeType::Bid, 100.0), fixed("seller", TradeType::Ask, 99.0)];
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		assert_eq!(StrategyRunner::in_process(&mut harness, &mut agents, 1, 10, ExperimentSeed::new(1)), 2);
+
+		// The first shout rests, the second is accepted and both agents see the same events
+		let events = &agents[0].events;
+		assert_eq!(events, &agents[1].events);
+		assert_eq!(events.len(), 4);
+		assert!(matches!(events[0], MarketEvent::Shout { .. }));
+		assert!(matches!(&events[2], MarketEvent::Trade(t) if t.price == events_price(&events[0])));
+		assert_eq!(events[3], MarketEvent::Quote { bid: None, ask: None });
+	}
+
+	#[test]
+	fn test_mixed_population() {
+		use crate::simulation::random_behavior::RandBehavior;
+		use crate::simulation::trader::Traders;
+		use crate::simulation::zic_behavior::SupplyDemand;
+		use std::sync::Arc;
+
+		let seed = ExperimentSeed::new(2);
+		let traders = Arc::new(Traders::new());
+		let market = SupplyDemand::linear(5, (90.0, 110.0), (90.0, 110.0));
+		let mut agents: Vec<Box<dyn Strategy>> = vec![Box::new(RandBehavior::new(Arc::clone(&traders), Default::default(), seed))];
+		agents.extend(market.zic_agents((1.0, 200.0), seed).into_iter().map(|a| Box::new(a) as Box<dyn Strategy>));
+
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		let submitted = StrategyRunner::in_process(&mut harness, &mut agents, 1, 200, seed);
+		assert!(submitted > 0);
+		assert!(traders.num_traders() > 0);
+		assert!(!harness.trade_log.is_empty());
+	}
+
+	fn events_price(event: &MarketEvent) -> f64 {
+		match event {
+			MarketEvent::Shout { price, .. } => *price,
+			_ => panic!("not a shout"),
+		}
+	}
+}
@@ -1,32 +1,39 @@
 use crate::order::Order;
 
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::Mutex;
 
 /// A struct for keeping track of the orders sent to the exchange. The
-/// struct is a threadsafe HashMap that stores the raw order that each trader
+/// struct is a threadsafe map that stores the raw order that each trader
 /// has generated, indexed by their unique trader_id. This is used externally
-/// to simulate traders communicating with the exchange.
+/// to simulate traders communicating with the exchange. The map is ordered so
+/// seeded runs iterate the traders in the same order every time.
 pub struct Traders {
-	pub traders: Mutex<HashMap<String, Order>>,
+	pub traders: Mutex<BTreeMap<String, Order>>,
+}
+
+impl Default for Traders {
+	fn default() -> Self {
+		Traders::new()
+	}
 }
 
 impl Traders {
 	pub fn new() -> Self {
 		Traders {
-			traders: Mutex::new(HashMap::new()),
+			traders: Mutex::new(BTreeMap::new()),
 		}
 	}
 
-	/// Add a new order to the Traders HashMap
+	/// Add a new order to the Traders map
 	pub fn new_trader(&mut self, order: Order) {
 		let mut traders = self.traders.lock().unwrap();
 		// or_insert will not overwrite an existing entry, but will insert if the key doesn't exist
 		traders.entry(order.trader_id.clone()).or_insert(order);
 	}
 
-	/// Add a vector of new orders to the Traders HashMap. This is preferable to new_trader
+	/// Add a vector of new orders to the Traders map. This is preferable to new_trader
 	/// as the mutex lock only has to be acquired once.
 	pub fn new_traders(&self, orders: Vec<Order>) {
 		let mut traders = self.traders.lock().unwrap();
@@ -35,12 +42,12 @@ impl Traders {
 		}
 	}
 
-	/// Updates a trader's order in the HashMap with the supplied 'order'
+	/// Updates a trader's order in the map with the supplied 'order'
 	pub fn update_trader(&mut self, order: Order) {
 		self.traders.lock().unwrap().insert(order.trader_id.clone(), order);
 	}
 
-	/// Removes the trader and their order from the HashMap
+	/// Removes the trader and their order from the map
 	pub fn del_trader(&mut self, trader_id: String) {
 		self.traders.lock().unwrap().remove(&trader_id);
 	}
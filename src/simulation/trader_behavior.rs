@@ -1,44 +1,69 @@
 use crate::simulation::trader::Traders;
 use crate::order::{Order, OrderType, TradeType};
 
+use std::collections::BTreeMap;
 use std::iter;
 use std::sync::Arc;
-use rand::{Rng, thread_rng};
+use rand::Rng;
 use rand::distributions::Alphanumeric;
 
 
+/// The ranges random orders are drawn from.
+/// price: (f64, f64) -> lowest and highest limit price
+/// quantity: (f64, f64) -> smallest and largest quantity
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderRange {
+	pub price: (f64, f64),
+	pub quantity: (f64, f64),
+}
+
+impl Default for OrderRange {
+	fn default() -> Self {
+		OrderRange {
+			price: (90.0, 110.0),
+			quantity: (0.0, 10.0),
+		}
+	}
+}
+
+impl OrderRange {
+	/// Whether both ranges have room to draw from, the low end below the high end
+	pub fn is_valid(&self) -> bool {
+		self.price.0 < self.price.1 && self.quantity.0 < self.quantity.1
+	}
+}
+
 /// Function for parsing an order into it's Json components. 
 pub fn params_for_json(order: &Order) -> (String, OrderType, TradeType, f64, f64) {
-    return (order.trader_id.clone(),
+    (order.trader_id.clone(),
         order.order_type.clone(),
         order.trade_type.clone(),
-        order.price.clone(),
-        order.quantity.clone());
+        order.price,
+        order.quantity)
 }
 
 /// A function to randomly generate update orders for existing traders within 
-/// the Trader HashMap. The output is a vector of tuples where each tuple contains
+/// the Traders map. The output is a vector of tuples where each tuple contains
 /// the required parameters to generate a JSON formatted order. The supplied u32
 /// 'upper' is to change the probability with which an update will occur for a 
 /// given trader. Probability of update = (1 / upper), where upper > 0
-pub fn gen_rand_updates(t_struct: Arc<Traders>, upper: u32) 
+pub fn gen_rand_updates<R: Rng + ?Sized>(t_struct: Arc<Traders>, upper: u32, range: &OrderRange, rng: &mut R) 
 -> Vec<(String, OrderType, TradeType, f64, f64)> 
 {
-		let mut rng = thread_rng();
-		// Get a lock on the HashMap 
+		// Get a lock on the map 
 		let mut orders = t_struct.traders.lock().unwrap();
 
 		// Vector of tuples to construct JSON messages
 		let mut to_send: Vec<_> = Vec::new();
 
-		// Iterate through hashmap and update based on rng
+		// Iterate through the map and update based on rng
 		for order in orders.values_mut() {
 			// (1 / upper) chance of updating the given order
 			if rng.gen_range(0, upper) == 1 {
 				// generate a new order with same trader_id and trader_type
-				let new_order = rand_update_order(order);
+				let new_order = rand_update_order(order, range, rng);
 				// parse and save the new order for params to make JSON
-				to_send.push(params_for_json(order));
+				to_send.push(params_for_json(&new_order));
 				// save the new order in the hashmap
 				*order = new_order;
 			}
@@ -47,15 +72,14 @@ pub fn gen_rand_updates(t_struct: Arc<Traders>, upper: u32)
 	}
 
 /// A function to randomly generate cancel orders for existing traders within 
-/// the Trader HashMap. The output is a vector of tuples where each tuple contains
+/// the Traders map. The output is a vector of tuples where each tuple contains
 /// the required parameters to generate a JSON formatted order. The supplied u32
 /// 'upper' is to change the probability with which an update will occur for a 
 /// given trader. Probability of update = (1 / upper), where upper > 0
-pub fn gen_rand_cancels(t_struct: Arc<Traders>, upper: u32) 
+pub fn gen_rand_cancels<R: Rng + ?Sized>(t_struct: Arc<Traders>, upper: u32, rng: &mut R) 
 -> Vec<(String, OrderType, TradeType, f64, f64)> 
 {
-		let mut rng = thread_rng();
-		// Get a lock on the HashMap 
+		// Get a lock on the map 
 		let mut orders = t_struct.traders.lock().unwrap();
 
 		// Vector of tuples to construct JSON messages
@@ -63,7 +87,7 @@ pub fn gen_rand_cancels(t_struct: Arc<Traders>, upper: u32)
 
 		let length_before = orders.len();
 
-		// Iterate through hashmap and filter out orders based on rng
+		// Iterate through the map and filter out orders based on rng
 		orders.retain(|_, order| {
 			let rand = rng.gen_range(0, upper);
 			// order was randomly selected to be cancelled
@@ -76,7 +100,7 @@ pub fn gen_rand_cancels(t_struct: Arc<Traders>, upper: u32)
 			}
 
 			// (1 / upper) chance of cancelling the given order
-			!(rand == 1)
+			rand != 1
 		});
 
 		assert_eq!(length_before, orders.len() + to_send.len());
@@ -85,25 +109,63 @@ pub fn gen_rand_cancels(t_struct: Arc<Traders>, upper: u32)
 
 /// Generates a random number of Bid and Ask orders all of OrderType::Enter
 /// and returns them in a vector.
-pub fn rand_enters(upper: u64) -> Vec<Order> {
-	let mut rng = thread_rng();
+pub fn rand_enters<R: Rng + ?Sized>(upper: u64, range: &OrderRange, rng: &mut R) -> Vec<Order> {
 	let mut orders = Vec::<Order>::new();
 
 	for _ in 0..rng.gen_range(0, upper) {
-		orders.push(rand_bid_enter());
+		orders.push(rand_bid_enter(range, rng));
 	}
 
 	for _ in 0..rng.gen_range(0, upper) {
-		orders.push(rand_ask_enter());
+		orders.push(rand_ask_enter(range, rng));
 	}
 	orders
 }
 
+/// Generates a single random Bid or Ask order of OrderType::Enter, each side equally likely
+pub fn rand_enter<R: Rng + ?Sized>(range: &OrderRange, rng: &mut R) -> Order {
+	if rng.gen::<bool>() {
+		rand_bid_enter(range, rng)
+	} else {
+		rand_ask_enter(range, rng)
+	}
+}
+
+/// Picks a random trader from the Traders map and randomizes its order as an update,
+/// returning the params to generate a JSON formatted order. Returns None if there are
+/// no traders. Trader ids are random so the trader following a random id is close to
+/// uniformly chosen, without walking the map.
+pub fn gen_rand_update<R: Rng + ?Sized>(t_struct: &Traders, range: &OrderRange, rng: &mut R) -> Option<(String, OrderType, TradeType, f64, f64)> {
+	let mut orders = t_struct.traders.lock().unwrap();
+	let id = pick_trader(&orders, rng)?;
+	let order = orders.get_mut(&id).expect("picked trader");
+	let new_order = rand_update_order(order, range, rng);
+	let params = params_for_json(&new_order);
+	*order = new_order;
+	Some(params)
+}
+
+/// Picks a random trader from the Traders map and removes it, returning the params for
+/// its cancel order. Returns None if there are no traders.
+pub fn gen_rand_cancel<R: Rng + ?Sized>(t_struct: &Traders, rng: &mut R) -> Option<(String, OrderType, TradeType, f64, f64)> {
+	let mut orders = t_struct.traders.lock().unwrap();
+	let id = pick_trader(&orders, rng)?;
+	let mut params = params_for_json(&orders.remove(&id).expect("picked trader"));
+	params.1 = OrderType::Cancel;
+	Some(params)
+}
+
+// The first trader at or after a random id, wrapping around to the first trader
+fn pick_trader<R: Rng + ?Sized>(orders: &BTreeMap<String, Order>, rng: &mut R) -> Option<String> {
+	let id = gen_order_id(rng);
+	orders.range(id..).next().or_else(|| orders.iter().next()).map(|(id, _)| id.clone())
+}
+
 /// Generates a random Ask order of OrderType::Enter
-pub fn rand_ask_enter() -> Order {
-	let (price, quantity) = gen_limit_order();			//TODOOO LOOK AT THIS AGAIN
+pub fn rand_ask_enter<R: Rng + ?Sized>(range: &OrderRange, rng: &mut R) -> Order {
+	let (price, quantity) = gen_limit_order(range, rng);
 	Order::new(
-		gen_order_id(),
+		gen_order_id(rng),
 		OrderType::Enter,
 		TradeType::Ask,
 		price,
@@ -112,10 +174,10 @@ pub fn rand_ask_enter() -> Order {
 }
 
 /// Generates a random Bid order of OrderType::Enter
-pub fn rand_bid_enter() -> Order {
-	let (price, quantity) = gen_limit_order();				//TODOOO LOOK AT THIS AGAIN
+pub fn rand_bid_enter<R: Rng + ?Sized>(range: &OrderRange, rng: &mut R) -> Order {
+	let (price, quantity) = gen_limit_order(range, rng);
 	Order::new(
-		gen_order_id(),
+		gen_order_id(rng),
 		OrderType::Enter,
 		TradeType::Bid,
 		price,
@@ -124,28 +186,26 @@ pub fn rand_bid_enter() -> Order {
 }
 
 /// Randomizes the fields of an order but retains trade_id and trade_type
-pub fn rand_update_order(old: &Order) -> Order {
+pub fn rand_update_order<R: Rng + ?Sized>(old: &Order, range: &OrderRange, rng: &mut R) -> Order {
 	
     let mut new = match old.trade_type {
-    	TradeType::Bid => rand_bid_enter(),
-    	TradeType::Ask => rand_ask_enter(),
+    	TradeType::Bid => rand_bid_enter(range, rng),
+    	TradeType::Ask => rand_ask_enter(range, rng),
     };
     new.order_type = OrderType::Update;
     new.trader_id = old.trader_id.clone();
     new
 }
 
-/// Create a random price and quantity
-pub fn gen_limit_order() -> (f64, f64) {
-	let mut rng = thread_rng();
-	let p: f64 = rng.gen_range(90.0, 110.0);
-	let q: f64 = rng.gen_range(0.0, 10.0);
+/// Create a random price and quantity within range
+pub fn gen_limit_order<R: Rng + ?Sized>(range: &OrderRange, rng: &mut R) -> (f64, f64) {
+	let p: f64 = rng.gen_range(range.price.0, range.price.1);
+	let q: f64 = rng.gen_range(range.quantity.0, range.quantity.1);
 	(p, q)
 }
 
 /// Generate a random trader id from random ascii chars
-pub fn gen_order_id() -> String {
-	let mut rng = thread_rng();
+pub fn gen_order_id<R: Rng + ?Sized>(rng: &mut R) -> String {
 	let id: String = iter::repeat(())
         .map(|()| rng.sample(Alphanumeric))
         .take(10)
@@ -156,6 +216,8 @@ pub fn gen_order_id() -> String {
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::simulation::seed::ExperimentSeed;
+
 	#[test]
 	fn test_new_traders() {
 		let t_struct = Traders::new();
@@ -165,10 +227,45 @@ mod tests {
 
 	#[test]
 	fn test_insert_traders() {
+		let mut rng = ExperimentSeed::new(1).stream("traders");
 		let mut t_struct = Traders::new();
-		t_struct.new_trader(rand_bid_enter());
-		t_struct.new_trader(rand_ask_enter());
+		t_struct.new_trader(rand_bid_enter(&OrderRange::default(), &mut rng));
+		t_struct.new_trader(rand_ask_enter(&OrderRange::default(), &mut rng));
 
 		assert_eq!(t_struct.traders.lock().unwrap().len(), 2);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_seeded_order_stream() {
+		let stream = |seed: u64| {
+			let mut rng = ExperimentSeed::new(seed).stream("arrivals");
+			let range = OrderRange::default();
+			let traders = Arc::new(Traders::new());
+			traders.new_traders(rand_enters(10, &range, &mut rng));
+			let mut params: Vec<_> = traders.traders.lock().unwrap().values().map(params_for_json).collect();
+			params.extend(gen_rand_updates(Arc::clone(&traders), 2, &range, &mut rng));
+			params.extend(gen_rand_cancels(traders, 2, &mut rng));
+			params
+		};
+		assert_eq!(stream(42), stream(42));
+		assert_ne!(stream(42), stream(43));
+	}
+
+	#[test]
+	fn test_single_updates_and_cancels() {
+		let mut rng = ExperimentSeed::new(1).stream("arrivals");
+		let range = OrderRange { price: (50.0, 60.0), quantity: (1.0, 2.0) };
+		let traders = Traders::new();
+		assert!(gen_rand_update(&traders, &range, &mut rng).is_none());
+		traders.new_traders((0..20).map(|_| rand_enter(&range, &mut rng)).collect());
+		assert!(traders.traders.lock().unwrap().values().all(|o| o.price >= 50.0 && o.price < 60.0 && o.quantity >= 1.0));
+
+		let (t_id, order_type, ..) = gen_rand_update(&traders, &range, &mut rng).unwrap();
+		assert_eq!(order_type, OrderType::Update);
+		assert_eq!(traders.traders.lock().unwrap()[&t_id].order_type, OrderType::Update);
+		let (t_id, order_type, ..) = gen_rand_cancel(&traders, &mut rng).unwrap();
+		assert_eq!(order_type, OrderType::Cancel);
+		assert!(!traders.traders.lock().unwrap().contains_key(&t_id));
+		assert_eq!(traders.num_traders(), 19);
+	}
+}
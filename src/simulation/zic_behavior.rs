@@ -0,0 +1,242 @@
+use crate::order::{Order, OrderType, TradeType};
+use crate::exchange::trade::Trade;
+use crate::simulation::seed::ExperimentSeed;
+use crate::simulation::strategy::{MarketEvent, Strategy};
+
+use rand::Rng;
+use rand::rngs::StdRng;
+use std::time::Duration;
+
+/// The private values of the buyers and costs of the sellers in a market, one unit each.
+/// Sorted into descending values and ascending costs they are the market's demand and
+/// supply schedules.
+/// values: Vec<f64> -> the most each buyer will pay for its unit
+/// costs: Vec<f64> -> the least each seller will accept for its unit
+#[derive(Debug, Clone, PartialEq)]
+pub struct SupplyDemand {
+	pub values: Vec<f64>,
+	pub costs: Vec<f64>,
+}
+
+/// The theoretical competitive equilibrium of a SupplyDemand.
+/// quantity: usize -> units traded where demand meets supply
+/// price_low, price_high: f64 -> range of prices that clear quantity units
+/// surplus: f64 -> values less costs of the intra-marginal units, the most any market can extract
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompetitiveEquilibrium {
+	pub quantity: usize,
+	pub price_low: f64,
+	pub price_high: f64,
+	pub surplus: f64,
+}
+
+impl CompetitiveEquilibrium {
+	/// The middle of the equilibrium price range
+	pub fn price(&self) -> f64 {
+		(self.price_low + self.price_high) / 2.0
+	}
+}
+
+impl SupplyDemand {
+	pub fn new(values: Vec<f64>, costs: Vec<f64>) -> SupplyDemand {
+		SupplyDemand {
+			values,
+			costs,
+		}
+	}
+
+	/// Evenly spaced schedules of n buyers with values from the first range and n sellers
+	/// with costs from the second, inclusive
+	pub fn linear(n: usize, values: (f64, f64), costs: (f64, f64)) -> SupplyDemand {
+		let steps = |(low, high): (f64, f64)| -> Vec<f64> {
+			(0..n).map(|i| if n > 1 { low + (high - low) * i as f64 / (n - 1) as f64 } else { low }).collect()
+		};
+		SupplyDemand::new(steps(values), steps(costs))
+	}
+
+	/// Schedules of n buyers and n sellers whose values and costs are drawn uniformly from
+	/// the ranges
+	pub fn uniform<R: Rng + ?Sized>(n: usize, values: (f64, f64), costs: (f64, f64), rng: &mut R) -> SupplyDemand {
+		let draws: Vec<f64> = (0..n).map(|_| rng.gen_range(values.0, values.1)).collect();
+		SupplyDemand::new(draws, (0..n).map(|_| rng.gen_range(costs.0, costs.1)).collect())
+	}
+
+	/// Finds the competitive equilibrium by walking down the demand schedule and up the
+	/// supply schedule while the next buyer values a unit at least as much as it costs the
+	/// next seller. Returns None if no unit can trade.
+	pub fn equilibrium(&self) -> Option<CompetitiveEquilibrium> {
+		let mut demand = self.values.clone();
+		let mut supply = self.costs.clone();
+		demand.sort_by(|a, b| b.partial_cmp(a).unwrap());
+		supply.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+		let quantity = demand.iter().zip(supply.iter()).take_while(|(v, c)| v >= c).count();
+		if quantity == 0 {
+			return None;
+		}
+		// The marginal units bound the price, along with the first extra-marginal units which
+		// mustn't want to trade at it
+		let mut price_low = supply[quantity - 1];
+		let mut price_high = demand[quantity - 1];
+		if let Some(v) = demand.get(quantity) {
+			price_low = price_low.max(*v);
+		}
+		if let Some(c) = supply.get(quantity) {
+			price_high = price_high.min(*c);
+		}
+		let surplus = demand[..quantity].iter().sum::<f64>() - supply[..quantity].iter().sum::<f64>();
+		Some(CompetitiveEquilibrium { quantity, price_low, price_high, surplus })
+	}
+
+	/// An agent for every buyer and seller, named buyer_{i} and seller_{i}, made from its
+	/// trader_id, side and value or cost
+	pub fn agents<S, F>(&self, mut make: F) -> Vec<S>
+		where F: FnMut(String, TradeType, f64) -> S
+	{
+		let buyers = self.values.iter().enumerate().map(|(i, v)| (format!("buyer_{}", i), TradeType::Bid, *v));
+		let sellers = self.costs.iter().enumerate().map(|(i, c)| (format!("seller_{}", i), TradeType::Ask, *c));
+		buyers.chain(sellers).map(|(id, trade_type, limit)| make(id, trade_type, limit)).collect()
+	}
+
+	/// A ZI-C agent for every buyer and seller, each drawing its shouts from its own
+	/// substream of the seed
+	pub fn zic_agents(&self, bounds: (f64, f64), seed: ExperimentSeed) -> Vec<ZicAgent> {
+		self.agents(|id, trade_type, limit| {
+			let rng = seed.stream(&id);
+			ZicAgent::new(id, trade_type, limit, bounds, rng)
+		})
+	}
+}
+
+/// A Gode-Sunder zero-intelligence constrained trader with one unit to trade. Each shout
+/// is drawn uniformly between its limit and the market's price bound on the side it can't
+/// lose money on: a buyer bids between min_price and its value, a seller asks between its
+/// cost and max_price. The first shout enters the order and later shouts replace it until
+/// the unit trades.
+/// trader_id: String -> trader_id the agent's orders are sent under
+/// trade_type: TradeType -> Bid for a buyer, Ask for a seller
+/// limit: f64 -> the buyer's private value or the seller's cost
+/// min_price, max_price: f64 -> the range shouts are drawn from
+/// traded: bool -> whether the agent's unit has traded
+pub struct ZicAgent {
+	pub trader_id: String,
+	pub trade_type: TradeType,
+	pub limit: f64,
+	pub min_price: f64,
+	pub max_price: f64,
+	pub traded: bool,
+	resting: bool,
+	rng: StdRng,
+}
+
+impl ZicAgent {
+	pub fn new(trader_id: String, trade_type: TradeType, limit: f64, bounds: (f64, f64), rng: StdRng) -> ZicAgent {
+		ZicAgent {
+			trader_id,
+			trade_type,
+			limit,
+			min_price: bounds.0,
+			max_price: bounds.1,
+			traded: false,
+			resting: false,
+			rng,
+		}
+	}
+
+	/// Draws the agent's next order, None once its unit has traded
+	pub fn shout(&mut self) -> Option<Order> {
+		if self.traded {
+			return None;
+		}
+		let (low, high) = match self.trade_type {
+			TradeType::Bid => (self.min_price, self.limit),
+			TradeType::Ask => (self.limit, self.max_price),
+		};
+		let price = if low < high { self.rng.gen_range(low, high) } else { self.limit };
+		let order_type = if self.resting { OrderType::Update } else { OrderType::Enter };
+		self.resting = true;
+		Some(Order::new(self.trader_id.clone(), order_type, self.trade_type.clone(), price, 1.0))
+	}
+
+	/// Marks the unit traded if the agent is on either side of the trade
+	pub fn on_trade(&mut self, trade: &Trade) {
+		if trade.bid_id == self.trader_id || trade.ask_id == self.trader_id {
+			self.traded = true;
+			self.resting = false;
+		}
+	}
+
+	/// The agent's profit from a trade, value less price for a buyer and price less cost
+	/// for a seller
+	pub fn profit(&self, trade: &Trade) -> f64 {
+		match self.trade_type {
+			TradeType::Bid => (self.limit - trade.price) * trade.quantity,
+			TradeType::Ask => (trade.price - self.limit) * trade.quantity,
+		}
+	}
+}
+
+impl Strategy for ZicAgent {
+	fn act(&mut self, _now: Duration) -> Vec<Order> {
+		self.shout().into_iter().collect()
+	}
+
+	fn on_event(&mut self, event: &MarketEvent) {
+		if let MarketEvent::Trade(trade) = event {
+			self.on_trade(trade);
+		}
+	}
+
+	fn active(&self) -> bool {
+		!self.traded
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::simulation::harness::Harness;
+	use crate::simulation::strategy::StrategyRunner;
+
+	#[test]
+	fn test_competitive_equilibrium() {
+		let market = SupplyDemand::new(vec![100.0, 80.0, 60.0, 40.0], vec![30.0, 50.0, 70.0, 90.0]);
+		let eq = market.equilibrium().unwrap();
+		// Two units trade, between the second seller's cost and the third buyer's value on
+		// one side and the second buyer's value and the third seller's cost on the other
+		assert_eq!((eq.quantity, eq.price_low, eq.price_high), (2, 60.0, 70.0));
+		assert_eq!(eq.price(), 65.0);
+		assert_eq!(eq.surplus, 100.0);
+
+		assert_eq!(SupplyDemand::new(vec![10.0], vec![20.0]).equilibrium(), None);
+		let linear = SupplyDemand::linear(5, (50.0, 150.0), (50.0, 150.0));
+		assert_eq!(linear.values, vec![50.0, 75.0, 100.0, 125.0, 150.0]);
+		assert_eq!(linear.equilibrium().unwrap().quantity, 3);
+	}
+
+	#[test]
+	fn test_zic_never_trades_at_a_loss() {
+		let seed = ExperimentSeed::new(5);
+		let market = SupplyDemand::uniform(20, (50.0, 150.0), (50.0, 150.0), &mut seed.stream("schedules"));
+		let mut agents = market.zic_agents((1.0, 200.0), seed);
+		let mut harness = Harness::new();
+		harness.set_logging(false);
+		StrategyRunner::in_process(&mut harness, &mut agents, 1, 2_000, seed);
+
+		let trades = harness.trade_log.since(0);
+		assert!(!trades.is_empty());
+		let eq = market.equilibrium().unwrap();
+		assert!(trades.len() <= 20);
+		for trade in trades.iter() {
+			for agent in agents.iter().filter(|a| a.trader_id == trade.bid_id || a.trader_id == trade.ask_id) {
+				assert!(agent.traded);
+				assert!(agent.profit(trade) >= 0.0);
+			}
+		}
+		let extracted: f64 = trades.iter().map(|t| {
+			agents.iter().filter(|a| a.trader_id == t.bid_id || a.trader_id == t.ask_id).map(|a| a.profit(t)).sum::<f64>()
+		}).sum();
+		assert!(extracted <= eq.surplus + 0.000_001);
+	}
+}
@@ -0,0 +1,31 @@
+use crate::order::{Order, OrderType, TradeType, ExecType, TimeInForce};
+
+/// An Enter limit order that is good till cancel, the starting point of most unit tests.
+/// Use the OrderSetup methods to change the rest of its fields.
+pub fn order(id: &str, trade_type: TradeType, price: f64, quantity: f64) -> Order {
+	Order::new(String::from(id), OrderType::Enter, trade_type, price, quantity)
+}
+
+/// Chained setters for the fields unit tests vary on top of order()
+pub trait OrderSetup {
+	fn with_exec_type(self, exec_type: ExecType) -> Order;
+	fn with_time_in_force(self, time_in_force: TimeInForce) -> Order;
+	fn with_gas_price(self, gas_price: f64) -> Order;
+}
+
+impl OrderSetup for Order {
+	fn with_exec_type(mut self, exec_type: ExecType) -> Order {
+		self.exec_type = exec_type;
+		self
+	}
+
+	fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Order {
+		self.time_in_force = time_in_force;
+		self
+	}
+
+	fn with_gas_price(mut self, gas_price: f64) -> Order {
+		self.gas_price = gas_price;
+		self
+	}
+}
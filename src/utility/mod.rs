@@ -1,8 +1,42 @@
+use std::cell::Cell;
 use std::time::{Duration, SystemTime};
 
+thread_local! {
+    // The simulated time of an in-process run on this thread, None on the wall clock
+    static VIRTUAL_TIME: Cell<Option<Duration>> = const { Cell::new(None) };
+    // Whether log_event! prints on this thread
+    static LOGGING: Cell<bool> = const { Cell::new(true) };
+}
 
-
+/// The time since UNIX_EPOCH, or the virtual time if an in-process simulation on
+/// this thread has set one
 pub fn get_time() -> Duration {
+    if let Some(time) = VIRTUAL_TIME.with(|t| t.get()) {
+        return time;
+    }
     SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
                          .expect("SystemTime::duration_since failed")
-}
\ No newline at end of file
+}
+
+/// Makes get_time return time on the calling thread, or the wall clock again for None
+pub fn set_virtual_time(time: Option<Duration>) {
+    VIRTUAL_TIME.with(|t| t.set(time));
+}
+
+/// Whether the exchange's log_event! messages are printed on the calling thread
+pub fn logging() -> bool {
+    LOGGING.with(|l| l.get())
+}
+
+/// Turns the exchange's log_event! messages on or off for the calling thread
+pub fn set_logging(on: bool) {
+    LOGGING.with(|l| l.set(on));
+}
+
+/// A small local hash (64-bit FNV-1a) that is stable across runs and platforms. It
+/// isn't collision resistant, so it's only for deriving seeds, not for commitments.
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, b| (hash ^ u64::from(*b)).wrapping_mul(PRIME))
+}
@@ -74,6 +74,7 @@ pub fn setup_full_queue() -> Arc<Queue> {
 	queue
 }
 
+#[allow(dead_code)]
 pub fn rand_coef_vector() -> Vec<f64> {
 	// Create a variable length vector filled with random f64's
 	let mut rng = rand::thread_rng();
@@ -84,18 +85,20 @@ pub fn rand_coef_vector() -> Vec<f64> {
 	coefs
 }
 
+#[allow(dead_code)]
 pub fn n_bid_enters(n: u32) -> Vec<Order> {
 	let mut bids = Vec::<Order>::new();
 	for _ in 0..n {
-		bids.push(rand_bid_enter());
+		bids.push(rand_bid_enter(&OrderRange::default(), &mut rand::thread_rng()));
 	}
 	bids
 }
 
+#[allow(dead_code)]
 pub fn n_ask_enters(n: u32) -> Vec<Order> {
 	let mut asks = Vec::<Order>::new();
 	for _ in 0..n {
-		asks.push(rand_ask_enter());
+		asks.push(rand_ask_enter(&OrderRange::default(), &mut rand::thread_rng()));
 	}
 	asks
 }
@@ -105,14 +108,14 @@ pub fn setup_orders() -> (Vec<Order>, Vec<Order>) {
 	let mut asks = Vec::<Order>::new();
 	for i in 1..101 {
 		bids.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Bid, 
     		i as f64, 
     		5.0, 
 		));
 		asks.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Ask, 
     		i as f64, 
@@ -130,7 +133,7 @@ pub fn setup_ask_cross_orders(num_bids: usize) -> (Vec<Order>, Vec<Order>) {
 	let mut asks = Vec::<Order>::new();
 	for i in 1..num_bids + 1 {
 		bids.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Bid, 
     		i as f64, 
@@ -140,7 +143,7 @@ pub fn setup_ask_cross_orders(num_bids: usize) -> (Vec<Order>, Vec<Order>) {
 
 	// Essentially a market order
 	asks.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Ask, 
     		0.0, 
@@ -149,7 +152,7 @@ pub fn setup_ask_cross_orders(num_bids: usize) -> (Vec<Order>, Vec<Order>) {
 
 	// An order that won't transact
 	asks.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Ask, 
     		num_bids as f64 * 1000.0, 
@@ -165,7 +168,7 @@ pub fn setup_bid_cross_orders(num_asks: usize) -> (Vec<Order>, Vec<Order>) {
 	let mut asks = Vec::<Order>::new();
 	for i in 1..num_asks + 1 {
 		asks.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Ask, 
     		50.0 + i as f64, 
@@ -175,7 +178,7 @@ pub fn setup_bid_cross_orders(num_asks: usize) -> (Vec<Order>, Vec<Order>) {
 
 	// Essentially a market order
 	bids.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Bid, 
     		num_asks as f64 * 1000.0, 
@@ -184,7 +187,7 @@ pub fn setup_bid_cross_orders(num_asks: usize) -> (Vec<Order>, Vec<Order>) {
 
 	// An order that won't transact
 	bids.push(Order::new(
-			gen_order_id(), 
+			gen_order_id(&mut rand::thread_rng()), 
     		OrderType::Enter, 
     		TradeType::Bid, 
     		0.0, 
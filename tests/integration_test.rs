@@ -1,10 +1,11 @@
 // extern crate <name_of_my_crate_to_test>
 use flow_rs::exchange::queue_processing::QueueProcessor;
 use flow_rs::exchange::order_processing::OrderProcessor;
-use flow_rs::exchange::order_processing::*;
 use flow_rs::order::*;
-use flow_rs::exchange::auction::Auction;
-use std::sync::Arc;
+use flow_rs::controller::State;
+use flow_rs::exchange::schedule::TradingSchedule;
+use flow_rs::exchange::trade::TradeLog;
+use std::sync::{Arc, Mutex};
 use rand::{Rng, thread_rng};
 
 // Include the common module for setting up state for tests
@@ -24,12 +25,12 @@ fn test_add_order_to_book() {
 
 	let book = common::setup_bids_book();
 
-	book.add_order(bid);
+	book.add_order(bid).unwrap();
 
 	assert_eq!(book.len(), 1);
 
 	let order = book.orders.lock().unwrap().pop().unwrap();
-
+	assert_eq!(order.trader_id, "bid_id");
 }
 
 
@@ -86,7 +87,7 @@ fn test_ask_transaction() {
 	}
 
 	// Process all of the bid orders in the queue
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 
@@ -110,7 +111,7 @@ fn test_ask_transaction() {
 	}
 
 	// Process the new ask orders
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 	
@@ -157,7 +158,7 @@ fn test_bid_transaction() {
 	}
 
 	// Process all of the bid orders in the queue
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 
@@ -181,7 +182,7 @@ fn test_bid_transaction() {
 	}
 
 	// Process the new ask orders
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 	
@@ -210,8 +211,8 @@ pub fn test_update_bid() {
 	let asks_book = Arc::new(common::setup_asks_book());
 	
 	// Setup bids and asks
-	let (mut bids, asks) = common::setup_orders();
-	bids[0].trader_id = format!("jason");
+	let (mut bids, _asks) = common::setup_orders();
+	bids[0].trader_id = String::from("jason");
 	let mut handles = Vec::new();
 
 	// Send all the orders in parallel 
@@ -237,7 +238,7 @@ pub fn test_update_bid() {
 
 	// Create a new order to update book 
 	let mut update_order = common::setup_bid_order();
-	update_order.trader_id = format!("jason");
+	update_order.trader_id = String::from("jason");
 	update_order.order_type = OrderType::Update;
 	update_order.price = 99.9;
 	update_order.quantity = 555.5;
@@ -257,12 +258,12 @@ pub fn test_update_bid() {
 	assert_eq!(bids_book.len(), 100);
 
 	// Find the order with id "jason"
-	let index = bids_book.peek_id_pos(format!("jason"));
+	let index = bids_book.peek_id_pos(String::from("jason"));
 
 	// Unwrap the index and check order has been updating
 	if let Some(i) = index {
 		let order = &bids_book.orders.lock().unwrap()[i];
-		assert_eq!(order.trader_id, format!("jason"));
+		assert_eq!(order.trader_id, String::from("jason"));
 		assert_eq!(order.price, 99.9);
 		assert_eq!(order.quantity, 555.5);
 		assert_eq!(order.order_type, OrderType::Update);
@@ -279,8 +280,8 @@ pub fn test_update_ask() {
 	let asks_book = Arc::new(common::setup_asks_book());
 	
 	// Setup bids and asks
-	let (bids, mut asks) = common::setup_orders();
-	asks[0].trader_id = format!("jason");
+	let (_bids, mut asks) = common::setup_orders();
+	asks[0].trader_id = String::from("jason");
 	let mut handles = Vec::new();
 
 	// Send all the asks in parallel 
@@ -307,7 +308,7 @@ pub fn test_update_ask() {
 
 	// Create a new order to update book 
 	let mut update_order = common::setup_ask_order();
-	update_order.trader_id = format!("jason");
+	update_order.trader_id = String::from("jason");
 	update_order.order_type = OrderType::Update;
 	update_order.price = 99.9;
 	update_order.quantity = 555.5;
@@ -327,12 +328,12 @@ pub fn test_update_ask() {
 	assert_eq!(asks_book.len(), 100);
 
 	// Find the order with id "jason"
-	let index = asks_book.peek_id_pos(format!("jason"));
+	let index = asks_book.peek_id_pos(String::from("jason"));
 
 	// Unwrap the index and check order has been updating
 	if let Some(i) = index {
 		let order = &asks_book.orders.lock().unwrap()[i];
-		assert_eq!(order.trader_id, format!("jason"));
+		assert_eq!(order.trader_id, String::from("jason"));
 		assert_eq!(order.price, 99.9);
 		assert_eq!(order.quantity, 555.5);
 		assert_eq!(order.order_type, OrderType::Update);
@@ -348,8 +349,8 @@ pub fn test_cancel_bid() {
 	let asks_book = Arc::new(common::setup_asks_book());
 	
 	// Setup bids and asks
-	let (mut bids, asks) = common::setup_orders();
-	bids[0].trader_id = format!("jason");
+	let (mut bids, _asks) = common::setup_orders();
+	bids[0].trader_id = String::from("jason");
 	bids[0].price = 99999.9;
 	bids[0].quantity = 1.0;
 	let mut handles = Vec::new();
@@ -380,7 +381,7 @@ pub fn test_cancel_bid() {
 
 	// Create a new order to update book 
 	let mut update_order = common::setup_bid_order();
-	update_order.trader_id = format!("jason");
+	update_order.trader_id = String::from("jason");
 	update_order.price = 999.9;
 	update_order.order_type = OrderType::Cancel;
 	update_order.quantity = -1.0; 
@@ -400,10 +401,10 @@ pub fn test_cancel_bid() {
 	assert_eq!(bids_book.len(), 99);
 
 	// Find the order with id "jason"
-	let index = bids_book.peek_id_pos(format!("jason"));
+	let index = bids_book.peek_id_pos(String::from("jason"));
 
 	// Unwrap the index and check order has been updating
-	if let Some(_) = index {
+	if index.is_some() {
 		panic!("Cancel Order should not exist anymore");
 	} 
 
@@ -420,8 +421,8 @@ pub fn test_cancel_ask() {
 	let asks_book = Arc::new(common::setup_asks_book());
 	
 	// Setup bids and asks
-	let (bids, mut asks) = common::setup_orders();
-	asks[0].trader_id = format!("jason");
+	let (_bids, mut asks) = common::setup_orders();
+	asks[0].trader_id = String::from("jason");
 	asks[0].price = 0.1;		// Set the best ask price
 	asks[0].quantity = 10.0;
 	let mut handles = Vec::new();
@@ -452,7 +453,7 @@ pub fn test_cancel_ask() {
 
 	// Create a new order to update book 
 	let mut update_order = common::setup_ask_order();
-	update_order.trader_id = format!("jason");
+	update_order.trader_id = String::from("jason");
 	update_order.price = 99999.9;
 	update_order.order_type = OrderType::Cancel;
 	update_order.quantity = 1.0; 
@@ -472,10 +473,10 @@ pub fn test_cancel_ask() {
 	assert_eq!(asks_book.len(), 99);
 
 	// Find the order with id "jason"
-	let index = asks_book.peek_id_pos(format!("jason"));
+	let index = asks_book.peek_id_pos(String::from("jason"));
 
 	// Unwrap the index and check order has been updating
-	if let Some(_) = index {
+	if index.is_some() {
 		panic!("Cancel Order should not exist anymore");
 	} 
 
@@ -509,7 +510,7 @@ pub fn test_update_ask_to_cross() {
 	}
 
 	// Process all of the bid orders in the queue
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 
@@ -524,7 +525,7 @@ pub fn test_update_ask_to_cross() {
 
 
 	// Setup ask to be updated: 
-	asks[0].trader_id = format!("jason");
+	asks[0].trader_id = String::from("jason");
 	asks[0].price = 99999.0;		// Modify from 0.0 -> 99999.0 so won't cross
 	asks[0].quantity = 50.0;
 
@@ -539,7 +540,7 @@ pub fn test_update_ask_to_cross() {
 	}
 
 	// Process the new ask orders
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 	
@@ -552,7 +553,7 @@ pub fn test_update_ask_to_cross() {
 
 	// Update the order:
 	let mut update_order = common::setup_ask_order();
-	update_order.trader_id = format!("jason");
+	update_order.trader_id = String::from("jason");
 	update_order.price = 0.0;	// Will tx as market order
 	update_order.order_type = OrderType::Update;
 	update_order.quantity = 50.0;	// Should fill 10 bids
@@ -606,7 +607,7 @@ pub fn test_update_bid_to_cross() {
 	}
 
 	// Process all of the ask orders in the queue
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 
@@ -616,12 +617,12 @@ pub fn test_update_bid_to_cross() {
 
 	// There should be num_asks bids in the book, with max price num_asks and quantity 5.0
 	assert_eq!(asks_book.len(), num_asks);
-	let mut a_min_price = asks_book.get_min_price();
+	let a_min_price = asks_book.get_min_price();
 	assert_eq!(a_min_price, 51.0);
 
 
 	// Setup bid to be updated: 
-	bids[0].trader_id = format!("jason");
+	bids[0].trader_id = String::from("jason");
 	bids[0].price = 0.0;		// Modify from 99999.0 -> 0.0 so won't cross
 	bids[0].quantity = 50.0;
 
@@ -636,7 +637,7 @@ pub fn test_update_bid_to_cross() {
 	}
 
 	// Process the new ask orders
-	let mut handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
+	let handles = QueueProcessor::conc_process_order_queue(Arc::clone(&queue), 
 							Arc::clone(&bids_book),
 							Arc::clone(&asks_book));
 	
@@ -649,7 +650,7 @@ pub fn test_update_bid_to_cross() {
 
 	// Update the order:
 	let mut update_order = common::setup_bid_order();
-	update_order.trader_id = format!("jason");
+	update_order.trader_id = String::from("jason");
 	update_order.price = 999999.0;	// Will tx as market order
 	update_order.order_type = OrderType::Update;
 	update_order.quantity = 50.0;	// Should fill 10 asks
@@ -689,3 +690,137 @@ pub fn test_update_bid_to_cross() {
 
 
 
+
+
+#[test]
+pub fn test_sell_stop_cascade() {
+	let queue = Arc::new(common::setup_queue());
+	let bids_book = Arc::new(common::setup_bids_book());
+	let asks_book = Arc::new(common::setup_asks_book());
+
+	// 100 bids priced 1 -> 100 with quantity 5.0
+	let (bids, _asks) = common::setup_orders();
+	for bid in bids {
+		queue.add(bid);
+	}
+
+	// Sell stop that will sweep 3 price levels once the price falls to 98
+	let mut first_stop = common::setup_ask_order();
+	first_stop.trader_id = String::from("first_stop");
+	first_stop.exec_type = ExecType::Stop(98.0);
+	first_stop.quantity = 15.0;
+	queue.add(first_stop);
+
+	// Sell stop limit that is only set off by the first stop's trades
+	let mut second_stop = common::setup_ask_order();
+	second_stop.trader_id = String::from("second_stop");
+	second_stop.exec_type = ExecType::StopLimit(95.0);
+	second_stop.price = 90.0;
+	queue.add(second_stop);
+
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert!(trades.is_empty());
+	assert_eq!(asks_book.stops.len(), 2);
+
+	// Trade at 98 triggers only the first stop
+	let mut ask = common::setup_ask_order();
+	ask.price = 98.0;
+	ask.quantity = 15.0;
+	queue.add(ask);
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert_eq!(trades.len(), 3);
+	assert_eq!(trades.last().unwrap().price, 98.0);
+	assert_eq!(asks_book.stops.len(), 1);
+
+	// The released stop sells down to 95 which releases the second stop
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	let prices: Vec<f64> = trades.iter().map(|t| t.price).collect();
+	assert_eq!(prices, vec![97.0, 96.0, 95.0]);
+	assert!(trades.iter().all(|t| t.ask_id == "first_stop"));
+	assert!(asks_book.stops.is_empty());
+
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert_eq!(trades.len(), 1);
+	assert_eq!(trades[0].ask_id, "second_stop");
+	assert_eq!(trades[0].price, 94.0);
+	assert_eq!(bids_book.get_max_price(), 93.0);
+	assert!(queue.pop().is_none());
+}
+
+#[test]
+pub fn test_stop_remainder_cancelled() {
+	let queue = Arc::new(common::setup_queue());
+	let bids_book = Arc::new(common::setup_bids_book());
+	let asks_book = Arc::new(common::setup_asks_book());
+
+	// A single bid of 5 @100 and a trade of 1 against it
+	queue.add(common::setup_bid_order());
+	let mut ask = common::setup_ask_order();
+	ask.quantity = 1.0;
+	queue.add(ask);
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert_eq!(trades.len(), 1);
+
+	// The sell stop triggers on entry and sells the 4 left, the other 6 don't rest at MIN_PRICE
+	let mut stop = common::setup_ask_order();
+	stop.trader_id = String::from("stop");
+	stop.exec_type = ExecType::Stop(100.0);
+	stop.quantity = 10.0;
+	queue.add(stop);
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert!(trades.is_empty());
+	assert!(asks_book.stops.is_empty());
+
+	let trades = QueueProcessor::seq_process_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert_eq!(trades.len(), 1);
+	assert_eq!((trades[0].ask_id.as_str(), trades[0].quantity), ("stop", 4.0));
+	assert!(bids_book.is_empty());
+	assert!(asks_book.is_empty());
+	assert!(queue.pop().is_none());
+}
+
+
+#[test]
+pub fn test_opening_auction() {
+	let queue = Arc::new(common::setup_queue());
+	let bids_book = Arc::new(common::setup_bids_book());
+	let asks_book = Arc::new(common::setup_asks_book());
+	let state = Mutex::new(State::PreAuction);
+	let trade_log = TradeLog::new();
+
+	// 100 bids priced 1 -> 100 with quantity 5.0 and 100 asks priced 1 -> 100 with quantity 50.0
+	let (bids, asks) = common::setup_orders();
+	for order in bids.into_iter().chain(asks) {
+		queue.add(order);
+	}
+
+	// During the pre-open orders rest in the Books without matching
+	QueueProcessor::accumulate_order_queue(Arc::clone(&queue), 
+							Arc::clone(&bids_book),
+							Arc::clone(&asks_book));
+	assert_eq!(bids_book.len(), 100);
+	assert_eq!(asks_book.len(), 100);
+	assert!(bids_book.get_max_price() > asks_book.get_min_price());
+
+	// Demand 5 * (101 - p) meets supply 50 * p closest at 10
+	let price = TradingSchedule::call_auction(&queue, Arc::clone(&bids_book), Arc::clone(&asks_book), 
+							&state, &trade_log, State::Process);
+	assert_eq!(price, Some(10.0));
+	assert!(trade_log.since(0).iter().all(|t| t.price == 10.0));
+	assert!(bids_book.get_max_price() < asks_book.get_min_price());
+	assert_eq!(*state.lock().unwrap(), State::Process);
+}